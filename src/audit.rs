@@ -0,0 +1,421 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An audit trail of the saves of a [`Database`], kept in a separate
+//! backend.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tokio::sync::Mutex;
+
+use crate::backend::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError, DeSerError};
+use crate::{Database, DeSerializer};
+
+/// A record of the audit trail, see [`Database::with_audit`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    /// When the save completed, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The actor given to [`Database::write_as`], `None` for other saves.
+    pub actor: Option<String>,
+    /// The changes since the previous record, as a JSON Patch (RFC 6902) of
+    /// `add`, `remove` and `replace` operations.
+    pub diff: Value,
+}
+
+impl AuditRecord {
+    /// Parse the records of an audit trail, as read from its backend.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`DeSerError::Json`] if a record isn't valid JSON, or with
+    /// [`DeSerError::Internal`] if it is missing a field.
+    pub fn parse_trail(data: &[u8]) -> error::DeSerResult<Vec<Self>> {
+        data.split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut record: Map<String, Value> = serde_json::from_slice(line)?;
+                let malformed = || DeSerError::Internal("malformed audit record".to_owned());
+                Ok(Self {
+                    timestamp: record
+                        .get("timestamp")
+                        .and_then(Value::as_u64)
+                        .ok_or_else(malformed)?,
+                    actor: match record.remove("actor") {
+                        Some(Value::String(actor)) => Some(actor),
+                        Some(Value::Null) => None,
+                        _ => return Err(malformed()),
+                    },
+                    diff: record.remove("diff").ok_or_else(malformed)?,
+                })
+            })
+            .collect()
+    }
+
+    fn to_line(&self) -> Vec<u8> {
+        let mut line = json!({
+            "timestamp": self.timestamp,
+            "actor": self.actor,
+            "diff": self.diff,
+        })
+        .to_string()
+        .into_bytes();
+        line.push(b'\n');
+        line
+    }
+}
+
+/// A [`Backend`] records are appended to, whatever its type.
+trait AuditSink: Send {
+    fn append<'a>(
+        &'a mut self,
+        record: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = error::BackendResult<()>> + Send + 'a>>;
+}
+
+impl<B: Backend + Send> AuditSink for B {
+    fn append<'a>(
+        &'a mut self,
+        record: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = error::BackendResult<()>> + Send + 'a>> {
+        Box::pin(async move {
+            if self.capabilities().contains(BackendCapabilities::APPEND) {
+                return self.append_data(record).await;
+            }
+            let mut trail = match self.get_data().await {
+                Ok(trail) => trail,
+                Err(BackendError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            };
+            trail.extend_from_slice(record);
+            self.put_data(&trail).await
+        })
+    }
+}
+
+struct Trail {
+    sink: Box<dyn AuditSink>,
+    /// The data as of the last record.
+    previous: Value,
+}
+
+/// The audit trail of a [`Database`], if enabled.
+#[derive(Default)]
+pub(crate) struct AuditTrail {
+    trail: Option<Mutex<Trail>>,
+}
+
+impl fmt::Debug for AuditTrail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditTrail")
+            .field("enabled", &self.trail.is_some())
+            .finish()
+    }
+}
+
+impl AuditTrail {
+    /// The data as it is being saved, if the trail is enabled.
+    pub(crate) fn snapshot<T: Serialize>(&self, data: &T) -> error::Result<Option<Value>> {
+        if self.trail.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::to_value(data).map_err(DeSerError::from)?))
+    }
+
+    /// Append the record of a save of `snapshot` by `actor`.
+    ///
+    /// If it can't be appended, the next record covers its changes.
+    pub(crate) async fn record(
+        &self,
+        actor: Option<String>,
+        snapshot: Option<Value>,
+    ) -> error::Result<()> {
+        let (Some(trail), Some(snapshot)) = (&self.trail, snapshot) else {
+            return Ok(());
+        };
+        let mut trail = trail.lock().await;
+        let mut diff = Vec::new();
+        diff_values(&trail.previous, &snapshot, &mut String::new(), &mut diff);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        let record = AuditRecord {
+            timestamp,
+            actor,
+            diff: Value::Array(diff),
+        };
+        trail.sink.append(&record.to_line()).await?;
+        trail.previous = snapshot;
+        Ok(())
+    }
+}
+
+/// Escape `key` as a segment of a JSON Pointer.
+fn pointer_segment(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Push the JSON Patch operations turning `old` into `new`, both at `path`,
+/// to `ops`.
+fn diff_values(old: &Value, new: &Value, path: &mut String, ops: &mut Vec<Value>) {
+    let len = path.len();
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                path.push('/');
+                path.push_str(&pointer_segment(key));
+                match new.get(key) {
+                    Some(new_value) => diff_values(old_value, new_value, path, ops),
+                    None => ops.push(json!({ "op": "remove", "path": path })),
+                }
+                path.truncate(len);
+            }
+            for (key, new_value) in new {
+                if !old.contains_key(key) {
+                    path.push('/');
+                    path.push_str(&pointer_segment(key));
+                    ops.push(json!({ "op": "add", "path": path, "value": new_value }));
+                    path.truncate(len);
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (i, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+                path.push('/');
+                path.push_str(&i.to_string());
+                diff_values(old_value, new_value, path, ops);
+                path.truncate(len);
+            }
+            for (i, new_value) in new.iter().enumerate().skip(old.len()) {
+                ops.push(json!({ "op": "add", "path": format!("{path}/{i}"), "value": new_value }));
+            }
+            // From the end, so that the indices stay valid.
+            for i in (new.len()..old.len()).rev() {
+                ops.push(json!({ "op": "remove", "path": format!("{path}/{i}") }));
+            }
+        }
+        (old, new) if old == new => {}
+        (_, new) => ops.push(json!({ "op": "replace", "path": path, "value": new })),
+    }
+}
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Keep an audit trail of the saves in `audit`, a backend separate from
+    /// the one holding the data.
+    ///
+    /// Every successful save appends an [`AuditRecord`] to it: a line of JSON
+    /// with the time, the actor given to [`Database::write_as`], and the
+    /// changes to the data since the previous record, as a JSON Patch. The
+    /// first record holds the changes since the trail was enabled, so the
+    /// trail together with the data as of now is the full history since
+    /// then. Records are appended with [`Backend::append_data`] if the
+    /// backend supports it, and by rewriting the whole trail otherwise.
+    ///
+    /// If a record can't be appended, the save returns the error although the
+    /// data was saved, and the next record covers its changes. Converting
+    /// the data to JSON on every save has a cost, like with a schema.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`DeSerError::Json`] if the data can't be converted to JSON.
+    ///
+    /// **Important**: This method is only available with the `json_enc`
+    /// feature
+    pub fn with_audit<A>(mut self, audit: A) -> error::Result<Self>
+    where
+        A: Backend + Send + 'static,
+    {
+        let previous = serde_json::to_value(&*self.data.get_mut()).map_err(DeSerError::from)?;
+        self.audit = AuditTrail {
+            trail: Some(Mutex::new(Trail {
+                sink: Box::new(audit),
+                previous,
+            })),
+        };
+        Ok(self)
+    }
+
+    /// Write lock the data, run `task` on it like [`Database::write`] and
+    /// save it, recording `actor` as the author of the changes in the audit
+    /// trail.
+    ///
+    /// Saving right away attributes the record to `actor` alone. Without a
+    /// trail set with [`Database::with_audit`], this is a write followed by
+    /// a save.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Database::save`], the changes stay in memory
+    /// if the data could not be saved.
+    pub async fn write_as<T, R>(&self, actor: impl Into<String>, task: T) -> error::Result<R>
+    where
+        T: FnOnce(&mut Data) -> R,
+    {
+        let mut lock = self.data.write().await;
+        let result = task(&mut lock);
+        self.oplog.record_write();
+        self.save_data_locked_as(lock, Some(actor.into())).await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_values, AuditRecord};
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::deser::Json;
+    use crate::Database;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// A backend shared with the test, without support for appending.
+    #[derive(Debug, Default, Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Backend for Shared {
+        async fn get_data(&mut self) -> crate::error::BackendResult<Vec<u8>> {
+            Ok(self.0.lock().expect("poisoned").clone())
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> crate::error::BackendResult<()> {
+            // Lets other tasks run in the middle of a save.
+            tokio::task::yield_now().await;
+            *self.0.lock().expect("poisoned") = data.to_vec();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_as_actors_are_recorded() {
+        let audit = Shared::default();
+        let db = Database::<BTreeMap<String, u32>, _, Json>::from_parts(
+            BTreeMap::new(),
+            MemoryBackend::new(),
+            Json::default(),
+        )
+        .with_audit(audit.clone())
+        .expect("could not enable audit");
+
+        db.write_as("alice", |data| data.insert("apples".to_owned(), 3))
+            .await
+            .expect("could not write");
+        db.write_as("bob", |data| {
+            data.insert("apples".to_owned(), 5);
+            data.insert("a/b".to_owned(), 1);
+        })
+        .await
+        .expect("could not write");
+
+        let trail = AuditRecord::parse_trail(&audit.0.lock().expect("poisoned"))
+            .expect("could not parse trail");
+        assert_eq!(2, trail.len());
+        assert_eq!(Some("alice"), trail[0].actor.as_deref());
+        assert_eq!(
+            json!([{ "op": "add", "path": "/apples", "value": 3 }]),
+            trail[0].diff
+        );
+        assert_eq!(Some("bob"), trail[1].actor.as_deref());
+        assert_eq!(
+            json!([
+                { "op": "replace", "path": "/apples", "value": 5 },
+                { "op": "add", "path": "/a~1b", "value": 1 },
+            ]),
+            trail[1].diff
+        );
+        assert!(trail[0].timestamp <= trail[1].timestamp);
+
+        db.save().await.expect("could not save");
+        let trail = AuditRecord::parse_trail(&audit.0.lock().expect("poisoned"))
+            .expect("could not parse trail");
+        assert_eq!(None, trail[2].actor);
+        assert_eq!(json!([]), trail[2].diff);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_saves_are_recorded_in_order() {
+        let data = Shared::default();
+        let audit = Shared::default();
+        let db = Arc::new(
+            Database::<BTreeMap<String, u32>, _, Json>::from_parts(
+                BTreeMap::new(),
+                data.clone(),
+                Json::default(),
+            )
+            .with_audit(audit.clone())
+            .expect("could not enable audit"),
+        );
+
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let db = Arc::clone(&db);
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        db.write_as(format!("actor {i}"), |data| {
+                            *data.entry("n".to_owned()).or_insert(0) += 1;
+                        })
+                        .await
+                        .expect("could not write");
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.expect("the task panicked");
+        }
+
+        let trail = AuditRecord::parse_trail(&audit.0.lock().expect("poisoned"))
+            .expect("could not parse trail");
+        let counts: Vec<_> = trail
+            .iter()
+            .map(|record| record.diff[0]["value"].clone())
+            .collect();
+        let expected: Vec<_> = (1..=200).map(|n| json!(n)).collect();
+        assert_eq!(expected, counts);
+        let saved: serde_json::Value =
+            serde_json::from_slice(&data.0.lock().expect("poisoned")).expect("invalid JSON");
+        assert_eq!(json!({ "n": 200 }), saved);
+    }
+
+    #[test]
+    fn diff_of_arrays_and_scalars() {
+        let mut ops = Vec::new();
+        diff_values(
+            &json!({ "list": [1, 2, 3], "gone": true, "n": 1 }),
+            &json!({ "list": [1, 4], "n": "one" }),
+            &mut String::new(),
+            &mut ops,
+        );
+        assert_eq!(
+            vec![
+                json!({ "op": "remove", "path": "/gone" }),
+                json!({ "op": "replace", "path": "/list/1", "value": 4 }),
+                json!({ "op": "remove", "path": "/list/2" }),
+                json!({ "op": "replace", "path": "/n", "value": "one" }),
+            ],
+            ops
+        );
+
+        let mut ops = Vec::new();
+        diff_values(&json!(1), &json!([1]), &mut String::new(), &mut ops);
+        assert_eq!(
+            vec![json!({ "op": "replace", "path": "", "value": [1] })],
+            ops
+        );
+    }
+}
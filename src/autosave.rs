@@ -0,0 +1,374 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Saving of a [`Database`] on background tasks, periodically or on demand.
+
+use std::future::Future;
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{watch, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+use crate::backend::Backend;
+use crate::error::{self, BackendError};
+use crate::{Database, DeSerializer};
+
+/// A handle to the background task started by [`Database::autosave`].
+///
+/// Dropping the handle does not stop the task. Use [`AutosaveHandle::shutdown`]
+/// or [`AutosaveHandle::with_shutdown_signal`] to stop it with a final save.
+/// [`AutosaveHandle::pause`] suspends the saves, during a bulk import for
+/// instance, until [`AutosaveHandle::resume`].
+#[derive(Debug)]
+pub struct AutosaveHandle {
+    shutdown: Arc<Notify>,
+    paused: watch::Sender<bool>,
+    task: JoinHandle<error::Result<()>>,
+}
+
+impl AutosaveHandle {
+    /// Stop the task once `signal` completes, saving one last time before it
+    /// exits.
+    ///
+    /// This is meant to be used with the likes of `tokio::signal::ctrl_c`, so
+    /// that the latest state is persisted when the application is asked to
+    /// terminate. Use [`AutosaveHandle::join`] to wait for the final save.
+    #[must_use]
+    pub fn with_shutdown_signal<F>(self, signal: F) -> Self
+    where
+        F: Future + Send + 'static,
+    {
+        let shutdown = Arc::clone(&self.shutdown);
+        tokio::spawn(async move {
+            signal.await;
+            shutdown.notify_one();
+        });
+        self
+    }
+
+    /// Stop the task after a final save and wait for it to finish.
+    pub async fn shutdown(self) -> error::Result<()> {
+        self.shutdown.notify_one();
+        self.join().await
+    }
+
+    /// Wait for the task to finish, without asking it to stop.
+    ///
+    /// Returns the error of the save that ended the task, if any.
+    pub async fn join(self) -> error::Result<()> {
+        self.task
+            .await
+            .map_err(|e| BackendError::Internal(format!("the autosave task failed: {e}")))?
+    }
+
+    /// Whether the task has stopped.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stop saving every period until [`AutosaveHandle::resume`].
+    ///
+    /// The data can still be changed meanwhile, the changes are saved when
+    /// the saves resume. Shutting down while paused still saves one last
+    /// time, but dropping the handle leaves the task paused for good.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Save again every period, after a save right away of what changed
+    /// while paused.
+    ///
+    /// The next periodic save happens one period after this save. Does
+    /// nothing if the saves are not paused.
+    pub fn resume(&self) {
+        self.paused
+            .send_if_modified(|paused| std::mem::replace(paused, false));
+    }
+
+    /// Whether the saves are paused.
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+}
+
+/// The outcome of a background save, shared with the calls coalesced into
+/// it. `None` until the save completed.
+type Outcome = Option<Result<(), String>>;
+
+/// The background saves started by [`Database::save_background`].
+#[derive(Debug, Default)]
+pub(crate) struct BackgroundSaves {
+    /// Held by the background save writing to the backend.
+    turn: Mutex<()>,
+    /// The outcome of the background save waiting for its turn, if any.
+    queued: std::sync::Mutex<Option<watch::Receiver<Outcome>>>,
+}
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    Back: Backend + Send + 'static,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone + 'static,
+{
+    /// Save the database every `period` on a background task.
+    ///
+    /// The first save happens one `period` after this call. If a save fails
+    /// the task stops, and the error is returned by [`AutosaveHandle::join`].
+    /// The saves can be paused with [`AutosaveHandle::pause`].
+    ///
+    /// This has to be called from within a tokio runtime.
+    pub fn autosave(self: &Arc<Self>, period: Duration) -> AutosaveHandle {
+        let shutdown = Arc::new(Notify::new());
+        let (paused, mut pause_state) = watch::channel(false);
+        let db = Arc::clone(self);
+        let stop = Arc::clone(&shutdown);
+        let task = tokio::spawn(async move {
+            let mut ticks = interval_at(Instant::now() + period, period);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {
+                        // This save also stands for a resume not seen yet.
+                        if !*pause_state.borrow_and_update() {
+                            db.save().await?;
+                        }
+                    }
+                    Ok(()) = pause_state.changed() => {
+                        if !*pause_state.borrow_and_update() {
+                            db.save().await?;
+                            ticks.reset();
+                        }
+                    }
+                    () = stop.notified() => return db.save().await,
+                }
+            }
+        });
+        AutosaveHandle {
+            shutdown,
+            paused,
+            task,
+        }
+    }
+
+    /// Save the database on a background task, returning right away.
+    ///
+    /// The save is like [`Database::save`], awaiting the returned handle
+    /// gives its result. Background saves run one after the other. If one is
+    /// already waiting for its turn, no other one is started: the waiting
+    /// save reads the data once it starts, so it also saves what changed
+    /// before this call, and the handle returns its result. When it failed,
+    /// the coalesced handles get the error as
+    /// [`BackendError::Internal`] with its message.
+    ///
+    /// **Durability**: the data is not saved yet when this returns, and is
+    /// lost if the program exits before the save completed. Await the
+    /// handle when it has to be persisted. Dropping the handle doesn't stop
+    /// the save.
+    ///
+    /// This has to be called from within a tokio runtime.
+    pub fn save_background(self: &Arc<Self>) -> JoinHandle<error::Result<()>> {
+        let mut queued = self
+            .background
+            .queued
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(outcome) = &*queued {
+            let mut outcome = outcome.clone();
+            return tokio::spawn(async move {
+                let outcome = outcome
+                    .wait_for(Option::is_some)
+                    .await
+                    .map_err(|_| BackendError::Internal("the background save was lost".into()))?;
+                match outcome.as_ref() {
+                    Some(Err(e)) => Err(BackendError::Internal(e.clone()).into()),
+                    _ => Ok(()),
+                }
+            });
+        }
+        let (done, outcome) = watch::channel(None);
+        *queued = Some(outcome);
+        drop(queued);
+
+        let db = Arc::clone(self);
+        tokio::spawn(async move {
+            let _turn = db.background.turn.lock().await;
+            // Calls from now on may have changed the data after this save
+            // read it, they need a save of their own.
+            *db.background
+                .queued
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = None;
+            let result = db.save().await;
+            let _ = done.send(Some(result.as_ref().map(drop).map_err(ToString::to_string)));
+            result
+        })
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::deser::Ron;
+    use crate::{error, Database, MemoryDatabase};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Counts the writes it receives.
+    #[derive(Debug, Default)]
+    struct CountingBackend {
+        inner: MemoryBackend,
+        puts: Arc<AtomicUsize>,
+    }
+
+    impl Backend for CountingBackend {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.inner.get_data().await
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.puts.fetch_add(1, Ordering::SeqCst);
+            self.inner.put_data(data).await
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_signal_saves_and_stops() {
+        let db =
+            Arc::new(MemoryDatabase::<Vec<u32>, Ron>::memory(vec![]).expect("could not create db"));
+        let (signal, fired) = tokio::sync::oneshot::channel::<()>();
+        let handle = db
+            .autosave(Duration::from_secs(1000))
+            .with_shutdown_signal(fired);
+
+        db.write(|d| d.push(42)).await.expect("could not write");
+        assert!(!handle.is_finished());
+        signal.send(()).expect("autosave is gone");
+        handle.join().await.expect("final save failed");
+
+        // Only the final save can have put the value into the backend.
+        db.write(Vec::clear).await.expect("could not write");
+        db.load().await.expect("could not load");
+        assert_eq!(
+            vec![42],
+            db.get_data(false).await.expect("could not get data")
+        );
+    }
+
+    /// Takes a second for every write.
+    #[derive(Debug, Default)]
+    struct SlowBackend {
+        inner: MemoryBackend,
+        started: Arc<AtomicUsize>,
+        done: Arc<AtomicUsize>,
+    }
+
+    impl Backend for SlowBackend {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.inner.get_data().await
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            self.inner.put_data(data).await?;
+            self.done.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn save_background_returns_early_and_coalesces() {
+        let backend = SlowBackend::default();
+        let (started, done) = (Arc::clone(&backend.started), Arc::clone(&backend.done));
+        let db = Arc::new(Database::<Vec<u32>, _, Ron>::from_parts(
+            vec![],
+            backend,
+            Ron,
+        ));
+
+        db.write(|d| d.push(1)).await.expect("could not write");
+        let first = db.save_background();
+        assert_eq!(0, done.load(Ordering::SeqCst));
+        while started.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // The first save is writing, these two share the next one.
+        db.write(|d| d.push(2)).await.expect("could not write");
+        let second = db.save_background();
+        db.write(|d| d.push(3)).await.expect("could not write");
+        let third = db.save_background();
+        assert!(!first.is_finished());
+
+        first.await.expect("task panicked").expect("save failed");
+        second.await.expect("task panicked").expect("save failed");
+        third.await.expect("task panicked").expect("save failed");
+        assert_eq!(2, done.load(Ordering::SeqCst));
+
+        db.write(Vec::clear).await.expect("could not write");
+        db.load().await.expect("could not load");
+        assert_eq!(
+            vec![1, 2, 3],
+            db.get_data(false).await.expect("could not get data")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn saves_every_period() {
+        let backend = CountingBackend::default();
+        let puts = Arc::clone(&backend.puts);
+        let db = Arc::new(Database::<u32, _, Ron>::from_parts(0, backend, Ron));
+        let handle = db.autosave(Duration::from_secs(10));
+
+        tokio::time::sleep(Duration::from_secs(35)).await;
+        assert_eq!(3, puts.load(Ordering::SeqCst));
+
+        handle.shutdown().await.expect("final save failed");
+        assert_eq!(4, puts.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paused_saves_wait_for_resume() {
+        let backend = CountingBackend::default();
+        let puts = Arc::clone(&backend.puts);
+        let db = Arc::new(Database::<Vec<u32>, _, Ron>::from_parts(
+            vec![],
+            backend,
+            Ron,
+        ));
+        let handle = db.autosave(Duration::from_secs(10));
+
+        handle.pause();
+        assert!(handle.is_paused());
+        for i in 0..5 {
+            db.write(|d| d.push(i)).await.expect("could not write");
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+        assert_eq!(0, puts.load(Ordering::SeqCst));
+
+        handle.resume();
+        assert!(!handle.is_paused());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(1, puts.load(Ordering::SeqCst));
+        handle.resume();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(1, puts.load(Ordering::SeqCst));
+
+        db.write(Vec::clear).await.expect("could not write");
+        db.load().await.expect("could not load");
+        assert_eq!(
+            vec![0, 1, 2, 3, 4],
+            db.get_data(false).await.expect("could not get data")
+        );
+        handle.shutdown().await.expect("final save failed");
+        assert_eq!(2, puts.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,373 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`BandwidthLimitedBackend`], limiting how
+//! many bytes per second go to and from another backend.
+
+use super::Backend;
+use crate::error;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+/// The most bytes passed on at once, 64 KiB.
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// A [`Backend`] limiting the throughput of the reads and writes of another
+/// backend to a number of bytes per second.
+///
+/// The limit is kept with a token bucket without burst: the bytes go to and
+/// from the inner backend in chunks of about a tenth of a second at the
+/// configured rate, and every chunk reserves the time it takes, right after
+/// the time reserved by the previous one, and is only passed on once that
+/// time is over. Reads and writes share the budget, so a big save also
+/// delays the loads after it.
+///
+/// The chunks go through [`Backend::put_data_from`] and
+/// [`Backend::get_data_into`] of the inner backend. An inner backend which
+/// streams its data sends it at the rate. One keeping the default
+/// implementations collects all the chunks before writing them at once,
+/// and produces all of its data before the chunks are paced out of it.
+#[derive(Debug)]
+pub struct BandwidthLimitedBackend<B> {
+    inner: B,
+    bucket: Bucket,
+}
+
+impl<B> BandwidthLimitedBackend<B> {
+    /// Limit the reads and writes of `inner` to `bytes_per_second`.
+    ///
+    /// This has to be called from within a tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// If `bytes_per_second` is zero.
+    pub fn new(inner: B, bytes_per_second: u64) -> Self {
+        assert!(bytes_per_second > 0, "the rate must not be zero");
+        Self {
+            inner,
+            bucket: Bucket {
+                bytes_per_second,
+                reserved_until: Instant::now(),
+            },
+        }
+    }
+
+    /// Return the inner backend.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+/// The token bucket shared by the reads and writes.
+#[derive(Debug)]
+struct Bucket {
+    bytes_per_second: u64,
+    /// When the time reserved by the previous chunk is over.
+    reserved_until: Instant,
+}
+
+impl Bucket {
+    /// The most bytes passed on at once, those of a tenth of a second.
+    fn chunk_size(&self) -> usize {
+        usize::try_from(self.bytes_per_second / 10)
+            .unwrap_or(MAX_CHUNK)
+            .clamp(1, MAX_CHUNK)
+    }
+
+    /// Reserve the time `len` bytes take, returning when it is over.
+    fn reserve(&mut self, len: usize) -> Instant {
+        let nanos = u128::try_from(len).unwrap_or(u128::MAX) * 1_000_000_000
+            / u128::from(self.bytes_per_second);
+        let cost = Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX));
+        self.reserved_until = self.reserved_until.max(Instant::now()) + cost;
+        self.reserved_until
+    }
+}
+
+/// Passes the bytes of a reader on chunk by chunk, each once its time is
+/// over.
+struct ThrottledReader<'a> {
+    inner: &'a mut (dyn AsyncRead + Unpin + Send),
+    bucket: &'a mut Bucket,
+    chunk: Vec<u8>,
+    /// The part of `chunk` not passed on yet.
+    start: usize,
+    end: usize,
+    /// The wait until `chunk` may be passed on.
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl<'a> ThrottledReader<'a> {
+    fn new(inner: &'a mut (dyn AsyncRead + Unpin + Send), bucket: &'a mut Bucket) -> Self {
+        Self {
+            inner,
+            chunk: vec![0; bucket.chunk_size()],
+            bucket,
+            start: 0,
+            end: 0,
+            wait: None,
+        }
+    }
+}
+
+impl AsyncRead for ThrottledReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.start < this.end {
+                if let Some(wait) = &mut this.wait {
+                    ready!(wait.as_mut().poll(cx));
+                    this.wait = None;
+                }
+                let len = buf.remaining().min(this.end - this.start);
+                buf.put_slice(&this.chunk[this.start..this.start + len]);
+                this.start += len;
+                return Poll::Ready(Ok(()));
+            }
+            let mut read = ReadBuf::new(&mut this.chunk);
+            ready!(Pin::new(&mut *this.inner).poll_read(cx, &mut read))?;
+            let len = read.filled().len();
+            if len == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            this.start = 0;
+            this.end = len;
+            this.wait = Some(Box::pin(tokio::time::sleep_until(this.bucket.reserve(len))));
+        }
+    }
+}
+
+/// Passes the bytes written to it on to a writer chunk by chunk, each once
+/// its time is over.
+struct ThrottledWriter<'a> {
+    inner: &'a mut (dyn AsyncWrite + Unpin + Send),
+    bucket: &'a mut Bucket,
+    /// The number of bytes whose time is over but which weren't passed on.
+    allowance: usize,
+    /// The wait until the given number of bytes may be passed on.
+    wait: Option<(Pin<Box<Sleep>>, usize)>,
+}
+
+impl<'a> ThrottledWriter<'a> {
+    fn new(inner: &'a mut (dyn AsyncWrite + Unpin + Send), bucket: &'a mut Bucket) -> Self {
+        Self {
+            inner,
+            bucket,
+            allowance: 0,
+            wait: None,
+        }
+    }
+}
+
+impl AsyncWrite for ThrottledWriter<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if this.allowance == 0 {
+            if this.wait.is_none() {
+                let len = buf.len().min(this.bucket.chunk_size());
+                let until = this.bucket.reserve(len);
+                this.wait = Some((Box::pin(tokio::time::sleep_until(until)), len));
+            }
+            if let Some((wait, len)) = &mut this.wait {
+                ready!(wait.as_mut().poll(cx));
+                this.allowance = *len;
+            }
+            this.wait = None;
+        }
+        let len = buf.len().min(this.allowance);
+        let written = ready!(Pin::new(&mut *this.inner).poll_write(cx, &buf[..len]))?;
+        this.allowance -= written;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+crate::delegate_backend! {
+    impl<B: Backend + Send> Backend for BandwidthLimitedBackend<B> => inner {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            let mut data = Vec::new();
+            self.get_data_into(&mut data).await?;
+            Ok(data)
+        }
+
+        async fn get_data_into(
+            &mut self,
+            writer: &mut (dyn AsyncWrite + Unpin + Send),
+        ) -> error::BackendResult<u64> {
+            let mut writer = ThrottledWriter::new(writer, &mut self.bucket);
+            self.inner.get_data_into(&mut writer).await
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.put_data_from(&mut &*data).await.map(drop)
+        }
+
+        async fn put_data_from(
+            &mut self,
+            reader: &mut (dyn AsyncRead + Unpin + Send),
+        ) -> error::BackendResult<u64> {
+            let mut reader = ThrottledReader::new(reader, &mut self.bucket);
+            self.inner.put_data_from(&mut reader).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BandwidthLimitedBackend;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::error;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+    use tokio::time::Instant;
+
+    /// Records when the bytes of the writes arrive, as a backend and as a
+    /// writer.
+    struct Arrivals {
+        start: Instant,
+        arrived: Vec<(Duration, usize)>,
+    }
+
+    impl Arrivals {
+        fn new() -> Self {
+            Self {
+                start: Instant::now(),
+                arrived: Vec::new(),
+            }
+        }
+
+        /// Whether the bytes came in 100 byte chunks, 100 ms apart.
+        fn paced(&self, chunks: u64) -> bool {
+            let expected: Vec<_> = (1..=chunks)
+                .map(|i| (Duration::from_millis(i * 100), 100))
+                .collect();
+            self.arrived == expected
+        }
+    }
+
+    impl Backend for Arrivals {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.arrived.push((self.start.elapsed(), data.len()));
+            Ok(())
+        }
+
+        async fn put_data_from(
+            &mut self,
+            reader: &mut (dyn AsyncRead + Unpin + Send),
+        ) -> error::BackendResult<u64> {
+            let mut chunk = [0; 1024];
+            let mut total = 0;
+            loop {
+                match reader.read(&mut chunk).await? {
+                    0 => return Ok(total),
+                    n => {
+                        self.arrived.push((self.start.elapsed(), n));
+                        total += n as u64;
+                    }
+                }
+            }
+        }
+    }
+
+    impl AsyncWrite for Arrivals {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.arrived.push((this.start.elapsed(), buf.len()));
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transfer_takes_rate_time() {
+        let mut backend = BandwidthLimitedBackend::new(MemoryBackend::new(), 1000);
+        let data = vec![7; 2500];
+
+        let start = Instant::now();
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(Duration::from_millis(2500), start.elapsed());
+
+        let start = Instant::now();
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(Duration::from_millis(2500), start.elapsed());
+
+        // Time spent idle isn't saved up for later.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let start = Instant::now();
+        backend
+            .put_data(&data[..500])
+            .await
+            .expect("could not put data");
+        assert_eq!(Duration::from_millis(500), start.elapsed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_streams_are_paced_chunk_by_chunk() {
+        let mut backend = BandwidthLimitedBackend::new(Arrivals::new(), 1000);
+        let data = vec![7; 2500];
+        assert_eq!(
+            2500,
+            backend
+                .put_data_from(&mut &data[..])
+                .await
+                .expect("could not put data")
+        );
+        assert!(backend.into_inner().paced(25));
+
+        let mut inner = MemoryBackend::new();
+        inner.put_data(&data).await.expect("could not put data");
+        let mut backend = BandwidthLimitedBackend::new(inner, 1000);
+        let mut writer = Arrivals::new();
+        assert_eq!(
+            2500,
+            backend
+                .get_data_into(&mut writer)
+                .await
+                .expect("could not get data")
+        );
+        assert!(writer.paced(25));
+    }
+}
@@ -0,0 +1,150 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`CacacheBackend`], storing data in a
+//! content-addressable `cacache` directory.
+
+use super::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A [`Backend`] storing the data under a key of a
+/// [`cacache`](https://docs.rs/cacache) cache directory.
+///
+/// The cache stores the data by its SHA-256 hash and verifies it on every
+/// read, so a corrupted cache fails with [`BackendError::ChecksumMismatch`]
+/// instead of returning wrong data. Identical data written under several
+/// keys is only stored once.
+///
+/// Reading a key that was never written fails with an I/O error of the kind
+/// [`NotFound`](io::ErrorKind::NotFound). The content of earlier writes is
+/// kept in the cache, nothing is removed from it.
+///
+/// **Important**: This is only available with the `cacache` feature
+#[derive(Debug, Clone)]
+pub struct CacacheBackend {
+    cache: PathBuf,
+    key: String,
+}
+
+impl CacacheBackend {
+    /// Store the data under `key` in the cache directory `cache`, which is
+    /// created on the first write.
+    pub fn new(cache: impl Into<PathBuf>, key: impl Into<String>) -> Self {
+        Self {
+            cache: cache.into(),
+            key: key.into(),
+        }
+    }
+
+    /// The cache directory.
+    #[must_use]
+    pub fn cache(&self) -> &Path {
+        &self.cache
+    }
+
+    /// The key the data is stored under.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Turn the errors of the cache into the errors of the other backends where
+/// there is one.
+fn backend_error(err: cacache::Error) -> BackendError {
+    match err {
+        cacache::Error::IntegrityError(_) | cacache::Error::SizeMismatch(..) => {
+            BackendError::ChecksumMismatch
+        }
+        cacache::Error::EntryNotFound(..) => {
+            BackendError::Io(io::Error::new(io::ErrorKind::NotFound, err))
+        }
+        cacache::Error::IoError(e, _) => BackendError::Io(e),
+        err @ cacache::Error::SerdeError(..) => BackendError::Cacache(err),
+    }
+}
+
+impl Backend for CacacheBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        cacache::read(&self.cache, &self.key)
+            .await
+            .map_err(backend_error)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        cacache::write(&self.cache, &self.key, data)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    /// The content is written before the index entry pointing to it, and
+    /// checked against its hash when read.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::ATOMIC | BackendCapabilities::CHECKSUM
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacacheBackend;
+    use crate::backend::Backend;
+    use crate::error::BackendError;
+    use std::convert::TryFrom;
+    use std::path::Path;
+
+    /// Overwrite every content file below `dir` with as many zeros.
+    fn corrupt(dir: &Path) {
+        for entry in std::fs::read_dir(dir).expect("could not read directory") {
+            let path = entry.expect("could not read entry").path();
+            if path.is_dir() {
+                corrupt(&path);
+            } else {
+                let len = std::fs::metadata(&path).expect("no metadata").len();
+                let zeros = vec![0; usize::try_from(len).expect("file too large")];
+                std::fs::write(&path, zeros).expect("could not corrupt file");
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_cacache_backend_roundtrip() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut backend = CacacheBackend::new(dir.path(), "db");
+        match backend.get_data().await {
+            Err(BackendError::Io(e)) => assert_eq!(std::io::ErrorKind::NotFound, e.kind()),
+            res => panic!("expected NotFound, got {:?}", res),
+        }
+
+        let data = [4, 5, 1, 6, 8, 1];
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        let data2 = [3, 99, 127, 6];
+        backend.put_data(&data2).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data2);
+
+        let mut other = CacacheBackend::new(dir.path(), "other");
+        assert!(other.get_data().await.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_cacache_backend_detects_corruption() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut backend = CacacheBackend::new(dir.path(), "db");
+        backend
+            .put_data(b"some important data")
+            .await
+            .expect("could not put data");
+
+        corrupt(&dir.path().join("content-v2"));
+        assert!(matches!(
+            backend.get_data().await,
+            Err(BackendError::ChecksumMismatch)
+        ));
+    }
+}
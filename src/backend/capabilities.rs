@@ -0,0 +1,153 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements [`BackendCapabilities`], what a backend can do
+//! besides reading and replacing its data.
+
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+
+/// The set of optional features a [`Backend`](super::Backend) supports, as
+/// returned by [`Backend::capabilities`](super::Backend::capabilities).
+///
+/// Sets are combined with `|` and checked with
+/// [`contains`](BackendCapabilities::contains):
+///
+/// ```rust
+/// use dropbreak::backend::BackendCapabilities;
+///
+/// let caps = BackendCapabilities::ATOMIC | BackendCapabilities::APPEND;
+/// assert!(caps.contains(BackendCapabilities::ATOMIC));
+/// assert!(!caps.contains(BackendCapabilities::ATOMIC | BackendCapabilities::LOCKING));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BackendCapabilities(u8);
+
+impl BackendCapabilities {
+    /// A write replaces the data all at once: a crash or error during it
+    /// leaves the previous data intact.
+    pub const ATOMIC: Self = Self(1);
+    /// Parts of the data can be read without reading all of it.
+    pub const RANGE_READ: Self = Self(1 << 1);
+    /// Data can be added to the end with
+    /// [`Backend::append_data`](super::Backend::append_data).
+    pub const APPEND: Self = Self(1 << 2);
+    /// The data can be made to expire after some time.
+    pub const EXPIRY: Self = Self(1 << 3);
+    /// The storage is locked against concurrent writers from other
+    /// processes.
+    pub const LOCKING: Self = Self(1 << 4);
+    /// Reads verify the data against a checksum stored with it.
+    pub const CHECKSUM: Self = Self(1 << 5);
+    /// Reads verify the data against a secret key, like a signature, so that
+    /// it can't be changed without the key.
+    pub const AUTHENTICATED: Self = Self(1 << 6);
+
+    /// The names of the capabilities, for `Debug`.
+    const NAMES: [(Self, &'static str); 7] = [
+        (Self::ATOMIC, "ATOMIC"),
+        (Self::RANGE_READ, "RANGE_READ"),
+        (Self::APPEND, "APPEND"),
+        (Self::EXPIRY, "EXPIRY"),
+        (Self::LOCKING, "LOCKING"),
+        (Self::CHECKSUM, "CHECKSUM"),
+        (Self::AUTHENTICATED, "AUTHENTICATED"),
+    ];
+
+    /// No capabilities at all.
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Whether there are no capabilities in the set.
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all capabilities of `other` are in the set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The capabilities in either set.
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// The capabilities in the set but not in `other`.
+    #[must_use]
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitOr for BackendCapabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+impl BitOrAssign for BackendCapabilities {
+    fn bitor_assign(&mut self, other: Self) {
+        *self = self.union(other);
+    }
+}
+
+impl BitAnd for BackendCapabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl fmt::Debug for BackendCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("(empty)");
+        }
+        let mut first = true;
+        for (flag, name) in &Self::NAMES {
+            if self.contains(*flag) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BackendCapabilities;
+
+    #[test]
+    fn test_capabilities_set_operations() {
+        let caps = BackendCapabilities::ATOMIC | BackendCapabilities::LOCKING;
+
+        assert!(caps.contains(BackendCapabilities::ATOMIC));
+        assert!(caps.contains(BackendCapabilities::empty()));
+        assert!(!caps.contains(BackendCapabilities::APPEND));
+        assert_eq!(
+            BackendCapabilities::LOCKING,
+            caps.difference(BackendCapabilities::ATOMIC)
+        );
+        assert_eq!(
+            BackendCapabilities::ATOMIC,
+            caps & BackendCapabilities::ATOMIC
+        );
+        assert!(BackendCapabilities::default().is_empty());
+        assert_eq!("ATOMIC | LOCKING", format!("{caps:?}"));
+        assert_eq!("(empty)", format!("{:?}", BackendCapabilities::empty()));
+    }
+}
@@ -0,0 +1,418 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`ObjectStoreChunkedBackend`], splitting the
+//! data across several objects of an object store.
+
+use super::{Backend, BackendCapabilities};
+use crate::deser::crc32;
+use crate::error::{self, BackendError};
+use crate::stats::to_u64;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: &str = "dropbreak-chunked 1";
+
+/// The size of the parts by default, 8 MiB.
+const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// What the manifest object records about the parts of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Manifest {
+    parts: usize,
+    size: u64,
+    /// The CRC-32 checksum of the data.
+    checksum: u32,
+}
+
+impl Manifest {
+    fn of(data: &[u8], chunk_size: usize) -> Self {
+        Self {
+            parts: data.len().div_ceil(chunk_size),
+            size: to_u64(data.len()),
+            checksum: crc32(data),
+        }
+    }
+
+    fn encode(self) -> String {
+        format!(
+            "{MAGIC}\nparts {}\nsize {}\nchecksum {}\n",
+            self.parts, self.size, self.checksum
+        )
+    }
+
+    fn decode(data: &[u8]) -> error::BackendResult<Self> {
+        let corrupted =
+            || BackendError::Internal("the manifest of the chunked backend is corrupted".into());
+        let text = std::str::from_utf8(data).map_err(|_| corrupted())?;
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(corrupted());
+        }
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+                .ok_or_else(corrupted)
+        };
+        Ok(Self {
+            parts: usize::try_from(field("parts")?).map_err(|_| corrupted())?,
+            size: field("size")?,
+            checksum: u32::try_from(field("checksum")?).map_err(|_| corrupted())?,
+        })
+    }
+}
+
+/// Turn the errors of the store into the errors of the other backends where
+/// there is one.
+fn backend_error(err: object_store::Error) -> BackendError {
+    match err {
+        object_store::Error::NotFound { .. } => {
+            BackendError::Io(io::Error::new(io::ErrorKind::NotFound, err))
+        }
+        err => BackendError::ObjectStore(err),
+    }
+}
+
+/// A [`Backend`] splitting the data across several objects of an
+/// [`ObjectStore`](https://docs.rs/object_store), for stores limiting the
+/// size of a single object.
+///
+/// The data is cut into parts of [`ObjectStoreChunkedBackend::chunk_size`]
+/// bytes, stored next to the object `name` as `name.part0000`,
+/// `name.part0001`, and so on. The object `name.manifest` records the number
+/// of parts, the total size and a CRC-32 checksum of the data.
+///
+/// Reads fetch the manifest, then its parts, and check that they add up to
+/// the size and checksum recorded. Writes upload all the parts, then replace
+/// the manifest, last. The parts are replaced in place, so a write that
+/// fails midway leaves the manifest of the earlier data with some of the new
+/// parts: reads then fail with [`BackendError::Internal`] instead of
+/// returning a mix of both, and the next successful write repairs the
+/// backend. Writes are not atomic for that reason. The parts past those the
+/// manifest lists are removed after every write, whether it succeeded or
+/// not, including those left behind by a writer that crashed. Removing them
+/// is best effort, parts that could not be removed are removed by the next
+/// write.
+///
+/// Reading data that was never written fails with an I/O error of the kind
+/// [`NotFound`](io::ErrorKind::NotFound). Two writers using the same name at
+/// once overwrite each other's parts, wrap the backend in a
+/// [`FencedBackend`](super::FencedBackend) if that can happen.
+///
+/// **Important**: This is only available with the `object_store` feature
+#[derive(Debug, Clone)]
+pub struct ObjectStoreChunkedBackend {
+    store: Arc<dyn ObjectStore>,
+    name: Path,
+    chunk_size: usize,
+}
+
+impl ObjectStoreChunkedBackend {
+    /// Store the data under `name` in `store`, in parts of 8 MiB.
+    pub fn new(store: Arc<dyn ObjectStore>, name: impl Into<Path>) -> Self {
+        Self {
+            store,
+            name: name.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Store the data in parts of `chunk_size` bytes, at least one.
+    #[must_use]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// The size of the parts, in bytes.
+    #[must_use]
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// The name the data is stored under.
+    #[must_use]
+    pub fn name(&self) -> &Path {
+        &self.name
+    }
+
+    /// Return the store.
+    #[must_use]
+    pub fn into_inner(self) -> Arc<dyn ObjectStore> {
+        self.store
+    }
+
+    fn manifest_path(&self) -> Path {
+        Path::from(format!("{}.manifest", self.name))
+    }
+
+    fn part_path(&self, index: usize) -> Path {
+        Path::from(format!("{}.part{index:04}", self.name))
+    }
+
+    /// The manifest, `None` if the data was never written.
+    async fn manifest(&self) -> error::BackendResult<Option<Manifest>> {
+        match self.store.get(&self.manifest_path()).await {
+            Ok(result) => Manifest::decode(&result.bytes().await.map_err(backend_error)?).map(Some),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(backend_error(e)),
+        }
+    }
+
+    /// Remove the parts past the first `keep` ones.
+    async fn remove_stale_parts(&self, keep: usize) -> error::BackendResult<()> {
+        let Some(file) = self.name.filename() else {
+            return Ok(());
+        };
+        let prefix = format!("{file}.part");
+        let directory: Path = self
+            .name
+            .parts()
+            .take(self.name.parts().count() - 1)
+            .collect();
+        let listing = self
+            .store
+            .list_with_delimiter(Some(&directory))
+            .await
+            .map_err(backend_error)?;
+        for object in listing.objects {
+            let stale = object
+                .location
+                .filename()
+                .and_then(|name| name.strip_prefix(&prefix)?.parse::<usize>().ok())
+                .is_some_and(|index| index >= keep);
+            if stale {
+                self.store
+                    .delete(&object.location)
+                    .await
+                    .map_err(backend_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Backend for ObjectStoreChunkedBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let Some(manifest) = self.manifest().await? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no manifest for {}", self.name),
+            )
+            .into());
+        };
+        let mut data = Vec::new();
+        for index in 0..manifest.parts {
+            let part = self
+                .store
+                .get(&self.part_path(index))
+                .await
+                .map_err(backend_error)?
+                .bytes()
+                .await
+                .map_err(backend_error)?;
+            data.extend_from_slice(&part);
+        }
+        if to_u64(data.len()) != manifest.size {
+            return Err(BackendError::Internal(format!(
+                "the parts of the chunked backend hold {} bytes, the manifest records {}",
+                data.len(),
+                manifest.size
+            )));
+        }
+        if crc32(&data) != manifest.checksum {
+            return Err(BackendError::Internal(
+                "the parts of the chunked backend don't match the checksum of the manifest".into(),
+            ));
+        }
+        Ok(data)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let earlier = self.manifest().await?.map_or(0, |manifest| manifest.parts);
+        let manifest = Manifest::of(data, self.chunk_size);
+
+        let mut result = Ok(());
+        for (index, chunk) in data.chunks(self.chunk_size).enumerate() {
+            result = self
+                .store
+                .put(&self.part_path(index), chunk.to_vec().into())
+                .await
+                .map(drop);
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = self
+                .store
+                .put(&self.manifest_path(), manifest.encode().into_bytes().into())
+                .await
+                .map(drop);
+        }
+        if let Err(e) = result {
+            // The parts uploaded past those of the earlier manifest are
+            // listed nowhere.
+            let _ = self.remove_stale_parts(earlier).await;
+            return Err(backend_error(e));
+        }
+
+        // The data is written, the next write retries the cleanup.
+        let _ = self.remove_stale_parts(manifest.parts).await;
+        Ok(())
+    }
+
+    /// The parts are replaced in place, before the manifest.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Manifest, ObjectStoreChunkedBackend};
+    use crate::backend::Backend;
+    use crate::error::BackendError;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use object_store::ObjectStore;
+    use std::sync::Arc;
+
+    async fn names(store: &InMemory) -> Vec<String> {
+        let listing = store
+            .list_with_delimiter(Some(&Path::from("dbs")))
+            .await
+            .expect("could not list");
+        let mut names: Vec<_> = listing
+            .objects
+            .iter()
+            .map(|o| o.location.to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_chunked_roundtrip_and_reopen() {
+        let store = Arc::new(InMemory::new());
+        let data: Vec<u8> = (0..10).collect();
+        let mut backend =
+            ObjectStoreChunkedBackend::new(store.clone(), "dbs/db").with_chunk_size(4);
+        assert!(matches!(
+            backend.get_data().await,
+            Err(BackendError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(
+            vec![
+                "dbs/db.manifest",
+                "dbs/db.part0000",
+                "dbs/db.part0001",
+                "dbs/db.part0002"
+            ],
+            names(&store).await
+        );
+
+        let mut reopened = ObjectStoreChunkedBackend::new(store.clone(), "dbs/db");
+        assert_eq!(data, reopened.get_data().await.expect("could not get data"));
+        reopened
+            .put_data(b"small")
+            .await
+            .expect("could not put data");
+        assert_eq!(
+            b"small".to_vec(),
+            backend.get_data().await.expect("could not get data")
+        );
+        assert_eq!(
+            vec!["dbs/db.manifest", "dbs/db.part0000"],
+            names(&store).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked_removes_leftover_parts() {
+        let store = Arc::new(InMemory::new());
+        let mut backend =
+            ObjectStoreChunkedBackend::new(store.clone(), "dbs/db").with_chunk_size(2);
+        backend.put_data(b"abc").await.expect("could not put data");
+
+        // A writer that crashed before replacing the manifest.
+        for stray in ["dbs/db.part0002", "dbs/db.part0007"] {
+            store
+                .put(&Path::from(stray), b"xx".to_vec().into())
+                .await
+                .expect("could not put part");
+        }
+        store
+            .put(&Path::from("dbs/other"), b"keep".to_vec().into())
+            .await
+            .expect("could not put object");
+        assert_eq!(
+            b"abc".to_vec(),
+            backend.get_data().await.expect("could not get data")
+        );
+
+        backend.put_data(b"defg").await.expect("could not put data");
+        assert_eq!(
+            b"defg".to_vec(),
+            backend.get_data().await.expect("could not get data")
+        );
+        assert_eq!(
+            vec![
+                "dbs/db.manifest",
+                "dbs/db.part0000",
+                "dbs/db.part0001",
+                "dbs/other"
+            ],
+            names(&store).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked_detects_missing_parts() {
+        let store = Arc::new(InMemory::new());
+        let mut backend =
+            ObjectStoreChunkedBackend::new(store.clone(), "dbs/db").with_chunk_size(2);
+        backend.put_data(b"abcd").await.expect("could not put data");
+        store
+            .put(&Path::from("dbs/db.part0001"), b"c".to_vec().into())
+            .await
+            .expect("could not put part");
+        assert!(matches!(
+            backend.get_data().await,
+            Err(BackendError::Internal(_))
+        ));
+
+        // A write cut short after its first part, of the same size.
+        store
+            .put(&Path::from("dbs/db.part0001"), b"cd".to_vec().into())
+            .await
+            .expect("could not put part");
+        store
+            .put(&Path::from("dbs/db.part0000"), b"xy".to_vec().into())
+            .await
+            .expect("could not put part");
+        assert!(matches!(
+            backend.get_data().await,
+            Err(BackendError::Internal(_))
+        ));
+        backend.put_data(b"efgh").await.expect("could not put data");
+        assert_eq!(
+            b"efgh".to_vec(),
+            backend.get_data().await.expect("could not get data")
+        );
+
+        let manifest = Manifest::of(b"some data", 8);
+        assert_eq!(2, manifest.parts);
+        assert_eq!(
+            manifest,
+            Manifest::decode(manifest.encode().as_bytes()).expect("could not decode")
+        );
+        assert!(Manifest::decode(b"garbage").is_err());
+    }
+}
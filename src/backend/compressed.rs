@@ -0,0 +1,178 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`CompressedBackend`], compressing the data
+//! of another backend once it is big enough to be worth it.
+
+use super::Backend;
+use crate::error::{self, BackendError};
+use flate2::write::{DeflateDecoder, DeflateEncoder};
+use flate2::Compression;
+use std::io::Write;
+
+/// The flag of data stored as it is.
+const STORED: u8 = 0;
+/// The flag of data compressed with DEFLATE.
+const DEFLATE: u8 = 1;
+
+/// The payload size from which data is compressed by default, 1 KiB.
+const DEFAULT_THRESHOLD: usize = 1024;
+
+/// A [`Backend`] compressing the data of another backend with DEFLATE, when
+/// it is bigger than a threshold.
+///
+/// Small payloads hardly shrink and would pay the CPU time of compressing
+/// them for nothing, so data up to [`CompressedBackend::threshold`] bytes is
+/// stored as it is. The stored bytes start with a flag telling whether they
+/// are compressed: `0` for data stored as it is, `1` for DEFLATE. Data that
+/// would not get smaller is stored as it is too. Reads look at the flag, so
+/// changing the threshold or the level never makes earlier data unreadable.
+///
+/// An inner backend holding no data at all reads as empty data.
+///
+/// **Important**: This is only available with the `compression` feature
+#[derive(Debug)]
+pub struct CompressedBackend<B> {
+    inner: B,
+    threshold: usize,
+    level: Compression,
+}
+
+impl<B> CompressedBackend<B> {
+    /// Compress the data of `inner` from 1 KiB on, at the default level.
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            threshold: DEFAULT_THRESHOLD,
+            level: Compression::default(),
+        }
+    }
+
+    /// Compress the data only once it is bigger than `threshold` bytes.
+    #[must_use]
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Compress at `level`, from 0 (fastest) to 9 (smallest).
+    #[must_use]
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = Compression::new(level.min(9));
+        self
+    }
+
+    /// The size up to which the data is stored uncompressed.
+    #[must_use]
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Return the inner backend.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// The bytes to store for `data`, starting with their flag.
+    fn encode(&self, data: &[u8]) -> error::BackendResult<Vec<u8>> {
+        if data.len() > self.threshold {
+            let mut encoder = DeflateEncoder::new(vec![DEFLATE], self.level);
+            encoder.write_all(data)?;
+            let compressed = encoder.finish()?;
+            if compressed.len() <= data.len() {
+                return Ok(compressed);
+            }
+        }
+        let mut stored = Vec::with_capacity(data.len() + 1);
+        stored.push(STORED);
+        stored.extend_from_slice(data);
+        Ok(stored)
+    }
+}
+
+/// The data held by the stored bytes `stored`.
+fn decode(stored: &[u8]) -> error::BackendResult<Vec<u8>> {
+    match stored.split_first() {
+        None => Ok(Vec::new()),
+        Some((&STORED, data)) => Ok(data.to_vec()),
+        Some((&DEFLATE, compressed)) => {
+            let mut decoder = DeflateDecoder::new(Vec::new());
+            decoder.write_all(compressed)?;
+            Ok(decoder.finish()?)
+        }
+        Some((flag, _)) => Err(BackendError::Internal(format!(
+            "unknown compression flag {flag}"
+        ))),
+    }
+}
+
+crate::delegate_backend! {
+    impl<B: Backend + Send> Backend for CompressedBackend<B> => inner {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            decode(&self.inner.get_data().await?)
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.put_data_counted(data).await.map(drop)
+        }
+
+        /// Returns the number of bytes stored, after compression.
+        async fn put_data_counted(&mut self, data: &[u8]) -> error::BackendResult<usize> {
+            let stored = self.encode(data)?;
+            self.inner.put_data_counted(&stored).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompressedBackend;
+    use crate::backend::{Backend, MemoryBackend};
+
+    #[tokio::test]
+    async fn test_compress_above_threshold() {
+        let mut backend = CompressedBackend::new(MemoryBackend::new()).with_threshold(64);
+        assert_eq!(64, backend.threshold());
+
+        let small = b"short and sweet".to_vec();
+        backend.put_data(&small).await.expect("could not put data");
+        assert_eq!(small, backend.get_data().await.expect("could not get data"));
+        let stored = backend.inner.get_data().await.expect("could not get data");
+        assert_eq!(0, stored[0]);
+        assert_eq!(small, stored[1..]);
+
+        let large = b"la".repeat(1000);
+        backend.put_data(&large).await.expect("could not put data");
+        assert_eq!(large, backend.get_data().await.expect("could not get data"));
+        let stored = backend.inner.get_data().await.expect("could not get data");
+        assert_eq!(1, stored[0]);
+        assert!(stored.len() < large.len() / 10);
+    }
+
+    #[tokio::test]
+    async fn test_incompressible_data_is_stored() {
+        let mut backend = CompressedBackend::new(MemoryBackend::new()).with_threshold(0);
+        assert!(backend
+            .get_data()
+            .await
+            .expect("could not get data")
+            .is_empty());
+
+        // Every byte value once, DEFLATE can't make it smaller.
+        let noise: Vec<u8> = (0..=255).collect();
+        backend.put_data(&noise).await.expect("could not put data");
+        assert_eq!(noise, backend.get_data().await.expect("could not get data"));
+        assert_eq!(
+            0,
+            backend.inner.get_data().await.expect("could not get data")[0]
+        );
+
+        backend
+            .inner
+            .put_data(&[7, 1, 2])
+            .await
+            .expect("could not put data");
+        assert!(backend.get_data().await.is_err());
+    }
+}
@@ -0,0 +1,188 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`DebugTeeBackend`], keeping a copy of every
+//! write in a directory for debugging.
+
+use super::Backend;
+use crate::error;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The extension of the snapshot files.
+const EXTENSION: &str = "snapshot";
+
+/// A [`Backend`] writing a snapshot of every save to a debug directory, on
+/// top of writing it to another backend.
+///
+/// Every write to the backend also creates a file named after the time of
+/// the write in the directory, so that every state the program persisted can
+/// be looked at later. Data that is valid UTF-8, like that of the text
+/// formats, is written as is, anything else as a hex dump. Only the latest
+/// snapshots are kept, 100 unless changed with [`DebugTeeBackend::keep`].
+///
+/// The snapshot is written after the data was written to the inner backend.
+/// If writing the snapshot fails its error is returned, even though the data
+/// was saved. Reads go to the inner backend only.
+///
+/// This is meant for development, it writes a file per save.
+///
+/// **Important**: This is only available with the `debug-tee` feature
+#[derive(Debug)]
+pub struct DebugTeeBackend<B> {
+    inner: B,
+    dir: PathBuf,
+    keep: usize,
+    /// Orders the snapshots taken in the same millisecond.
+    sequence: u64,
+}
+
+impl<B> DebugTeeBackend<B> {
+    /// Write the data to `inner` and the snapshots to `dir`, which is
+    /// created if it doesn't exist.
+    pub fn new(inner: B, dir: impl Into<PathBuf>) -> error::BackendResult<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            inner,
+            dir,
+            keep: 100,
+            sequence: 0,
+        })
+    }
+
+    /// Keep only the latest `keep` snapshots, deleting older ones after every
+    /// write. At least one snapshot is always kept.
+    #[must_use]
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep.max(1);
+        self
+    }
+
+    /// The directory the snapshots are written to.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Return the inner backend.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// Write a snapshot of `data` and delete the snapshots beyond the limit.
+    fn snapshot(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or_default();
+        let name = format!("{millis:013}-{:06}.{EXTENSION}", self.sequence);
+        self.sequence += 1;
+        std::fs::write(self.dir.join(name), pretty(data))?;
+
+        let mut snapshots = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                snapshots.push(path);
+            }
+        }
+        if snapshots.len() > self.keep {
+            snapshots.sort();
+            for old in &snapshots[..snapshots.len() - self.keep] {
+                std::fs::remove_file(old)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The data as text if it is UTF-8, as a hex dump otherwise.
+fn pretty(data: &[u8]) -> Vec<u8> {
+    if std::str::from_utf8(data).is_ok() {
+        return data.to_vec();
+    }
+    let mut dump = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x}:", line * 16);
+        for byte in chunk {
+            let _ = write!(dump, " {byte:02x}");
+        }
+        dump.push('\n');
+    }
+    dump.into_bytes()
+}
+
+crate::delegate_backend! {
+    impl<B: Backend + Send> Backend for DebugTeeBackend<B> => inner {
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.inner.put_data(data).await?;
+            self.snapshot(data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pretty, DebugTeeBackend, EXTENSION};
+    use crate::backend::{Backend, MemoryBackend};
+    use std::path::{Path, PathBuf};
+
+    fn snapshots(dir: &Path) -> Vec<PathBuf> {
+        let mut snapshots: Vec<PathBuf> = std::fs::read_dir(dir)
+            .expect("could not read debug directory")
+            .map(|entry| entry.expect("could not read entry").path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+            .collect();
+        snapshots.sort();
+        snapshots
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_snapshot_per_save() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let debug_dir = dir.path().join("debug");
+        let mut backend = DebugTeeBackend::new(MemoryBackend::new(), &debug_dir)
+            .expect("could not create backend")
+            .keep(2);
+
+        backend
+            .put_data(b"first")
+            .await
+            .expect("could not put data");
+        assert_eq!(1, snapshots(&debug_dir).len());
+        backend
+            .put_data_counted(b"second")
+            .await
+            .expect("could not put data");
+        backend
+            .put_data(b"third")
+            .await
+            .expect("could not put data");
+
+        let snapshots = snapshots(&debug_dir);
+        assert_eq!(2, snapshots.len());
+        let contents: Vec<Vec<u8>> = snapshots
+            .iter()
+            .map(|path| std::fs::read(path).expect("could not read snapshot"))
+            .collect();
+        assert_eq!(vec![b"second".to_vec(), b"third".to_vec()], contents);
+        assert_eq!(
+            b"third",
+            &backend.into_inner().get_data().await.expect("no data")[..]
+        );
+    }
+
+    #[test]
+    fn test_pretty_binary() {
+        let data: Vec<u8> = (0xf0..=0xff).chain(0..2).collect();
+        assert_eq!(
+            "00000000: f0 f1 f2 f3 f4 f5 f6 f7 f8 f9 fa fb fc fd fe ff\n\
+             00000010: 00 01\n",
+            String::from_utf8(pretty(&data)).expect("hex dump is not UTF-8")
+        );
+    }
+}
@@ -0,0 +1,416 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`delegate_backend!`](crate::delegate_backend)
+//! macro.
+
+/// Implements [`Backend`](crate::backend::Backend) for a wrapper by
+/// delegating to one of its fields.
+///
+/// Only the methods that do something different from the inner backend have
+/// to be written out, every other method is forwarded to the field. The
+/// methods derived from others follow what they are derived from:
+///
+/// - if `get_data` is overridden, `get_data_cow` and `get_data_into` keep
+///   the trait default (which calls the overridden `get_data`) unless they
+///   are overridden as well,
+/// - if `put_data` is overridden, so are `put_data_counted`,
+///   `put_data_with_progress` and `put_data_from`,
+/// - if `get_data` or `put_data` is overridden, `append_data` keeps the trait
+///   default, failing as unsupported, unless it is overridden as well,
+/// - `capabilities` reports those of the field, without
+///   [`APPEND`](crate::backend::BackendCapabilities::APPEND) if `append_data`
+///   isn't forwarded.
+///
+/// This way a wrapper transforming the data can't be bypassed through one of
+/// the variants. Overrides of the `async` methods have to be written as
+/// `async fn`.
+///
+/// The field is given by name, or by index for tuple structs. Generic
+/// parameters may have bounds made of plain trait names.
+///
+/// # Examples
+///
+/// ```rust
+/// use dropbreak::backend::{Backend, MemoryBackend};
+/// use dropbreak::error::BackendResult;
+///
+/// /// Counts the writes that reach the inner backend.
+/// struct Counting<B> {
+///     inner: B,
+///     writes: usize,
+/// }
+///
+/// dropbreak::delegate_backend! {
+///     impl<B: Backend + Send> Backend for Counting<B> => inner {
+///         async fn put_data(&mut self, data: &[u8]) -> BackendResult<()> {
+///             self.writes += 1;
+///             self.inner.put_data(data).await
+///         }
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut backend = Counting { inner: MemoryBackend::new(), writes: 0 };
+/// backend.put_data(b"hello").await?;
+/// backend.put_data_counted(b"world").await?;
+/// assert_eq!(2, backend.writes);
+/// assert_eq!(b"world", &backend.get_data().await?[..]);
+/// # Ok(())
+/// # }
+/// ```
+#[macro_export]
+macro_rules! delegate_backend {
+    (
+        impl $(<$($gen:ident $(: $bound:ident $(+ $bounds:ident)*)?),+ $(,)?>)? Backend for $ty:ty => $field:tt {
+            $($fns:tt)*
+        }
+    ) => {
+        impl $(<$($gen $(: $bound $(+ $bounds)*)?),+>)? $crate::backend::Backend for $ty {
+            $($fns)*
+
+            $crate::delegate_backend!(@delegate init $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate get_data $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate get_data_cow $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate get_data_into $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate put_data $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate put_data_counted $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate put_data_with_progress $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate put_data_from $field [$($fns)*]);
+            $crate::delegate_backend!(@delegate append_data $field [$($fns)*]);
+            $crate::delegate_backend!(@capabilities $field [$($fns)*] [$($fns)*]);
+        }
+    };
+
+    // `capabilities` is overridden.
+    (@capabilities $field:tt [$($all:tt)*] [$(#[$attr:meta])* fn capabilities $($rest:tt)*]) => {};
+    (
+        @capabilities $field:tt [$($all:tt)*] [
+            $(#[$attr:meta])* $(async)? fn $name:ident ($($args:tt)*) -> $ret:ty $body:block
+            $($more:tt)*
+        ]
+    ) => {
+        $crate::delegate_backend!(@capabilities $field [$($all)*] [$($more)*]);
+    };
+    (@capabilities $field:tt [$($all:tt)*] []) => {
+        fn capabilities(&self) -> $crate::backend::BackendCapabilities {
+            let capabilities = $crate::backend::Backend::capabilities(&self.$field);
+            if $crate::delegate_backend!(@supports_append [$($all)*]) {
+                capabilities
+            } else {
+                capabilities.difference($crate::backend::BackendCapabilities::APPEND)
+            }
+        }
+    };
+
+    // Whether `append_data` is overridden or forwarded.
+    (@supports_append [$(#[$attr:meta])* async fn append_data $($rest:tt)*]) => { true };
+    (
+        @supports_append [
+            $(#[$attr:meta])* async fn get_data ($($args:tt)*) -> $ret:ty $body:block
+            $($more:tt)*
+        ]
+    ) => {
+        $crate::delegate_backend!(@overrides_append [$($more)*])
+    };
+    (
+        @supports_append [
+            $(#[$attr:meta])* async fn put_data ($($args:tt)*) -> $ret:ty $body:block
+            $($more:tt)*
+        ]
+    ) => {
+        $crate::delegate_backend!(@overrides_append [$($more)*])
+    };
+    (
+        @supports_append [
+            $(#[$attr:meta])* $(async)? fn $name:ident ($($args:tt)*) -> $ret:ty $body:block
+            $($more:tt)*
+        ]
+    ) => {
+        $crate::delegate_backend!(@supports_append [$($more)*])
+    };
+    (@supports_append []) => { true };
+    (@overrides_append [$(#[$attr:meta])* async fn append_data $($rest:tt)*]) => { true };
+    (
+        @overrides_append [
+            $(#[$attr:meta])* $(async)? fn $name:ident ($($args:tt)*) -> $ret:ty $body:block
+            $($more:tt)*
+        ]
+    ) => {
+        $crate::delegate_backend!(@overrides_append [$($more)*])
+    };
+    (@overrides_append []) => { false };
+
+    // The method, or the one it is derived from, is overridden.
+    (@delegate init $field:tt [$(#[$attr:meta])* async fn init $($rest:tt)*]) => {};
+    (@delegate get_data $field:tt [$(#[$attr:meta])* async fn get_data $($rest:tt)*]) => {};
+    (@delegate get_data_cow $field:tt [$(#[$attr:meta])* async fn get_data_cow $($rest:tt)*]) => {};
+    (@delegate get_data_cow $field:tt [$(#[$attr:meta])* async fn get_data $($rest:tt)*]) => {};
+    (@delegate get_data_into $field:tt [$(#[$attr:meta])* async fn get_data_into $($rest:tt)*]) => {};
+    (@delegate get_data_into $field:tt [$(#[$attr:meta])* async fn get_data $($rest:tt)*]) => {};
+    (@delegate put_data $field:tt [$(#[$attr:meta])* async fn put_data $($rest:tt)*]) => {};
+    (@delegate put_data_counted $field:tt [$(#[$attr:meta])* async fn put_data_counted $($rest:tt)*]) => {};
+    (@delegate put_data_counted $field:tt [$(#[$attr:meta])* async fn put_data $($rest:tt)*]) => {};
+    (@delegate put_data_with_progress $field:tt [$(#[$attr:meta])* async fn put_data_with_progress $($rest:tt)*]) => {};
+    (@delegate put_data_with_progress $field:tt [$(#[$attr:meta])* async fn put_data $($rest:tt)*]) => {};
+    (@delegate put_data_from $field:tt [$(#[$attr:meta])* async fn put_data_from $($rest:tt)*]) => {};
+    (@delegate put_data_from $field:tt [$(#[$attr:meta])* async fn put_data $($rest:tt)*]) => {};
+    (@delegate append_data $field:tt [$(#[$attr:meta])* async fn append_data $($rest:tt)*]) => {};
+    (@delegate append_data $field:tt [$(#[$attr:meta])* async fn get_data $($rest:tt)*]) => {};
+    (@delegate append_data $field:tt [$(#[$attr:meta])* async fn put_data $($rest:tt)*]) => {};
+
+    // Look at the next override.
+    (
+        @delegate $method:ident $field:tt [
+            $(#[$attr:meta])* $(async)? fn $name:ident ($($args:tt)*) -> $ret:ty $body:block
+            $($more:tt)*
+        ]
+    ) => {
+        $crate::delegate_backend!(@delegate $method $field [$($more)*]);
+    };
+
+    // Not overridden, forward it to the field.
+    (@delegate init $field:tt []) => {
+        async fn init(&mut self) -> $crate::error::BackendResult<()> {
+            $crate::backend::Backend::init(&mut self.$field).await
+        }
+    };
+    (@delegate get_data $field:tt []) => {
+        async fn get_data(&mut self) -> $crate::error::BackendResult<::std::vec::Vec<u8>> {
+            $crate::backend::Backend::get_data(&mut self.$field).await
+        }
+    };
+    (@delegate get_data_cow $field:tt []) => {
+        async fn get_data_cow(
+            &mut self,
+        ) -> $crate::error::BackendResult<::std::borrow::Cow<'_, [u8]>> {
+            $crate::backend::Backend::get_data_cow(&mut self.$field).await
+        }
+    };
+    (@delegate get_data_into $field:tt []) => {
+        async fn get_data_into(
+            &mut self,
+            writer: &mut (dyn $crate::__tokio::io::AsyncWrite + ::std::marker::Unpin + ::std::marker::Send),
+        ) -> $crate::error::BackendResult<u64> {
+            $crate::backend::Backend::get_data_into(&mut self.$field, writer).await
+        }
+    };
+    (@delegate put_data $field:tt []) => {
+        async fn put_data(&mut self, data: &[u8]) -> $crate::error::BackendResult<()> {
+            $crate::backend::Backend::put_data(&mut self.$field, data).await
+        }
+    };
+    (@delegate put_data_counted $field:tt []) => {
+        async fn put_data_counted(&mut self, data: &[u8]) -> $crate::error::BackendResult<usize> {
+            $crate::backend::Backend::put_data_counted(&mut self.$field, data).await
+        }
+    };
+    (@delegate put_data_with_progress $field:tt []) => {
+        async fn put_data_with_progress(
+            &mut self,
+            data: &[u8],
+            progress: &mut (dyn FnMut(u64, u64) + ::std::marker::Send),
+        ) -> $crate::error::BackendResult<()> {
+            $crate::backend::Backend::put_data_with_progress(&mut self.$field, data, progress).await
+        }
+    };
+    (@delegate put_data_from $field:tt []) => {
+        async fn put_data_from(
+            &mut self,
+            reader: &mut (dyn $crate::__tokio::io::AsyncRead + ::std::marker::Unpin + ::std::marker::Send),
+        ) -> $crate::error::BackendResult<u64> {
+            $crate::backend::Backend::put_data_from(&mut self.$field, reader).await
+        }
+    };
+    (@delegate append_data $field:tt []) => {
+        async fn append_data(&mut self, data: &[u8]) -> $crate::error::BackendResult<()> {
+            $crate::backend::Backend::append_data(&mut self.$field, data).await
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{Backend, BackendCapabilities, MemoryBackend};
+    use crate::error::{self, BackendError};
+    use std::borrow::Cow;
+
+    /// Overrides nothing.
+    struct Plain(MemoryBackend);
+
+    crate::delegate_backend! {
+        impl Backend for Plain => 0 {}
+    }
+
+    /// Overrides the writes only.
+    struct Counting<B> {
+        inner: B,
+        writes: usize,
+    }
+
+    crate::delegate_backend! {
+        impl<B: Backend + Send> Backend for Counting<B> => inner {
+            async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+                self.writes += 1;
+                self.inner.put_data(data).await
+            }
+        }
+    }
+
+    /// Transforms the data both ways.
+    struct Flipped {
+        inner: MemoryBackend,
+    }
+
+    /// Appends through a different path than its writes.
+    struct Appending {
+        inner: MemoryBackend,
+    }
+
+    crate::delegate_backend! {
+        impl Backend for Appending => inner {
+            async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+                self.inner.put_data(data).await
+            }
+
+            fn capabilities(&self) -> BackendCapabilities {
+                BackendCapabilities::ATOMIC
+            }
+
+            async fn append_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+                self.inner.append_data(data).await
+            }
+        }
+    }
+
+    crate::delegate_backend! {
+        impl Backend for Flipped => inner {
+            /// Reads the data back unflipped.
+            async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+                let data = self.inner.get_data().await?;
+                Ok(data.into_iter().map(|b| !b).collect())
+            }
+
+            async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+                let flipped: Vec<u8> = data.iter().map(|b| !b).collect();
+                self.inner.put_data(&flipped).await
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delegate_all_methods() {
+        let mut backend = Plain(MemoryBackend::new());
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        let written = backend
+            .put_data_counted(&data[..3])
+            .await
+            .expect("could not put data");
+        assert_eq!(3, written);
+        // Borrowed data can only come from the inner backend, the trait
+        // default returns owned data.
+        match backend.get_data_cow().await.expect("could not get data") {
+            Cow::Borrowed(bytes) => assert_eq!(&data[..3], bytes),
+            Cow::Owned(_) => panic!("get_data_cow was not delegated"),
+        }
+        backend
+            .append_data(&data[3..])
+            .await
+            .expect("could not append data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(BackendCapabilities::APPEND, backend.capabilities());
+    }
+
+    #[tokio::test]
+    async fn test_delegate_put_data_override() {
+        let mut backend = Counting {
+            inner: MemoryBackend::new(),
+            writes: 0,
+        };
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        let written = backend
+            .put_data_counted(&data)
+            .await
+            .expect("could not put data");
+        assert_eq!(data.len(), written);
+        backend
+            .put_data_with_progress(&data, &mut |_, _| {})
+            .await
+            .expect("could not put data");
+        assert_eq!(3, backend.writes);
+        assert!(backend.capabilities().is_empty());
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert!(matches!(
+            backend.get_data_cow().await.expect("could not get data"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delegate_transforming_override() {
+        let mut backend = Flipped {
+            inner: MemoryBackend::new(),
+        };
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend
+            .put_data_counted(&data)
+            .await
+            .expect("could not put data");
+        assert_ne!(
+            backend.inner.get_data().await.expect("could not get data"),
+            data
+        );
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(
+            backend.get_data_cow().await.expect("could not get data"),
+            &data[..]
+        );
+        let mut streamed = Vec::new();
+        backend
+            .get_data_into(&mut streamed)
+            .await
+            .expect("could not get data");
+        assert_eq!(streamed, data);
+        backend
+            .put_data_from(&mut &data[..3])
+            .await
+            .expect("could not put data");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            data[..3]
+        );
+        // Appending to the inner backend would skip the transformation.
+        match backend.append_data(&data).await {
+            Err(BackendError::Unsupported { operation }) => assert_eq!("append_data", operation),
+            res => panic!("expected Unsupported, got {:?}", res),
+        }
+        assert!(!backend.capabilities().contains(BackendCapabilities::APPEND));
+    }
+
+    #[tokio::test]
+    async fn test_delegate_capabilities_override() {
+        let mut backend = Appending {
+            inner: MemoryBackend::new(),
+        };
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend
+            .put_data(&data[..3])
+            .await
+            .expect("could not put data");
+        backend
+            .append_data(&data[3..])
+            .await
+            .expect("could not append data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(BackendCapabilities::ATOMIC, backend.capabilities());
+    }
+}
@@ -0,0 +1,232 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`DirectIoBackend`], writing a file with
+//! `O_DIRECT` to bypass the page cache.
+
+use super::Backend;
+use crate::error;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// The smallest block size used, the logical block size of most devices.
+const MIN_BLOCK_SIZE: usize = 512;
+
+/// A [`Backend`] using a file opened with `O_DIRECT`, so that reads and
+/// writes go straight to the device instead of through the page cache.
+///
+/// This is meant for large data on dedicated storage, where caching what was
+/// just written only evicts more useful pages. `O_DIRECT` needs the memory
+/// buffers, the file offsets and the lengths of all transfers to be
+/// multiples of the block size of the device. The data is copied into an
+/// aligned buffer padded with zeros to a whole number of blocks, written at
+/// the start of the file, and the file is then truncated to the length of
+/// the data. Reads are rounded up the same way.
+///
+/// File systems which don't support `O_DIRECT`, like `tmpfs`, refuse to open
+/// the file with it. The backend then falls back to regular reads and
+/// writes, see [`DirectIoBackend::is_direct`].
+///
+/// The file is written in place and synced, so unlike with a
+/// [`PathBackend`](super::PathBackend) a crash during a write can leave it
+/// corrupted.
+///
+/// **Important**: This is only available on Linux
+#[derive(Debug)]
+pub struct DirectIoBackend {
+    path: PathBuf,
+    file: File,
+    direct: bool,
+    block_size: usize,
+}
+
+/// A buffer of `len` zeros starting at an address aligned to `align`.
+struct AlignedBuffer {
+    memory: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize, align: usize) -> Self {
+        let memory = vec![0; len + align];
+        let offset = memory.as_ptr().align_offset(align);
+        Self {
+            memory,
+            offset,
+            len,
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.memory[self.offset..self.offset + self.len]
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.memory[self.offset..self.offset + self.len]
+    }
+}
+
+/// Round `len` up to a multiple of `block_size`.
+fn round_up(len: usize, block_size: usize) -> usize {
+    len.div_ceil(block_size) * block_size
+}
+
+impl DirectIoBackend {
+    /// Opens the file at `path` with `O_DIRECT`, creating it if it doesn't
+    /// exist.
+    ///
+    /// The block size is the I/O block size the file system reports for the
+    /// file, at least 512 bytes.
+    pub fn open(path: impl AsRef<Path>) -> error::BackendResult<Self> {
+        let path = path.as_ref().to_owned();
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        let (file, direct) = match options.clone().custom_flags(libc::O_DIRECT).open(&path) {
+            Ok(file) => (file, true),
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => (options.open(&path)?, false),
+            Err(e) => return Err(e.into()),
+        };
+        let block_size = usize::try_from(file.metadata()?.blksize())
+            .unwrap_or(MIN_BLOCK_SIZE)
+            .max(MIN_BLOCK_SIZE)
+            .next_power_of_two();
+        Ok(Self {
+            path,
+            file,
+            direct,
+            block_size,
+        })
+    }
+
+    /// Use `block_size` for the alignment instead of the one reported by the
+    /// file system, for devices with a larger logical block size.
+    ///
+    /// # Panics
+    ///
+    /// If `block_size` is not a power of two.
+    #[must_use]
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        assert!(
+            block_size.is_power_of_two(),
+            "the block size must be a power of two"
+        );
+        self.block_size = block_size;
+        self
+    }
+
+    /// Whether the file could be opened with `O_DIRECT`, or regular reads and
+    /// writes are used instead.
+    #[must_use]
+    pub fn is_direct(&self) -> bool {
+        self.direct
+    }
+
+    /// The block size the transfers are aligned to.
+    #[must_use]
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The path of the file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Backend for DirectIoBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let len = usize::try_from(self.file.metadata()?.len())
+            .map_err(|e| error::BackendError::Internal(e.to_string()))?;
+        let mut buffer = AlignedBuffer::new(round_up(len, self.block_size), self.block_size);
+        let mut read = 0;
+        while read < len {
+            // The offsets stay aligned, the last read may stop short at the
+            // end of the file.
+            match self
+                .file
+                .read_at(&mut buffer.as_mut_slice()[read..], read as u64)
+            {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let mut data = buffer.as_slice().to_vec();
+        data.truncate(read.min(len));
+        Ok(data)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let mut buffer = AlignedBuffer::new(round_up(data.len(), self.block_size), self.block_size);
+        buffer.as_mut_slice()[..data.len()].copy_from_slice(data);
+        self.file.write_all_at(buffer.as_slice(), 0)?;
+        self.file.set_len(data.len() as u64)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{round_up, AlignedBuffer, DirectIoBackend};
+    use crate::backend::Backend;
+
+    #[test]
+    fn test_aligned_buffer() {
+        let buffer = AlignedBuffer::new(4096, 4096);
+        assert_eq!(0, buffer.as_slice().as_ptr() as usize % 4096);
+        assert_eq!(4096, buffer.as_slice().len());
+        assert_eq!(0, round_up(0, 512));
+        assert_eq!(512, round_up(1, 512));
+        assert_eq!(1024, round_up(1024, 512));
+    }
+
+    // Works with and without `O_DIRECT`, depending on the file system of the
+    // temporary directory.
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_direct_io_roundtrip() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("db");
+        let mut backend = DirectIoBackend::open(&path).expect("could not open backend");
+        assert!(backend
+            .get_data()
+            .await
+            .expect("could not get data")
+            .is_empty());
+
+        let block = backend.block_size();
+        let aligned: Vec<u8> = (0..=255).cycle().take(4 * block).collect();
+        backend
+            .put_data(&aligned)
+            .await
+            .expect("could not put data");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            aligned
+        );
+
+        let unaligned = &aligned[..block + 7];
+        backend
+            .put_data(unaligned)
+            .await
+            .expect("could not put data");
+        assert_eq!(
+            std::fs::metadata(&path).expect("no metadata").len(),
+            unaligned.len() as u64
+        );
+        drop(backend);
+        let mut backend = DirectIoBackend::open(&path).expect("could not open backend");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            unaligned
+        );
+    }
+}
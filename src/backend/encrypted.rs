@@ -0,0 +1,436 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`EncryptedBackend`], encrypting the data
+//! written to another backend.
+
+use super::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Written at the start of the stored data.
+const MAGIC: &[u8; 6] = b"DBENC1";
+/// The key was given to [`EncryptedBackend::new`].
+const KIND_KEY: u8 = 0;
+/// The key was derived from a passphrase, with the salt and parameters
+/// following.
+const KIND_PASSPHRASE: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+/// The cost parameters of Argon2id, used by
+/// [`EncryptedBackend::from_passphrase`] to derive the key.
+///
+/// Higher costs make guessing the passphrase slower for an attacker, and
+/// opening the backend slower for everyone. The default is the minimum
+/// recommended by OWASP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// The memory used, in KiB.
+    pub memory_kib: u32,
+    /// The number of passes over the memory.
+    pub iterations: u32,
+    /// The number of lanes.
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// The salt and parameters stored in the header.
+type StoredKdf<'a> = (&'a [u8], KdfParams);
+
+/// Where the key of an [`EncryptedBackend`] comes from.
+enum KeySource {
+    Key,
+    Passphrase {
+        passphrase: Vec<u8>,
+        salt: Vec<u8>,
+        params: KdfParams,
+    },
+}
+
+/// A [`Backend`] encrypting the data written to another backend, and
+/// decrypting it when it is read back.
+///
+/// The data is encrypted with ChaCha20-Poly1305 under a fresh random nonce
+/// on every write, which also authenticates it: a read fails with
+/// [`BackendError::Decryption`] if the key is wrong or the stored data was
+/// changed.
+///
+/// The key is either given as is, with [`EncryptedBackend::new`], or derived
+/// from a passphrase with Argon2id, with
+/// [`EncryptedBackend::from_passphrase`]. A derived key can only be
+/// reproduced with the same salt and cost parameters, so these are stored in
+/// a header in front of the encrypted data, which is authenticated along
+/// with it. Data written with other ones, like before the parameters were
+/// raised, is still read with the passphrase, and written with the new ones
+/// on the next write. Data whose parameters cost more than the configured
+/// ones in any way is refused with [`BackendError::Decryption`], so that
+/// changed data can't make a read spend any memory or time. Keys are derived
+/// on a blocking thread when reading.
+///
+/// **Important**: This is only available with the `encryption` feature
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use dropbreak::backend::{EncryptedBackend, KdfParams, PathBackend};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let (data, _) = PathBackend::from_path_or_create("db.ron.enc".into()).await?;
+/// let backend = EncryptedBackend::from_passphrase(
+///     data,
+///     "correct horse battery staple",
+///     b"a salt unique to this database",
+///     KdfParams::default(),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct EncryptedBackend<B> {
+    inner: B,
+    key: [u8; 32],
+    source: KeySource,
+}
+
+// Manual so that neither the key nor the passphrase is printed.
+impl<B: fmt::Debug> fmt::Debug for EncryptedBackend<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedBackend")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Derive a key from `passphrase`, `salt` and `params`, see
+/// [`EncryptedBackend::derive_key`].
+fn derive(passphrase: &[u8], salt: &[u8], params: KdfParams) -> error::BackendResult<[u8; 32]> {
+    let kdf_error = |e: argon2::Error| BackendError::Internal(e.to_string());
+    let params = Params::new(
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        Some(32),
+    )
+    .map_err(kdf_error)?;
+    let mut key = [0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut key)
+        .map_err(kdf_error)?;
+    Ok(key)
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let mut value = [0; 4];
+    value.copy_from_slice(bytes.get(at..at + 4)?);
+    Some(u32::from_le_bytes(value))
+}
+
+impl<B> EncryptedBackend<B> {
+    /// Encrypt the data stored in `inner` with `key`.
+    pub fn new(inner: B, key: [u8; 32]) -> Self {
+        Self {
+            inner,
+            key,
+            source: KeySource::Key,
+        }
+    }
+
+    /// Encrypt the data stored in `inner` with a key derived from
+    /// `passphrase` and `salt`.
+    ///
+    /// The salt should be unique to the database, so that the same
+    /// passphrase doesn't give the same key elsewhere, and at least 8 bytes
+    /// long. Fails with [`BackendError::Internal`] if the salt or the
+    /// parameters are refused by Argon2.
+    pub fn from_passphrase(
+        inner: B,
+        passphrase: impl Into<Vec<u8>>,
+        salt: impl Into<Vec<u8>>,
+        params: KdfParams,
+    ) -> error::BackendResult<Self> {
+        let passphrase = passphrase.into();
+        let salt = salt.into();
+        let key = Self::derive_key(&passphrase, &salt, params)?;
+        Ok(Self {
+            inner,
+            key,
+            source: KeySource::Passphrase {
+                passphrase,
+                salt,
+                params,
+            },
+        })
+    }
+
+    /// Derive the key [`EncryptedBackend::from_passphrase`] uses from
+    /// `passphrase`, `salt` and `params`.
+    pub fn derive_key(
+        passphrase: &[u8],
+        salt: &[u8],
+        params: KdfParams,
+    ) -> error::BackendResult<[u8; 32]> {
+        derive(passphrase, salt, params)
+    }
+
+    /// Return the inner backend.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// The header written in front of the encrypted data.
+    fn header(&self) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        match &self.source {
+            KeySource::Key => header.push(KIND_KEY),
+            KeySource::Passphrase { salt, params, .. } => {
+                header.push(KIND_PASSPHRASE);
+                // Salts longer than this are refused by Argon2.
+                header.push(u8::try_from(salt.len()).unwrap_or(u8::MAX));
+                header.extend_from_slice(salt);
+                for cost in [params.memory_kib, params.iterations, params.parallelism] {
+                    header.extend_from_slice(&cost.to_le_bytes());
+                }
+            }
+        }
+        header
+    }
+
+    /// The length of the header of the stored `data`, and the salt and
+    /// parameters to derive its key with if they aren't the configured ones.
+    fn parse_header<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> error::BackendResult<(usize, Option<StoredKdf<'a>>)> {
+        let rest = data
+            .strip_prefix(&MAGIC[..])
+            .ok_or(BackendError::Decryption)?;
+        match (rest.first(), &self.source) {
+            (Some(&KIND_KEY), _) => Ok((MAGIC.len() + 1, None)),
+            (Some(&KIND_PASSPHRASE), KeySource::Passphrase { salt, params, .. }) => {
+                let salt_len = usize::from(*rest.get(1).ok_or(BackendError::Decryption)?);
+                let stored_salt = rest.get(2..2 + salt_len).ok_or(BackendError::Decryption)?;
+                let at = 2 + salt_len;
+                let stored = match (
+                    read_u32(rest, at),
+                    read_u32(rest, at + 4),
+                    read_u32(rest, at + 8),
+                ) {
+                    (Some(memory_kib), Some(iterations), Some(parallelism)) => KdfParams {
+                        memory_kib,
+                        iterations,
+                        parallelism,
+                    },
+                    _ => return Err(BackendError::Decryption),
+                };
+                if stored.memory_kib > params.memory_kib
+                    || stored.iterations > params.iterations
+                    || stored.parallelism > params.parallelism
+                {
+                    return Err(BackendError::Decryption);
+                }
+                let derive = if stored_salt == salt.as_slice() && stored == *params {
+                    None
+                } else {
+                    Some((stored_salt, stored))
+                };
+                Ok((MAGIC.len() + at + 12, derive))
+            }
+            _ => Err(BackendError::Decryption),
+        }
+    }
+}
+
+impl<B> Backend for EncryptedBackend<B>
+where
+    B: Backend + Send,
+{
+    async fn init(&mut self) -> error::BackendResult<()> {
+        self.inner.init().await
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let data = self.inner.get_data().await?;
+        let (header_len, derive_with) = self.parse_header(&data)?;
+        let key = match (derive_with, &self.source) {
+            (Some((salt, params)), KeySource::Passphrase { passphrase, .. }) => {
+                // Argon2 takes as long as its parameters make it, keep it off
+                // the runtime's workers.
+                let passphrase = passphrase.clone();
+                let salt = salt.to_vec();
+                tokio::task::spawn_blocking(move || derive(&passphrase, &salt, params))
+                    .await
+                    .map_err(|e| BackendError::Internal(e.to_string()))??
+            }
+            _ => self.key,
+        };
+        let (header, encrypted) = data.split_at(header_len);
+        if encrypted.len() < NONCE_LEN {
+            return Err(BackendError::Decryption);
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: header,
+        };
+        ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| BackendError::Decryption)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut stored = self.header();
+        let payload = Payload {
+            msg: data,
+            aad: &stored,
+        };
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .encrypt(&nonce, payload)
+            .map_err(|_| BackendError::Internal("could not encrypt the data".into()))?;
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&ciphertext);
+        self.inner.put_data(&stored).await
+    }
+
+    /// The data is encrypted as a whole, it can't be appended to, and
+    /// authenticated by the cipher.
+    fn capabilities(&self) -> BackendCapabilities {
+        self.inner
+            .capabilities()
+            .difference(BackendCapabilities::APPEND)
+            | BackendCapabilities::AUTHENTICATED
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EncryptedBackend, KdfParams};
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::error::BackendError;
+
+    /// Cheap parameters, to keep the tests fast.
+    const PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+    const SALT: &[u8] = b"dropbreak test salt";
+
+    fn open(data: Vec<u8>, passphrase: &str) -> EncryptedBackend<MemoryBackend> {
+        EncryptedBackend::from_passphrase(MemoryBackend::from_vec(data), passphrase, SALT, PARAMS)
+            .expect("could not derive key")
+    }
+
+    #[tokio::test]
+    async fn test_passphrase_round_trip() {
+        let mut backend = open(Vec::new(), "hunter2");
+        backend
+            .put_data(&[4, 5, 1, 6, 8, 1])
+            .await
+            .expect("could not put data");
+        let stored = backend.into_inner().get_data().await.expect("no data");
+        assert!(!stored.windows(6).any(|w| w == [4, 5, 1, 6, 8, 1]));
+
+        let mut reopened = open(stored.clone(), "hunter2");
+        assert_eq!(
+            reopened.get_data().await.expect("could not get data"),
+            [4, 5, 1, 6, 8, 1]
+        );
+
+        // The salt and parameters are read from the header.
+        let mut other_salt = EncryptedBackend::from_passphrase(
+            MemoryBackend::from_vec(stored),
+            "hunter2",
+            "another salt",
+            KdfParams::default(),
+        )
+        .expect("could not derive key");
+        assert_eq!(
+            other_salt.get_data().await.expect("could not get data"),
+            [4, 5, 1, 6, 8, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wrong_passphrase_fails() {
+        let mut backend = open(Vec::new(), "hunter2");
+        backend
+            .put_data(&[4, 5, 1, 6, 8, 1])
+            .await
+            .expect("could not put data");
+        let stored = backend.into_inner().get_data().await.expect("no data");
+
+        let mut wrong = open(stored.clone(), "hunter3");
+        assert!(matches!(
+            wrong.get_data().await,
+            Err(BackendError::Decryption)
+        ));
+        let mut raw_key = EncryptedBackend::new(MemoryBackend::from_vec(stored), [0; 32]);
+        assert!(matches!(
+            raw_key.get_data().await,
+            Err(BackendError::Decryption)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_changed_header_is_refused() {
+        let mut backend = open(Vec::new(), "hunter2");
+        backend
+            .put_data(&[4, 5, 1, 6, 8, 1])
+            .await
+            .expect("could not put data");
+        let stored = backend.into_inner().get_data().await.expect("no data");
+        // The memory cost follows the magic, the kind, the salt length and
+        // the salt.
+        let memory_at = 6 + 2 + SALT.len();
+
+        let mut greedy = stored.clone();
+        greedy[memory_at..memory_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            open(greedy, "hunter2").get_data().await,
+            Err(BackendError::Decryption)
+        ));
+
+        // Within the configured costs, but not the ones the data was
+        // encrypted with.
+        let mut cheaper = stored;
+        cheaper[memory_at..memory_at + 4].copy_from_slice(&32_u32.to_le_bytes());
+        assert!(matches!(
+            open(cheaper, "hunter2").get_data().await,
+            Err(BackendError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn test_derive_key_is_deterministic() {
+        let key = EncryptedBackend::<MemoryBackend>::derive_key(b"hunter2", SALT, PARAMS)
+            .expect("could not derive key");
+        assert_eq!(
+            key,
+            EncryptedBackend::<MemoryBackend>::derive_key(b"hunter2", SALT, PARAMS)
+                .expect("could not derive key")
+        );
+        assert_ne!(
+            key,
+            EncryptedBackend::<MemoryBackend>::derive_key(b"hunter2", b"other salt", PARAMS)
+                .expect("could not derive key")
+        );
+        assert!(
+            EncryptedBackend::<MemoryBackend>::derive_key(b"hunter2", b"short", PARAMS).is_err()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`FencedBackend`], refusing to overwrite data
+//! written by a newer writer.
+
+use super::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError};
+use std::io;
+
+/// Written at the start of the stored data, followed by the generation.
+const MAGIC: &[u8; 8] = b"DBFENCE1";
+const HEADER_LEN: usize = MAGIC.len() + 8;
+
+/// Split stored `data` into its generation and the data itself.
+///
+/// Data without a header, written before the backend was fenced, is of
+/// generation 0.
+fn split(data: &[u8]) -> (u64, &[u8]) {
+    match data.strip_prefix(&MAGIC[..]) {
+        Some(rest) if rest.len() >= 8 => {
+            let mut generation = [0; 8];
+            generation.copy_from_slice(&rest[..8]);
+            (u64::from_le_bytes(generation), &rest[8..])
+        }
+        _ => (0, data),
+    }
+}
+
+/// A [`Backend`] storing a generation number in front of the data, which
+/// every write increments, and refusing to write over a newer generation.
+///
+/// This keeps a writer that is out of date, like a process which was started
+/// before an upgrade and is still running next to the new one, from
+/// overwriting data it never read. A write first reads the generation in the
+/// backend, and fails with [`BackendError::StaleGeneration`] if it is newer
+/// than the one this backend last read or wrote. Otherwise the data is
+/// written with the next generation. Reading the data again catches the
+/// writer up.
+///
+/// Checking the generation and writing are separate operations of the inner
+/// backend, two writers racing each other can both pass the check. This
+/// catches writers that are behind, it is not a lock.
+#[derive(Debug)]
+pub struct FencedBackend<B> {
+    inner: B,
+    generation: u64,
+}
+
+impl<B> FencedBackend<B> {
+    /// Fence the data stored in `inner`.
+    ///
+    /// Until the data is read, this writer is of generation 0 and can only
+    /// write over data that was never written through a `FencedBackend`.
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            generation: 0,
+        }
+    }
+
+    /// The generation of the data this backend last read or wrote.
+    #[must_use]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Return the inner backend.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B> Backend for FencedBackend<B>
+where
+    B: Backend + Send,
+{
+    async fn init(&mut self) -> error::BackendResult<()> {
+        self.inner.init().await
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let data = self.inner.get_data().await?;
+        let (generation, data) = split(&data);
+        self.generation = generation;
+        Ok(data.to_vec())
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let theirs = match self.inner.get_data().await {
+            Ok(stored) => split(&stored).0,
+            Err(BackendError::Io(e)) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        if theirs > self.generation {
+            return Err(BackendError::StaleGeneration {
+                ours: self.generation,
+                theirs,
+            });
+        }
+
+        let generation = theirs + 1;
+        let mut stored = Vec::with_capacity(HEADER_LEN + data.len());
+        stored.extend_from_slice(MAGIC);
+        stored.extend_from_slice(&generation.to_le_bytes());
+        stored.extend_from_slice(data);
+        self.inner.put_data(&stored).await?;
+        self.generation = generation;
+        Ok(())
+    }
+
+    /// The header has to be rewritten on every write, it can't be appended
+    /// to.
+    fn capabilities(&self) -> BackendCapabilities {
+        self.inner
+            .capabilities()
+            .difference(BackendCapabilities::APPEND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FencedBackend;
+    use crate::backend::{Backend, PathBackend};
+    use crate::error::BackendError;
+    use std::path::Path;
+
+    async fn open(path: &Path) -> FencedBackend<PathBackend> {
+        let (backend, _) = PathBackend::from_path_or_create(path.to_owned())
+            .await
+            .expect("could not open file");
+        FencedBackend::new(backend)
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_stale_writer_is_rejected() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("db");
+        let mut old = open(&path).await;
+        let mut new = open(&path).await;
+        assert!(old.get_data().await.expect("could not get data").is_empty());
+        assert!(new.get_data().await.expect("could not get data").is_empty());
+
+        new.put_data(b"upgraded").await.expect("could not put data");
+        assert_eq!(1, new.generation());
+        match old.put_data(b"outdated").await {
+            Err(BackendError::StaleGeneration { ours, theirs }) => {
+                assert_eq!((0, 1), (ours, theirs));
+            }
+            res => panic!("expected StaleGeneration, got {:?}", res),
+        }
+        assert_eq!(
+            open(&path)
+                .await
+                .get_data()
+                .await
+                .expect("could not get data"),
+            b"upgraded"
+        );
+
+        // Once caught up, the old writer can write again.
+        assert_eq!(
+            old.get_data().await.expect("could not get data"),
+            b"upgraded"
+        );
+        old.put_data(b"merged").await.expect("could not put data");
+        assert_eq!(2, old.generation());
+        assert!(matches!(
+            new.put_data(b"upgraded again").await,
+            Err(BackendError::StaleGeneration { ours: 1, theirs: 2 })
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`GrpcBackend`], a thin client storing data
+//! in a remote `Storage` gRPC service.
+
+use super::Backend;
+use crate::error;
+use tonic::transport::{Channel, Endpoint};
+
+/// The protocol spoken by [`GrpcBackend`], generated from
+/// `proto/storage.proto`.
+///
+/// A storage service is implemented through
+/// [`proto::storage_server::Storage`].
+///
+/// The code is checked in so that building the crate doesn't need `protoc`.
+/// It was generated by `tonic-build` 0.12 with `build_transport(false)`.
+#[allow(
+    missing_docs,
+    clippy::pedantic,
+    clippy::derive_partial_eq_without_eq,
+    clippy::wildcard_imports
+)]
+pub mod proto {
+    include!("grpc/dropbreak.storage.v1.rs");
+}
+
+use proto::storage_client::StorageClient;
+use proto::{GetRequest, PutRequest};
+
+/// A [`Backend`] storing the data under a key of a remote `Storage` service.
+///
+/// See `proto/storage.proto` for the service definition. No data is cached,
+/// every read and write is a call to the service.
+#[derive(Debug, Clone)]
+pub struct GrpcBackend {
+    client: StorageClient<Channel>,
+    key: String,
+}
+
+impl GrpcBackend {
+    /// Connects to the storage service at `dst`, storing the data under
+    /// `key`.
+    pub async fn connect<D>(dst: D, key: impl Into<String>) -> error::BackendResult<Self>
+    where
+        D: std::convert::TryInto<Endpoint>,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let channel = Endpoint::new(dst)?.connect().await?;
+        Ok(Self::from_channel(channel, key))
+    }
+
+    /// Uses an already established [`Channel`] to the storage service,
+    /// storing the data under `key`.
+    #[must_use]
+    pub fn from_channel(channel: Channel, key: impl Into<String>) -> Self {
+        Self {
+            client: StorageClient::new(channel),
+            key: key.into(),
+        }
+    }
+
+    /// The key the data is stored under.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl Backend for GrpcBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let request = GetRequest {
+            key: self.key.clone(),
+        };
+        let response = self.client.get(request).await.map_err(Box::new)?;
+        Ok(response.into_inner().data)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let request = PutRequest {
+            key: self.key.clone(),
+            data: data.to_vec(),
+        };
+        self.client.put(request).await.map_err(Box::new)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::storage_server::{Storage, StorageServer};
+    use super::proto::{GetRequest, GetResponse, PutRequest, PutResponse};
+    use super::GrpcBackend;
+    use crate::backend::Backend;
+    use crate::error::BackendError;
+    use std::collections::HashMap;
+    use tokio::sync::Mutex;
+    use tonic::{Code, Request, Response, Status};
+
+    /// An in-memory storage service.
+    #[derive(Debug, Default)]
+    struct MemoryStorage(Mutex<HashMap<String, Vec<u8>>>);
+
+    #[tonic::async_trait]
+    impl Storage for MemoryStorage {
+        async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+            let key = request.into_inner().key;
+            match self.0.lock().await.get(&key) {
+                Some(data) => Ok(Response::new(GetResponse { data: data.clone() })),
+                None => Err(Status::not_found(key)),
+            }
+        }
+
+        async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+            let PutRequest { key, data } = request.into_inner();
+            self.0.lock().await.insert(key, data);
+            Ok(Response::new(PutResponse {}))
+        }
+    }
+
+    /// Starts the storage service on a local port and returns its address.
+    async fn serve() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("could not bind listener");
+        let addr = listener.local_addr().expect("listener has no address");
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(StorageServer::new(MemoryStorage::default()))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_grpc_backend_roundtrip() {
+        let addr = serve().await;
+        let mut backend = GrpcBackend::connect(addr.clone(), "db")
+            .await
+            .expect("could not connect");
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+
+        // Another client for the same key sees the data, one for another key
+        // doesn't.
+        let mut same = GrpcBackend::connect(addr.clone(), "db")
+            .await
+            .expect("could not connect");
+        assert_eq!(same.get_data().await.expect("could not get data"), data);
+        let mut other = GrpcBackend::connect(addr, "other")
+            .await
+            .expect("could not connect");
+        match other.get_data().await {
+            Err(BackendError::Grpc(status)) => assert_eq!(Code::NotFound, status.code()),
+            res => panic!("expected a NotFound status, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_grpc_backend_connect_fails() {
+        // Bind and drop a listener to get a port nothing listens on.
+        let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("could not bind listener")
+            .local_addr()
+            .expect("listener has no address");
+        let err = GrpcBackend::connect(format!("http://{addr}"), "db")
+            .await
+            .expect_err("connected to nothing");
+        assert!(matches!(err, BackendError::GrpcTransport(_)));
+    }
+}
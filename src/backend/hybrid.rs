@@ -0,0 +1,655 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`HybridBackend`], appending the changes to a
+//! log and folding them into a snapshot once the log grew too big.
+
+use super::{Backend, BackendCapabilities};
+use crate::error;
+use crate::stats::to_u64;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex as StdMutex, PoisonError};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// The length of the header of the log, the hash of its snapshot.
+const HEADER_LEN: usize = 8;
+/// The length of the offset, removed and inserted lengths of a record.
+const RECORD_HEADER_LEN: usize = 24;
+/// The offset of the records marking the start of a compaction, which no
+/// change can have.
+const COMPACTION_MARK: u64 = u64::MAX;
+
+/// What a [`HybridBackend`] did so far, see
+/// [`HybridBackend::compaction_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompactionStats {
+    /// How often the log was folded into the snapshot.
+    pub compactions: u64,
+    /// The size of the snapshot.
+    pub snapshot_bytes: u64,
+    /// The size of the log, with its header.
+    pub log_bytes: u64,
+    /// The number of changes in the log.
+    pub log_records: u64,
+}
+
+/// A [`Backend`] writing the changes to the data as deltas appended to a log,
+/// on top of a snapshot of the data.
+///
+/// A write only appends the part of the data that changed, as one record of
+/// the log backend, so small changes to big data stay cheap. Once the log is
+/// bigger than a percentage of the snapshot, see
+/// [`HybridBackend::with_compaction_threshold`], the write that crossed it
+/// starts a compaction in a background task, which replaces the snapshot with
+/// the data as of that write. Writes don't wait for it and keep appending to
+/// the log; once the snapshot is written, the records up to the write that
+/// started the compaction are dropped from the log. The log stays bounded
+/// relative to the data, and reads only replay a bounded number of changes.
+/// Reads wait for a running compaction to finish.
+///
+/// The log backend has to support
+/// [`Backend::append_data`], like a [`FileBackend`](super::FileBackend). It
+/// starts with a hash of the snapshot its changes apply to, and a compaction
+/// marks in the log the write it starts from: when a crash happened after the
+/// snapshot was replaced but before the log was rewritten, only the records
+/// after that mark are applied to the new snapshot. A record cut short by a
+/// crash during an append is ignored as well, and removed from the log by the
+/// next read, so that the records appended after it are read again.
+///
+/// Errors of the compactions are ignored, the snapshot and the log are then
+/// left to be folded by the next compaction. Compactions run on the tokio
+/// runtime, writes have to be made from within it.
+///
+/// The first write after the backend was created, if the data wasn't read
+/// before, writes a new snapshot, since there is nothing to compute the
+/// changes against.
+#[derive(Debug)]
+pub struct HybridBackend<S, L> {
+    snapshot: Arc<Mutex<S>>,
+    log: Arc<Mutex<L>>,
+    threshold: u64,
+    /// The data as last read or written, which the next write is compared
+    /// with.
+    current: Option<Vec<u8>>,
+    /// Updated along with the log, while holding its lock.
+    stats: Arc<StdMutex<CompactionStats>>,
+    compaction: Option<JoinHandle<()>>,
+}
+
+/// A record of the log.
+enum Record<'a> {
+    /// Replaces `removed` bytes at `offset` with `inserted`.
+    Change {
+        offset: u64,
+        removed: u64,
+        inserted: &'a [u8],
+    },
+    /// A compaction started, into a snapshot with the hash `hash`.
+    Compaction { hash: u64 },
+}
+
+/// A hash of `data` which is the same on every platform and version, since
+/// it is stored.
+fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The `u64` at `at` in `bytes`, which has to be long enough.
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(value)
+}
+
+/// A record with the given header fields, followed by `inserted`.
+fn encode_record(offset: u64, removed: u64, inserted: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + inserted.len());
+    record.extend_from_slice(&offset.to_le_bytes());
+    record.extend_from_slice(&removed.to_le_bytes());
+    record.extend_from_slice(&to_u64(inserted.len()).to_le_bytes());
+    record.extend_from_slice(inserted);
+    record
+}
+
+/// The record turning `old` into `new`: the offset of the first byte which
+/// differs, how many bytes are replaced and by which ones.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let removed = old.len() - prefix - suffix;
+    encode_record(
+        to_u64(prefix),
+        to_u64(removed),
+        &new[prefix..new.len() - suffix],
+    )
+}
+
+/// The record marking the start of a compaction into a snapshot with the
+/// hash `hash`.
+fn encode_mark(hash: u64) -> Vec<u8> {
+    encode_record(COMPACTION_MARK, hash, &[])
+}
+
+/// The header of `log`, if it has one.
+fn log_header(log: &[u8]) -> Option<u64> {
+    (log.len() >= HEADER_LEN).then(|| read_u64(log, 0))
+}
+
+/// The records of `log`, each with the length of the log up to its end. A
+/// record cut short by a crash during the append ends the log.
+fn parse_log(log: &[u8]) -> Vec<(Record<'_>, usize)> {
+    let mut records = Vec::new();
+    let mut at = HEADER_LEN;
+    while at + RECORD_HEADER_LEN <= log.len() {
+        let (offset, removed) = (read_u64(log, at), read_u64(log, at + 8));
+        let start = at + RECORD_HEADER_LEN;
+        let Some(inserted) = usize::try_from(read_u64(log, at + 16))
+            .ok()
+            .and_then(|len| log.get(start..start.checked_add(len)?))
+        else {
+            break;
+        };
+        at = start + inserted.len();
+        let record = if offset == COMPACTION_MARK {
+            Record::Compaction { hash: removed }
+        } else {
+            Record::Change {
+                offset,
+                removed,
+                inserted,
+            }
+        };
+        records.push((record, at));
+    }
+    records
+}
+
+/// The index of the record after the last mark of a compaction into a
+/// snapshot with the hash `hash`.
+fn after_mark(records: &[(Record<'_>, usize)], hash: u64) -> Option<usize> {
+    records
+        .iter()
+        .rposition(
+            |(record, _)| matches!(record, Record::Compaction { hash: mark } if *mark == hash),
+        )
+        .map(|mark| mark + 1)
+}
+
+/// Apply the changes of `records` to `data`, returning how many there were.
+fn apply_records(data: &mut Vec<u8>, records: &[(Record<'_>, usize)]) -> error::BackendResult<u64> {
+    let corrupted =
+        || error::BackendError::Internal("the log of the hybrid backend is corrupted".into());
+    let mut changes = 0;
+    for (record, _) in records {
+        let Record::Change {
+            offset,
+            removed,
+            inserted,
+        } = record
+        else {
+            continue;
+        };
+        let (Ok(offset), Ok(removed)) = (usize::try_from(*offset), usize::try_from(*removed))
+        else {
+            return Err(corrupted());
+        };
+        if offset
+            .checked_add(removed)
+            .is_none_or(|end| end > data.len())
+        {
+            return Err(corrupted());
+        }
+        data.splice(offset..offset + removed, inserted.iter().copied());
+        changes += 1;
+    }
+    Ok(changes)
+}
+
+/// The log made of the header `hash` and the records of `log` from the
+/// `first` one on, with the number of changes among them.
+fn rebase_log(
+    log: &[u8],
+    records: &[(Record<'_>, usize)],
+    first: usize,
+    hash: u64,
+) -> (Vec<u8>, u64) {
+    let start = first
+        .checked_sub(1)
+        .map_or(HEADER_LEN, |mark| records[mark].1);
+    let end = records.last().map_or(HEADER_LEN, |(_, end)| *end);
+    let mut rebased = hash.to_le_bytes().to_vec();
+    rebased.extend_from_slice(&log[start.min(end)..end]);
+    let changes = records[first..]
+        .iter()
+        .filter(|(record, _)| matches!(record, Record::Change { .. }))
+        .count();
+    (rebased, to_u64(changes))
+}
+
+/// Lock `stats`, which holds no invariant a panic could break.
+fn lock_stats(stats: &StdMutex<CompactionStats>) -> std::sync::MutexGuard<'_, CompactionStats> {
+    stats.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Write `data`, with the hash `hash`, as the new snapshot, and drop the
+/// records up to the mark of this compaction from the log.
+async fn compact<S: Backend, L: Backend>(
+    snapshot: &Mutex<S>,
+    log: &Mutex<L>,
+    stats: &StdMutex<CompactionStats>,
+    data: &[u8],
+    hash: u64,
+) -> error::BackendResult<()> {
+    let mut snapshot = snapshot.lock().await;
+    snapshot.put_data(data).await?;
+    // Writes appended to the log while the snapshot was written, so the log
+    // is read back once it is locked.
+    let mut log = log.lock().await;
+    let bytes = log.get_data().await?;
+    let records = parse_log(&bytes);
+    let first = after_mark(&records, hash).ok_or_else(|| {
+        error::BackendError::Internal("the log of the hybrid backend lost a compaction".into())
+    })?;
+    let (rebased, changes) = rebase_log(&bytes, &records, first, hash);
+    log.put_data(&rebased).await?;
+    let mut stats = lock_stats(stats);
+    stats.compactions += 1;
+    stats.snapshot_bytes = to_u64(data.len());
+    stats.log_bytes = to_u64(rebased.len());
+    stats.log_records = changes;
+    Ok(())
+}
+
+impl<S, L> HybridBackend<S, L>
+where
+    S: Backend + Send + 'static,
+    L: Backend + Send + 'static,
+{
+    /// Keep the data in `snapshot` and append the changes to `log`.
+    ///
+    /// The log is compacted once it is bigger than the snapshot.
+    pub fn new(snapshot: S, log: L) -> Self {
+        Self {
+            snapshot: Arc::new(Mutex::new(snapshot)),
+            log: Arc::new(Mutex::new(log)),
+            threshold: 100,
+            current: None,
+            stats: Arc::new(StdMutex::new(CompactionStats::default())),
+            compaction: None,
+        }
+    }
+
+    /// Compact the log once it is bigger than `percent` percent of the
+    /// snapshot.
+    ///
+    /// Lower thresholds keep reads fast and the storage small, higher ones
+    /// make fewer compactions copy the whole data. A threshold of 0 starts a
+    /// compaction on every write, unless one is running already.
+    #[must_use]
+    pub fn with_compaction_threshold(mut self, percent: u32) -> Self {
+        self.threshold = u64::from(percent);
+        self
+    }
+
+    /// The size of the log, in percent of the snapshot, from which it is
+    /// compacted.
+    #[must_use]
+    pub fn compaction_threshold(&self) -> u32 {
+        u32::try_from(self.threshold).unwrap_or(u32::MAX)
+    }
+
+    /// The compactions so far and the current sizes of the snapshot and the
+    /// log, as of the last read, write or compaction.
+    #[must_use]
+    pub fn compaction_stats(&self) -> CompactionStats {
+        *lock_stats(&self.stats)
+    }
+
+    /// Wait for the compaction started by an earlier write to finish.
+    pub async fn wait_for_compaction(&mut self) {
+        if let Some(compaction) = self.compaction.take() {
+            // A compaction that panicked has nothing left to do either.
+            let _ = compaction.await;
+        }
+    }
+
+    /// Wait for the compaction, and return the snapshot and log backends.
+    pub async fn into_parts(mut self) -> (S, L) {
+        self.wait_for_compaction().await;
+        (
+            Arc::try_unwrap(self.snapshot)
+                .unwrap_or_else(|_| unreachable!("the compaction is over"))
+                .into_inner(),
+            Arc::try_unwrap(self.log)
+                .unwrap_or_else(|_| unreachable!("the compaction is over"))
+                .into_inner(),
+        )
+    }
+
+    /// Start a compaction into `data`, whose mark with the hash `hash` was
+    /// appended to the log.
+    fn start_compaction(&mut self, data: Vec<u8>, hash: u64) {
+        let snapshot = Arc::clone(&self.snapshot);
+        let log = Arc::clone(&self.log);
+        let stats = Arc::clone(&self.stats);
+        self.compaction = Some(tokio::spawn(async move {
+            let _ = compact(&snapshot, &log, &stats, &data, hash).await;
+        }));
+    }
+
+    /// Write `data` as the new snapshot and empty the log, right away.
+    async fn replace(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        self.wait_for_compaction().await;
+        self.current = None;
+        let hash = stable_hash(data);
+        self.snapshot.lock().await.put_data(data).await?;
+        let mut log = self.log.lock().await;
+        log.put_data(&hash.to_le_bytes()).await?;
+        self.current = Some(data.to_vec());
+        let mut stats = lock_stats(&self.stats);
+        stats.compactions += 1;
+        stats.snapshot_bytes = to_u64(data.len());
+        stats.log_bytes = to_u64(HEADER_LEN);
+        stats.log_records = 0;
+        Ok(())
+    }
+}
+
+impl<S, L> Backend for HybridBackend<S, L>
+where
+    S: Backend + Send + 'static,
+    L: Backend + Send + 'static,
+{
+    async fn init(&mut self) -> error::BackendResult<()> {
+        self.snapshot.lock().await.init().await?;
+        self.log.lock().await.init().await
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        // Both are held, so that no compaction replaces the snapshot between
+        // reading it and reading its log.
+        let mut snapshot = self.snapshot.lock().await;
+        let mut log_backend = self.log.lock().await;
+        let mut data = snapshot.get_data().await?;
+        let log = log_backend.get_data().await?;
+        let hash = stable_hash(&data);
+        let records = parse_log(&log);
+
+        let first = if log_header(&log) == Some(hash) {
+            Some(0)
+        } else {
+            // Cut short after writing the snapshot, or left over from before
+            // the last compaction.
+            after_mark(&records, hash)
+        };
+        let (rebased, changes) = match first {
+            Some(first) => {
+                let changes = apply_records(&mut data, &records[first..])?;
+                let (rebased, _) = rebase_log(&log, &records, first, hash);
+                (rebased, changes)
+            }
+            None => (hash.to_le_bytes().to_vec(), 0),
+        };
+        // Appending after a torn record would hide the new records behind
+        // it.
+        if rebased != log {
+            log_backend.put_data(&rebased).await?;
+        }
+        let mut stats = lock_stats(&self.stats);
+        stats.snapshot_bytes = to_u64(data.len());
+        stats.log_bytes = to_u64(rebased.len());
+        stats.log_records = changes;
+        self.current = Some(data.clone());
+        Ok(data)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let mut record = match &self.current {
+            Some(current) if current.as_slice() == data => return Ok(()),
+            Some(current) => encode_delta(current, data),
+            None => return self.replace(data).await,
+        };
+        let mut log = self.log.lock().await;
+        let stats = self.compaction_stats();
+        let log_bytes = stats.log_bytes + to_u64(record.len());
+        let compacting = self
+            .compaction
+            .as_ref()
+            .is_some_and(|compaction| !compaction.is_finished());
+        let hash = (!compacting
+            && log_bytes.saturating_mul(100) > stats.snapshot_bytes.saturating_mul(self.threshold))
+        .then(|| stable_hash(data));
+        if let Some(hash) = hash {
+            record.extend(encode_mark(hash));
+        }
+
+        // Until the append is known to have succeeded, the log can't be
+        // trusted to match the data in memory.
+        self.current = None;
+        log.append_data(&record).await?;
+        self.current = Some(data.to_vec());
+        {
+            let mut stats = lock_stats(&self.stats);
+            stats.log_bytes += to_u64(record.len());
+            stats.log_records += 1;
+        }
+        drop(log);
+        if let Some(hash) = hash {
+            self.start_compaction(data.to_vec(), hash);
+        }
+        Ok(())
+    }
+
+    /// Writes append to the log, which may not be atomic.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_delta, encode_mark, stable_hash, HybridBackend};
+    use crate::backend::{Backend, BackendCapabilities, MemoryBackend};
+    use crate::error;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::Semaphore;
+
+    /// A [`MemoryBackend`] whose writes wait for a permit of `gate`.
+    #[derive(Debug)]
+    struct Gated {
+        inner: MemoryBackend,
+        gate: Arc<Semaphore>,
+    }
+
+    impl Backend for Gated {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.inner.get_data().await
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.gate
+                .acquire()
+                .await
+                .expect("the gate is closed")
+                .forget();
+            self.inner.put_data(data).await
+        }
+
+        fn capabilities(&self) -> BackendCapabilities {
+            self.inner.capabilities()
+        }
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let old = b"hello little world".to_vec();
+        for new in [
+            &b"hello big world"[..],
+            b"",
+            b"hello little world!",
+            b"oh hello",
+        ] {
+            let mut log = vec![0; super::HEADER_LEN];
+            log.extend(encode_delta(&old, new));
+            let records = super::parse_log(&log);
+            assert_eq!(log.len(), records[0].1);
+            let mut data = old.clone();
+            assert_eq!(
+                1,
+                super::apply_records(&mut data, &records).expect("bad log")
+            );
+            assert_eq!(new, data.as_slice());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_compacts_automatically() {
+        let mut backend = HybridBackend::new(MemoryBackend::new(), MemoryBackend::new())
+            .with_compaction_threshold(50);
+        assert_eq!(50, backend.compaction_threshold());
+        assert!(backend
+            .get_data()
+            .await
+            .expect("could not get data")
+            .is_empty());
+
+        let mut data: Vec<u8> = (0..=255).collect();
+        backend.put_data(&data).await.expect("could not put data");
+        backend.wait_for_compaction().await;
+        assert_eq!(1, backend.compaction_stats().compactions);
+
+        for i in 0..20 {
+            let before = backend.compaction_stats();
+            data[i * 10] ^= 0xff;
+            backend.put_data(&data).await.expect("could not put data");
+            backend.wait_for_compaction().await;
+            let after = backend.compaction_stats();
+            assert!(after.log_bytes <= after.snapshot_bytes / 2);
+            if after.compactions > before.compactions {
+                assert!(after.log_bytes < before.log_bytes);
+            } else {
+                assert_eq!(before.log_records + 1, after.log_records);
+            }
+        }
+        let stats = backend.compaction_stats();
+        assert!(stats.compactions > 1);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+
+        let (snapshot, log) = backend.into_parts().await;
+        let mut reopened = HybridBackend::new(snapshot, log);
+        assert_eq!(reopened.get_data().await.expect("could not get data"), data);
+        assert_eq!(stats.log_records, reopened.compaction_stats().log_records);
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_writes_during_compaction() {
+        // One permit for the first write, which writes the snapshot itself.
+        let gate = Arc::new(Semaphore::new(1));
+        let snapshot = Gated {
+            inner: MemoryBackend::new(),
+            gate: Arc::clone(&gate),
+        };
+        let mut backend =
+            HybridBackend::new(snapshot, MemoryBackend::new()).with_compaction_threshold(50);
+        let mut data: Vec<u8> = (0..=255).collect();
+        backend.put_data(&data).await.expect("could not put data");
+
+        // The compaction started by one of these waits for the gate, the
+        // writes don't.
+        for i in 0..20 {
+            data[i * 10] ^= 0xff;
+            tokio::time::timeout(Duration::from_secs(10), backend.put_data(&data))
+                .await
+                .expect("the write waited for the compaction")
+                .expect("could not put data");
+        }
+        let stats = backend.compaction_stats();
+        assert_eq!(1, stats.compactions);
+        assert_eq!(20, stats.log_records);
+
+        gate.add_permits(1);
+        backend.wait_for_compaction().await;
+        let compacted = backend.compaction_stats();
+        assert_eq!(2, compacted.compactions);
+        assert!(compacted.log_records < stats.log_records);
+        assert!(compacted.log_bytes < stats.log_bytes);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+
+        let (snapshot, log) = backend.into_parts().await;
+        let mut reopened = HybridBackend::new(snapshot.inner, log);
+        assert_eq!(reopened.get_data().await.expect("could not get data"), data);
+        assert_eq!(
+            compacted.log_records,
+            reopened.compaction_stats().log_records
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_recovers_compaction_cut_short() {
+        let mut backend = HybridBackend::new(MemoryBackend::new(), MemoryBackend::new());
+        let mut data: Vec<u8> = (0..=255).collect();
+        backend.put_data(&data).await.expect("could not put data");
+        data[3] = 0;
+        backend.put_data(&data).await.expect("could not put data");
+
+        // A compaction into the data so far, and a write made meanwhile, and
+        // a crash after the snapshot was written but before the log was.
+        let (mut snapshot, mut log) = backend.into_parts().await;
+        let compacted = data.clone();
+        data[7] = 0;
+        let mut appended = encode_mark(stable_hash(&compacted));
+        appended.extend(encode_delta(&compacted, &data));
+        log.append_data(&appended)
+            .await
+            .expect("could not append data");
+        snapshot
+            .put_data(&compacted)
+            .await
+            .expect("could not put data");
+
+        let mut backend = HybridBackend::new(snapshot, log);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(1, backend.compaction_stats().log_records);
+
+        let (snapshot, log) = backend.into_parts().await;
+        let mut reopened = HybridBackend::new(snapshot, log);
+        assert_eq!(reopened.get_data().await.expect("could not get data"), data);
+        assert_eq!(1, reopened.compaction_stats().log_records);
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_appends_after_torn_record() {
+        let mut backend = HybridBackend::new(MemoryBackend::new(), MemoryBackend::new());
+        let mut data: Vec<u8> = (0..=255).collect();
+        backend.put_data(&data).await.expect("could not put data");
+        data[3] = 0;
+        backend.put_data(&data).await.expect("could not put data");
+
+        // A crash in the middle of the next append.
+        let (snapshot, mut log) = backend.into_parts().await;
+        let mut torn = encode_delta(&data, b"never written");
+        torn.truncate(torn.len() - 4);
+        log.append_data(&torn).await.expect("could not append data");
+
+        let mut backend = HybridBackend::new(snapshot, log);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        data[7] = 0;
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(2, backend.compaction_stats().log_records);
+
+        let (snapshot, log) = backend.into_parts().await;
+        let mut reopened = HybridBackend::new(snapshot, log);
+        assert_eq!(reopened.get_data().await.expect("could not get data"), data);
+        assert_eq!(2, reopened.compaction_stats().log_records);
+    }
+}
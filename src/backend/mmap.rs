@@ -1,9 +1,14 @@
-use super::Backend;
+use super::{Backend, BackendCapabilities};
 
 use crate::error;
 
+use std::borrow::Cow;
 use std::cmp;
+use std::collections::HashMap;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, Weak};
+use tempfile::NamedTempFile;
 
 #[derive(Debug)]
 struct Mmap {
@@ -32,9 +37,9 @@ impl Mmap {
     /// Copies data to mmap and modifies data's end cursor.
     fn write(&mut self, data: &[u8]) -> error::BackendResult<()> {
         if data.len() > self.len {
-            return Err(error::BackendError::Internal(format!(
-                "Unexpected write beyond mmap's backend capacity."
-            )));
+            return Err(error::BackendError::Internal(
+                "Unexpected write beyond mmap's backend capacity.".to_owned(),
+            ));
         }
         self.end = data.len();
         self.as_mut_slice().copy_from_slice(data);
@@ -88,14 +93,18 @@ impl MmapStorage {
 }
 
 impl Backend for MmapStorage {
-    fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
         let mmap = self.mmap.as_slice();
         let mut buffer = Vec::with_capacity(mmap.len());
         buffer.extend_from_slice(mmap);
         Ok(buffer)
     }
 
-    fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+    async fn get_data_cow(&mut self) -> error::BackendResult<Cow<'_, [u8]>> {
+        Ok(Cow::Borrowed(self.mmap.as_slice()))
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
         if self.mmap.len < data.len() {
             self.mmap.resize_no_copy(data.len())?;
         }
@@ -105,42 +114,250 @@ impl Backend for MmapStorage {
     }
 }
 
+/// The mapping of a file, shared by all the [`MmapBackend`]s of its path.
+#[derive(Debug)]
+struct SharedMap {
+    path: PathBuf,
+    state: Mutex<MapState>,
+}
+
+#[derive(Debug, Default)]
+struct MapState {
+    /// Bumped every time the file is mapped again.
+    generation: u64,
+    /// `None` for an empty file, which can't be mapped.
+    map: Option<Arc<memmap::Mmap>>,
+}
+
+/// The shared mappings, by canonical path.
+fn shared_maps() -> &'static Mutex<HashMap<PathBuf, Weak<SharedMap>>> {
+    static MAPS: OnceLock<Mutex<HashMap<PathBuf, Weak<SharedMap>>>> = OnceLock::new();
+    MAPS.get_or_init(Mutex::default)
+}
+
+impl SharedMap {
+    /// The mapping of the file at the canonical `path`, mapping it if no
+    /// backend has it mapped yet.
+    fn get(path: PathBuf) -> io::Result<Arc<Self>> {
+        let mut maps = shared_maps().lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(shared) = maps.get(&path).and_then(Weak::upgrade) {
+            return Ok(shared);
+        }
+        maps.retain(|_, shared| shared.strong_count() > 0);
+        let shared = Arc::new(Self {
+            path: path.clone(),
+            state: Mutex::default(),
+        });
+        shared.remap()?;
+        maps.insert(path, Arc::downgrade(&shared));
+        Ok(shared)
+    }
+
+    /// Map the file again, for every backend sharing it.
+    fn remap(&self) -> io::Result<()> {
+        let file = std::fs::File::open(&self.path)?;
+        let map = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            // SAFETY: the backends only ever replace the file by renaming a
+            // new one over it, they never modify a mapped file. Modifying it
+            // in place from outside is documented as unsupported.
+            #[allow(unsafe_code)]
+            let map = unsafe { memmap::Mmap::map(&file)? };
+            Some(Arc::new(map))
+        };
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.generation += 1;
+        state.map = map;
+        Ok(())
+    }
+}
+
+/// A backend reading a file through a memory map shared by all the
+/// `MmapBackend`s of the same path in the process.
+///
+/// Many databases reading the same file then use a single mapping, instead
+/// of each mapping or reading it on their own. Loads borrow the data straight
+/// from the mapping.
+///
+/// Writes are atomic like with a [`PathBackend`](super::PathBackend): the
+/// data is written to a temporary file which is renamed over the file. The
+/// shared mapping is then replaced by one of the new file, so the write is
+/// seen by the next load of every backend of the path. The mapping of the old
+/// file is kept until no backend uses it anymore.
+///
+/// Writes by other processes are only seen after
+/// [`MmapBackend::refresh`]. They have to replace the file as well: a file
+/// modified in place while it is mapped can change under a load, or cut it
+/// short.
+///
+/// **Important**: This is only available with the `mmap` feature
+#[derive(Debug)]
+pub struct MmapBackend {
+    shared: Arc<SharedMap>,
+    /// The generation of `map`.
+    seen: u64,
+    map: Option<Arc<memmap::Mmap>>,
+}
+
+impl MmapBackend {
+    /// Opens the file at `path`, creating it empty if it doesn't exist, and
+    /// shares the mapping of the other backends of the file.
+    pub fn open(path: impl AsRef<Path>) -> error::BackendResult<Self> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.as_ref())?;
+        let shared = SharedMap::get(path.as_ref().canonicalize()?)?;
+        Ok(Self {
+            shared,
+            seen: 0,
+            map: None,
+        })
+    }
+
+    /// The canonical path of the file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    /// Map the file again, for every backend of the path, to see what other
+    /// processes wrote to it.
+    pub fn refresh(&self) -> error::BackendResult<()> {
+        Ok(self.shared.remap()?)
+    }
+
+    /// Catch up with the shared mapping and return the mapped data.
+    fn mapped(&mut self) -> &[u8] {
+        let state = self
+            .shared
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.generation != self.seen {
+            self.seen = state.generation;
+            self.map = state.map.clone();
+        }
+        drop(state);
+        self.map.as_deref().map_or(&[], |map| &map[..])
+    }
+}
+
+impl Backend for MmapBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        Ok(self.mapped().to_vec())
+    }
+
+    async fn get_data_cow(&mut self) -> error::BackendResult<Cow<'_, [u8]>> {
+        Ok(Cow::Borrowed(self.mapped()))
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        use std::io::Write;
+
+        let path = &self.shared.path;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut tempf = NamedTempFile::new_in(dir)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            tempf.as_file().set_permissions(metadata.permissions())?;
+        }
+        tempf.write_all(data)?;
+        tempf.as_file().sync_all()?;
+        tempf.persist(path)?;
+        self.refresh()
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::ATOMIC
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Backend, MmapStorage};
+    use super::{Backend, MmapBackend, MmapStorage};
+    use std::borrow::Cow;
+    use std::sync::Arc;
 
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_mmap_storage() {
+    async fn test_mmap_storage() {
         let data = [4, 5, 1, 6, 8, 1];
         let mut storage = MmapStorage::new().expect("To crate mmap storage");
 
-        storage.put_data(&data).expect("To put data");
+        storage.put_data(&data).await.expect("To put data");
         assert_eq!(storage.mmap.end, data.len());
-        assert_eq!(storage.get_data().expect("To get data"), data);
+        assert_eq!(storage.get_data().await.expect("To get data"), data);
     }
 
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_mmap_storage_extend() {
+    async fn test_mmap_storage_extend() {
         let data = [4, 5, 1, 6, 8, 1];
         let mut storage = MmapStorage::with_size(4).expect("To crate mmap storage");
 
-        storage.put_data(&data).expect("To put data");
+        storage.put_data(&data).await.expect("To put data");
         assert_eq!(storage.mmap.end, data.len());
         assert_eq!(storage.mmap.len, 8);
-        assert_eq!(storage.get_data().expect("To get data"), data);
+        assert_eq!(storage.get_data().await.expect("To get data"), data);
     }
 
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_mmap_storage_increase_by_new_data_size() {
+    async fn test_mmap_storage_increase_by_new_data_size() {
         let data = [4, 5, 1, 6, 8, 1];
         let mut storage = MmapStorage::with_size(1).expect("To crate mmap storage");
 
-        storage.put_data(&data).expect("To put data");
+        storage.put_data(&data).await.expect("To put data");
         assert_eq!(storage.mmap.end, data.len());
         assert_eq!(storage.mmap.len, data.len());
-        assert_eq!(storage.get_data().expect("To get data"), data);
+        assert_eq!(storage.get_data().await.expect("To get data"), data);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_mmap_storage_cow_borrows() {
+        let data = [4, 5, 1, 6, 8, 1];
+        let mut storage = MmapStorage::new().expect("To crate mmap storage");
+
+        storage.put_data(&data).await.expect("To put data");
+        let cow = storage.get_data_cow().await.expect("To get data");
+        assert!(matches!(cow, Cow::Borrowed(_)));
+        assert_eq!(&cow[..], &data[..]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_mmap_backend_shared_mapping() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("db");
+        let mut first = MmapBackend::open(&path).expect("could not open backend");
+        let mut second = MmapBackend::open(&path).expect("could not open backend");
+        assert!(Arc::ptr_eq(&first.shared, &second.shared));
+        assert!(second
+            .get_data()
+            .await
+            .expect("could not get data")
+            .is_empty());
+
+        let data = [4, 5, 1, 6, 8, 1];
+        first.put_data(&data).await.expect("could not put data");
+        assert_eq!(second.get_data().await.expect("could not get data"), data);
+        let cow = second.get_data_cow().await.expect("could not get data");
+        assert!(matches!(cow, Cow::Borrowed(_)));
+        assert_eq!(&cow[..], &data[..]);
+        drop(cow);
+
+        // A write from outside, replacing the file, is seen after a refresh.
+        let data2 = [3, 99, 127, 6];
+        let replacement = dir.path().join("db.new");
+        std::fs::write(&replacement, data2).expect("could not write file");
+        std::fs::rename(&replacement, &path).expect("could not replace file");
+        assert_eq!(first.get_data().await.expect("could not get data"), data);
+        second.refresh().expect("could not refresh");
+        assert_eq!(first.get_data().await.expect("could not get data"), data2);
+        assert_eq!(std::fs::read(&path).expect("could not read file"), data2);
     }
 }
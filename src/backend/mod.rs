@@ -8,9 +8,17 @@
 //! `MemoryBackend`.
 //!
 //! Implementing your own Backend should be straightforward. Check the `Backend`
-//! documentation for details.
+//! documentation for details. Wrappers around another backend can use
+//! [`delegate_backend!`](crate::delegate_backend) to forward the methods they
+//! don't change.
 
 use crate::error;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// The Backend Trait.
 ///
@@ -20,38 +28,411 @@ use crate::error;
 ///
 /// **Important**: You can only return custom errors if the `other_errors` feature is enabled
 pub trait Backend {
+    /// Set up the backend for its first use, like connecting to a server,
+    /// creating a bucket or a table.
+    ///
+    /// This keeps constructing a backend cheap: backends needing such a setup
+    /// run it on their first read or write if `init` wasn't called, so
+    /// calling it is optional and moves the cost and the errors of the setup
+    /// up front. Calling it again after it succeeded does nothing. Wrappers
+    /// forward it to the backends they wrap. The default implementation does
+    /// nothing.
+    fn init(&mut self) -> impl std::future::Future<Output = error::BackendResult<()>> + Send
+    where
+        Self: Send,
+    {
+        async { Ok(()) }
+    }
+
     /// Read the all data from the backend.
     fn get_data(
         &mut self,
     ) -> impl std::future::Future<Output = error::BackendResult<Vec<u8>>> + Send;
 
+    /// Read all the data from the backend, borrowing it if the backend
+    /// already holds it in memory.
+    ///
+    /// This lets the data be deserialized without first copying it. The
+    /// default implementation returns the owned result of
+    /// [`Backend::get_data`].
+    fn get_data_cow(
+        &mut self,
+    ) -> impl std::future::Future<Output = error::BackendResult<Cow<'_, [u8]>>> + Send
+    where
+        Self: Send,
+    {
+        async move { self.get_data().await.map(Cow::Owned) }
+    }
+
+    /// Read all the data from the backend into `writer`, returning the
+    /// number of bytes read.
+    ///
+    /// This lets data bigger than the memory be processed as it is read.
+    /// Backends which can read their data bit by bit write it as it comes,
+    /// the default implementation writes the result of
+    /// [`Backend::get_data`] at once.
+    fn get_data_into(
+        &mut self,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> impl std::future::Future<Output = error::BackendResult<u64>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            let data = self.get_data().await?;
+            writer.write_all(&data).await?;
+            writer.flush().await?;
+            Ok(data.len() as u64)
+        }
+    }
+
     /// Write the whole slice to the backend.
-    fn put_data(&mut self, data: &[u8]) -> impl std::future::Future<Output = error::BackendResult<()>> + Send;
+    fn put_data(
+        &mut self,
+        data: &[u8],
+    ) -> impl std::future::Future<Output = error::BackendResult<()>> + Send;
+
+    /// Write the whole slice to the backend, returning the number of bytes
+    /// written.
+    ///
+    /// The default implementation calls [`Backend::put_data`] and returns the
+    /// length of `data`.
+    fn put_data_counted(
+        &mut self,
+        data: &[u8],
+    ) -> impl std::future::Future<Output = error::BackendResult<usize>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            self.put_data(data).await?;
+            Ok(data.len())
+        }
+    }
+
+    /// Write the whole slice to the backend, calling `progress` with the
+    /// number of bytes written so far and the total number of bytes.
+    ///
+    /// This lets big saves show their progress. The number of calls depends
+    /// on the backend, but the bytes written never decrease and the last call
+    /// reports all of them. The default implementation calls
+    /// [`Backend::put_data`] and reports the progress once it completed.
+    fn put_data_with_progress(
+        &mut self,
+        data: &[u8],
+        progress: &mut (dyn FnMut(u64, u64) + Send),
+    ) -> impl std::future::Future<Output = error::BackendResult<()>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            self.put_data(data).await?;
+            let total = data.len() as u64;
+            progress(total, total);
+            Ok(())
+        }
+    }
+
+    /// Replace the data of the backend with everything `reader` yields,
+    /// returning the number of bytes written.
+    ///
+    /// Backends which can write their data bit by bit consume the reader as
+    /// it comes, the default implementation reads it to the end and calls
+    /// [`Backend::put_data_counted`].
+    fn put_data_from(
+        &mut self,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> impl std::future::Future<Output = error::BackendResult<u64>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            let data = read_until_eof(reader).await?;
+            Ok(self.put_data_counted(&data).await? as u64)
+        }
+    }
+
+    /// Add `data` to the end of the data already in the backend.
+    ///
+    /// This is optional: backends which can only replace all of their data
+    /// at once keep the default implementation, which fails with
+    /// [`BackendError::Unsupported`](error::BackendError::Unsupported).
+    fn append_data(
+        &mut self,
+        data: &[u8],
+    ) -> impl std::future::Future<Output = error::BackendResult<()>> + Send
+    where
+        Self: Send,
+    {
+        let _ = data;
+        async {
+            Err(error::BackendError::Unsupported {
+                operation: "append_data",
+            })
+        }
+    }
+
+    /// The optional features this backend supports.
+    ///
+    /// This lets callers pick a code path up front, instead of trying an
+    /// optional method and handling
+    /// [`BackendError::Unsupported`](error::BackendError::Unsupported). The
+    /// default implementation reports none.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::empty()
+    }
 }
 
 impl<T: Backend> Backend for Box<T>
 where
     T: Send,
 {
+    async fn init(&mut self) -> error::BackendResult<()> {
+        use std::ops::DerefMut;
+        self.deref_mut().init().await
+    }
+
     async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
         use std::ops::DerefMut;
         self.deref_mut().get_data().await
     }
 
+    async fn get_data_cow(&mut self) -> error::BackendResult<Cow<'_, [u8]>> {
+        use std::ops::DerefMut;
+        self.deref_mut().get_data_cow().await
+    }
+
+    async fn get_data_into(
+        &mut self,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> error::BackendResult<u64> {
+        use std::ops::DerefMut;
+        self.deref_mut().get_data_into(writer).await
+    }
+
     async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
         use std::ops::DerefMut;
         self.deref_mut().put_data(data).await
     }
+
+    async fn put_data_counted(&mut self, data: &[u8]) -> error::BackendResult<usize> {
+        use std::ops::DerefMut;
+        self.deref_mut().put_data_counted(data).await
+    }
+
+    async fn put_data_with_progress(
+        &mut self,
+        data: &[u8],
+        progress: &mut (dyn FnMut(u64, u64) + Send),
+    ) -> error::BackendResult<()> {
+        use std::ops::DerefMut;
+        self.deref_mut()
+            .put_data_with_progress(data, progress)
+            .await
+    }
+
+    async fn put_data_from(
+        &mut self,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> error::BackendResult<u64> {
+        use std::ops::DerefMut;
+        self.deref_mut().put_data_from(reader).await
+    }
+
+    async fn append_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        use std::ops::DerefMut;
+        self.deref_mut().append_data(data).await
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        use std::ops::Deref;
+        self.deref().capabilities()
+    }
+}
+
+/// The hash used to detect that the data in a backend changed.
+///
+/// With `canonical`, JSON data is hashed in its
+/// [canonical form](crate::deser::canonicalize), so that data which was only
+/// reformatted keeps its hash. Data which isn't JSON is hashed as it is.
+pub(crate) fn data_hash(data: &[u8], canonical: bool) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    #[cfg(feature = "json_enc")]
+    {
+        if canonical {
+            if let Ok(canonical) = crate::deser::canonicalize(data) {
+                canonical.hash(&mut hasher);
+                return hasher.finish();
+            }
+        }
+    }
+    #[cfg(not(feature = "json_enc"))]
+    let _ = canonical;
+    data.hash(&mut hasher);
+    hasher.finish()
 }
 
+/// Reads `reader` until it signals the end of the data.
+///
+/// Readers over pipes, sockets or network file systems may return fewer bytes
+/// than asked for, or be interrupted, long before the end is reached. This
+/// keeps reading until a read returns no bytes at all.
+pub(crate) async fn read_until_eof<R: AsyncRead + Unpin>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 8 * 1024];
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) => return Ok(buffer),
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The directory the file at `path` is in.
+pub(crate) fn dir_of(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Creates a temporary file in `dir`, with the permissions a file created
+/// with `OpenOptions` gets rather than the private ones of `tempfile`.
+pub(crate) fn temp_file_in(dir: &Path) -> io::Result<tempfile::NamedTempFile> {
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
+    }
+    builder.tempfile_in(dir)
+}
+
+/// Creates the file at `path` holding `data`, unless there already is one.
+///
+/// The data is written and synced to a temporary file next to `path`, which
+/// is only then moved to `path`. The file so never exists without its data:
+/// a crash while creating it leaves no file at all, rather than an empty one
+/// that would pass for initialised. Returns whether the file was created,
+/// `false` if there already was one.
+pub(crate) fn create_file_with(path: &Path, data: &[u8]) -> error::BackendResult<bool> {
+    use std::io::Write;
+
+    let mut tempf = temp_file_in(dir_of(path))?;
+    tempf.write_all(data)?;
+    tempf.as_file().sync_all()?;
+    match tempf.persist_noclobber(path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+mod bandwidth;
+pub use bandwidth::BandwidthLimitedBackend;
+
+mod capabilities;
+pub use capabilities::BackendCapabilities;
+
+#[cfg(feature = "cacache")]
+mod cacache;
+#[cfg(feature = "cacache")]
+pub use self::cacache::CacacheBackend;
+
+#[cfg(feature = "object_store")]
+mod chunked;
+#[cfg(feature = "object_store")]
+pub use chunked::ObjectStoreChunkedBackend;
+
+#[cfg(feature = "compression")]
+mod compressed;
+#[cfg(feature = "compression")]
+pub use compressed::CompressedBackend;
+
+mod delegate;
+
+#[cfg(target_os = "linux")]
+mod direct_io;
+#[cfg(target_os = "linux")]
+pub use direct_io::DirectIoBackend;
+
+#[cfg(feature = "encryption")]
+mod encrypted;
+#[cfg(feature = "encryption")]
+pub use encrypted::{EncryptedBackend, KdfParams};
+
+mod fenced;
+pub use fenced::FencedBackend;
+
+mod hybrid;
+pub use hybrid::{CompactionStats, HybridBackend};
+
 #[cfg(feature = "mmap")]
 mod mmap;
 #[cfg(feature = "mmap")]
-pub use mmap::MmapStorage;
+pub use mmap::{MmapBackend, MmapStorage};
 
 mod path;
 pub use path::PathBackend;
 
+mod reconnect;
+pub use reconnect::ReconnectingBackend;
+
+mod replicated;
+pub use replicated::ReplicatedBackend;
+
+mod stream;
+pub use stream::StreamBackend;
+
+#[cfg(feature = "signed")]
+mod signed;
+#[cfg(feature = "signed")]
+pub use signed::SignedBackend;
+
+#[cfg(feature = "sled")]
+mod sled;
+#[cfg(feature = "sled")]
+pub use self::sled::SledBackend;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteBackend;
+
+#[cfg(feature = "testing")]
+mod temp_path;
+#[cfg(feature = "testing")]
+pub use temp_path::TempPathBackend;
+
+#[cfg(feature = "debug-tee")]
+mod debug_tee;
+#[cfg(feature = "debug-tee")]
+pub use debug_tee::DebugTeeBackend;
+
+mod versioned;
+pub use versioned::{ObjectVersion, VersionedBackend};
+
+mod write_back;
+pub use write_back::WriteBackCacheBackend;
+
+#[cfg(feature = "zip")]
+mod zip;
+#[cfg(feature = "zip")]
+pub use self::zip::ZipBackend;
+
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "grpc")]
+pub use grpc::GrpcBackend;
+
+#[cfg(all(windows, feature = "windows"))]
+mod registry;
+#[cfg(all(windows, feature = "windows"))]
+pub use registry::RegistryBackend;
+
 /// A backend using a file.
 #[derive(Debug)]
 pub struct FileBackend(std::fs::File);
@@ -75,6 +456,20 @@ impl Backend for FileBackend {
         self.0.sync_all()?;
         Ok(())
     }
+
+    async fn append_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        use std::io::{Seek, SeekFrom, Write};
+
+        self.0.seek(SeekFrom::End(0))?;
+        self.0.write_all(data)?;
+        self.0.sync_all()?;
+        Ok(())
+    }
+
+    /// The file is truncated before it is written, so writes are not atomic.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::APPEND
+    }
 }
 
 impl FileBackend {
@@ -104,6 +499,12 @@ impl FileBackend {
     /// Creates a file if it doesn't yet exist.
     ///
     /// Returns the [`FileBackend`] and whether the file already existed.
+    ///
+    /// The file is created empty. If the program stops before the first
+    /// save, the empty file is left behind and counts as existing the next
+    /// time. The [`FileDatabase`](crate::FileDatabase) constructors don't
+    /// use this, they only create the file once its first contents are
+    /// written.
     pub fn from_path_or_create<P: AsRef<std::path::Path>>(
         path: P,
     ) -> error::BackendResult<(Self, bool)> {
@@ -116,6 +517,7 @@ impl FileBackend {
                     .read(true)
                     .write(true)
                     .create(true)
+                    .truncate(false)
                     .open(path)?,
             ),
             exists,
@@ -131,7 +533,7 @@ impl FileBackend {
     {
         Self::from_path_or_create(path).map(|(mut b, exists)| {
             if !exists {
-                closure(&mut b.0)
+                closure(&mut b.0);
             }
             b
         })
@@ -150,69 +552,277 @@ impl MemoryBackend {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Construct a new Memory Database already holding `data`.
+    #[must_use]
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        Self(data)
+    }
 }
 
 impl Backend for MemoryBackend {
     async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
-        println!("Returning data: {:?}", &self.0);
         Ok(self.0.clone())
     }
 
+    async fn get_data_cow(&mut self) -> error::BackendResult<Cow<'_, [u8]>> {
+        Ok(Cow::Borrowed(&self.0))
+    }
+
     async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
-        println!("Writing data: {:?}", data);
-        self.0 = data.to_owned();
+        data.clone_into(&mut self.0);
         Ok(())
     }
-}
 
-/*
+    async fn append_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        self.0.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::APPEND
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use super::{Backend, FileBackend, MemoryBackend};
+    use super::{
+        read_until_eof, Backend, BackendCapabilities, FileBackend, MemoryBackend, PathBackend,
+    };
+    use crate::error::BackendError;
+    use std::borrow::Cow;
     use std::io::{Read, Seek, SeekFrom, Write};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
     use tempfile::NamedTempFile;
+    use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+    /// Hands out its data three bytes at a time, interrupted and not ready in
+    /// between.
+    struct FaultyReader {
+        data: Vec<u8>,
+        pos: usize,
+        polls: usize,
+    }
+
+    impl AsyncRead for FaultyReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            self.polls += 1;
+            match self.polls % 3 {
+                0 => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                1 => Poll::Ready(Err(std::io::ErrorKind::Interrupted.into())),
+                _ => {
+                    let end = self.data.len().min(self.pos + 3);
+                    let n = (end - self.pos).min(buf.remaining());
+                    buf.put_slice(&self.data[self.pos..self.pos + n]);
+                    self.pos += n;
+                    Poll::Ready(Ok(()))
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_until_eof_assembles_fragments() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
 
+        // A single read only returns a fragment.
+        let mut reader = FaultyReader {
+            data: data.clone(),
+            pos: 0,
+            polls: 1,
+        };
+        let mut buf = [0; 64];
+        assert_eq!(3, reader.read(&mut buf).await.expect("could not read"));
+
+        let reader = FaultyReader {
+            data: data.clone(),
+            pos: 0,
+            polls: 0,
+        };
+        assert_eq!(read_until_eof(reader).await.expect("could not read"), data);
+    }
+
+    #[cfg(feature = "json_enc")]
     #[test]
-    fn test_memory_backend() {
+    fn test_data_hash_canonical() {
+        use super::data_hash;
+
+        let stored = br#"{"b": [1, 2.50], "a": {"y": null, "x": true}}"#;
+        let reformatted = br#"{"a":{"x":true,"y":null},"b":[1,2.5]}"#;
+        assert_eq!(data_hash(stored, true), data_hash(reformatted, true));
+        assert_ne!(data_hash(stored, false), data_hash(reformatted, false));
+        assert_ne!(
+            data_hash(stored, true),
+            data_hash(br#"{"a":{"x":true,"y":null},"b":[1,2.6]}"#, true)
+        );
+        // Data which isn't JSON is hashed as it is.
+        assert_eq!(data_hash(b"not json", true), data_hash(b"not json", false));
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend() {
         let mut backend = MemoryBackend::new();
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
     }
 
-    #[test]
+    // The default reports the progress once, at the end.
+    #[tokio::test]
+    async fn test_memory_backend_progress_once() {
+        let mut backend = MemoryBackend::new();
+        let data = [4, 5, 1, 6, 8, 1];
+
+        let mut reports = Vec::new();
+        backend
+            .put_data_with_progress(&data, &mut |written, total| {
+                reports.push((written, total));
+            })
+            .await
+            .expect("could not put data");
+        assert_eq!(vec![(6, 6)], reports);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_append() {
+        let mut backend = MemoryBackend::from_vec(vec![4, 5, 1]);
+
+        backend
+            .append_data(&[6, 8, 1])
+            .await
+            .expect("could not append data");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [4, 5, 1, 6, 8, 1]
+        );
+    }
+
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_file_backend_from_file() {
+    async fn test_file_backend_append() {
+        let file = tempfile::tempfile().expect("could not create temporary file");
+        let mut backend = FileBackend::from_file(file);
+
+        backend
+            .put_data(&[4, 5, 1])
+            .await
+            .expect("could not put data");
+        backend
+            .append_data(&[6, 8, 1])
+            .await
+            .expect("could not append data");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [4, 5, 1, 6, 8, 1]
+        );
+    }
+
+    // The file is replaced on every write, appending is left to the default.
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_append_unsupported() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let (mut backend, _) = PathBackend::from_path_or_create(dir.path().join("db"))
+            .await
+            .expect("could not create backend");
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        match backend.append_data(&data).await {
+            Err(BackendError::Unsupported { operation }) => assert_eq!("append_data", operation),
+            res => panic!("expected Unsupported, got {:?}", res),
+        }
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_backend_capabilities() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let (path, _) = PathBackend::from_path_or_create(dir.path().join("db"))
+            .await
+            .expect("could not create backend");
+        let file = FileBackend::from_file(tempfile::tempfile().expect("could not create file"));
+        let memory = MemoryBackend::new();
+
+        assert_eq!(
+            BackendCapabilities::ATOMIC | BackendCapabilities::LOCKING,
+            path.capabilities()
+        );
+        assert_eq!(BackendCapabilities::APPEND, file.capabilities());
+        assert!(!memory.capabilities().contains(BackendCapabilities::ATOMIC));
+        assert!(memory.capabilities().contains(BackendCapabilities::APPEND));
+        assert_eq!(
+            memory.capabilities(),
+            Box::new(MemoryBackend::new()).capabilities()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_cow_borrows() {
+        let mut backend = MemoryBackend::new();
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        let cow = backend.get_data_cow().await.expect("could not get data");
+        assert!(matches!(cow, Cow::Borrowed(_)));
+        assert_eq!(&cow[..], &data[..]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_file_backend_cow_owned() {
+        let file = tempfile::tempfile().expect("could not create temporary file");
+        let mut backend = FileBackend::from_file(file);
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        let cow = backend.get_data_cow().await.expect("could not get data");
+        assert!(matches!(cow, Cow::Owned(_)));
+        assert_eq!(&cow[..], &data[..]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_file_backend_from_file() {
         let file = tempfile::tempfile().expect("could not create temporary file");
         let mut backend = FileBackend::from_file(file);
         let data = [4, 5, 1, 6, 8, 1];
         let data2 = [3, 99, 127, 6];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
 
-        backend.put_data(&data2).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data2);
+        backend.put_data(&data2).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data2);
     }
 
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_file_backend_from_path_existing() {
+    async fn test_file_backend_from_path_existing() {
         let file = NamedTempFile::new().expect("could not create temporary file");
         let (mut backend, existed) =
             FileBackend::from_path_or_create(file.path()).expect("could not create backend");
         assert!(existed);
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
     }
 
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_file_backend_from_path_new() {
+    async fn test_file_backend_from_path_new() {
         let dir = tempfile::tempdir().expect("could not create temporary directory");
         let mut file_path = dir.path().to_owned();
         file_path.push("rustbreak_path_db.db");
@@ -221,21 +831,21 @@ mod tests {
         assert!(!existed);
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
         dir.close().expect("Error while deleting temp directory!");
     }
 
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_file_backend_from_path_nofail() {
+    async fn test_file_backend_from_path_nofail() {
         let file = NamedTempFile::new().expect("could not create temporary file");
         let file_path = file.path().to_owned();
         let mut backend = FileBackend::from_path_or_fail(file_path).expect("should not fail");
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
     }
 
     #[test]
@@ -250,19 +860,19 @@ mod tests {
             assert_eq!(std::io::ErrorKind::NotFound, io_err.kind());
         } else {
             panic!("Wrong kind of error returned: {}", err);
-        };
+        }
         dir.close().expect("Error while deleting temp directory!");
     }
 
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_file_backend_into_inner() {
+    async fn test_file_backend_into_inner() {
         let file = tempfile::tempfile().expect("could not create temporary file");
         let mut backend = FileBackend::from_file(file);
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
 
         let mut file = backend.into_inner();
         file.seek(SeekFrom::Start(0)).unwrap();
@@ -271,19 +881,23 @@ mod tests {
         assert_eq!(&contents[..], &data[..]);
     }
 
-    #[test]
-    fn allow_boxed_backends() {
+    #[tokio::test]
+    async fn allow_boxed_backends() {
         let mut backend = Box::new(MemoryBackend::new());
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).unwrap();
-        assert_eq!(backend.get_data().unwrap(), data);
+        backend.put_data(&data).await.unwrap();
+        assert_eq!(backend.get_data().await.unwrap(), data);
+        assert!(matches!(
+            backend.get_data_cow().await.unwrap(),
+            Cow::Borrowed(_)
+        ));
     }
 
     // If the file already exists, the closure shouldn't be called.
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_file_backend_create_and_existing_nocall() {
+    async fn test_file_backend_create_and_existing_nocall() {
         let file = NamedTempFile::new().expect("could not create temporary file");
         let mut backend = FileBackend::from_path_or_create_and(file.path(), |_| {
             panic!("Closure called but file already existed");
@@ -291,32 +905,30 @@ mod tests {
         .expect("could not create backend");
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
     }
 
     // If the file does not yet exist, the closure should be called.
-    #[test]
+    #[tokio::test]
     #[cfg_attr(miri, ignore)]
-    fn test_file_backend_create_and_new() {
+    async fn test_file_backend_create_and_new() {
         let dir = tempfile::tempdir().expect("could not create temporary directory");
         let mut file_path = dir.path().to_owned();
         file_path.push("rustbreak_path_db.db");
         let mut backend = FileBackend::from_path_or_create_and(file_path, |f| {
             f.write_all(b"this is a new file")
-                .expect("could not write to file")
+                .expect("could not write to file");
         })
         .expect("could not create backend");
         assert_eq!(
-            backend.get_data().expect("could not get data"),
+            backend.get_data().await.expect("could not get data"),
             b"this is a new file"
         );
         let data = [4, 5, 1, 6, 8, 1];
 
-        backend.put_data(&data).expect("could not put data");
-        assert_eq!(backend.get_data().expect("could not get data"), data);
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
         dir.close().expect("Error while deleting temp directory!");
     }
 }
-
-*/
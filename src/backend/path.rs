@@ -5,20 +5,276 @@
 //! Module which implements the [`PathBackend`], storing data in a file on the
 //! file system (with a path) and featuring atomic saves.
 
-use super::Backend;
+use super::{Backend, BackendCapabilities};
 use crate::error;
+use std::future::Future;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 use tempfile::NamedTempFile;
 use tokio::fs::{File, OpenOptions};
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
 
 /// A [`Backend`] using a file given the path.
 ///
 /// Features atomic saves, so that the database file won't be corrupted or
 /// deleted if the program panics during the save.
+///
+/// # Network file systems
+///
+/// On NFS and similar file systems `rename` and `fsync` give weaker
+/// guarantees than on a local disk, and open files can go stale when another
+/// client replaces them. [`PathBackend::nfs_safe`] enables a mode which:
+///
+/// - writes the temporary file with `O_SYNC` and syncs the directory after
+///   the rename,
+/// - reads the file back after saving and fails if it doesn't contain the
+///   written data,
+/// - reopens and retries a few times when a read fails with `ESTALE`.
+///
+/// This makes the failure modes visible, it doesn't make the file system any
+/// more consistent: other clients may still see the old contents until their
+/// attribute cache expires, the read-back only verifies what this client
+/// sees, and concurrent writers on different hosts are not coordinated. Saves
+/// are also noticeably slower. `O_SYNC` and `ESTALE` only exist on unix, on
+/// other platforms only the read-back is done.
+///
+/// # Eventual durability
+///
+/// Every save normally waits for the data to reach the disk. With
+/// [`PathBackend::eventual_durability`] saves only write and rename the
+/// temporary file, and a background task syncs the file and its directory
+/// periodically instead. The saved data is visible to readers right away.
+///
+/// This gives up the guarantee of atomic saves against power loss and
+/// operating system crashes, not only the latest saves. The temporary file
+/// is renamed before its data is synced, and many file systems may persist
+/// the rename first: after a crash the file can then be empty or hold part
+/// of the new data, and the previous contents are gone. Only use this for
+/// data which can be rebuilt or lost. A crash of the program alone loses
+/// nothing, the data is in the page cache. NFS safe mode still syncs every
+/// save.
+///
+/// # Locking
+///
+/// Every save holds an exclusive advisory lock on the `.lock` file next to
+/// the database file, created by the first save and left in place. Saves of
+/// other processes using a [`PathBackend`] on the same file wait for it, so
+/// that their temporary files are never renamed over each other's halfway.
+/// The lock is advisory: programs which don't take it are not held back, and
+/// reads don't take it, they see the file before or after a save thanks to
+/// the atomic rename. On network file systems the lock is only as reliable as
+/// their support for `flock`.
 #[derive(Debug)]
 pub struct PathBackend {
     path: PathBuf,
+    nfs_safe: bool,
+    barrier: Option<DurabilityBarrier>,
+}
+
+/// The state shared with the task syncing the file in eventual durability
+/// mode.
+#[derive(Debug)]
+struct BarrierState {
+    /// The file and the directory it is in, which change when the file is
+    /// moved.
+    location: Mutex<(PathBuf, PathBuf)>,
+    /// Whether something was saved since the last sync.
+    dirty: AtomicBool,
+    /// How many syncs were done.
+    synced: AtomicUsize,
+}
+
+impl BarrierState {
+    /// Syncs the file and the directory it is in.
+    fn sync(&self) -> io::Result<()> {
+        let (path, dir) = self
+            .location
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        std::fs::File::open(path)?.sync_all()?;
+        #[cfg(unix)]
+        std::fs::File::open(dir)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = dir;
+        Ok(())
+    }
+}
+
+/// The task syncing the file in eventual durability mode, stopped when
+/// dropped.
+#[derive(Debug)]
+struct DurabilityBarrier {
+    state: Arc<BarrierState>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for DurabilityBarrier {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// How many times a read failing with `ESTALE` is retried in NFS safe mode.
+const STALE_RETRIES: usize = 3;
+
+/// Whether `err` is caused by a stale file handle.
+fn is_stale(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        err.raw_os_error() == Some(libc::ESTALE)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// Runs `op`, running it again up to `retries` times while it fails with a
+/// stale file handle.
+///
+/// `op` has to open the file itself, so every retry works on a fresh handle.
+async fn retry_stale<F, Fut, T>(mut retries: usize, mut op: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    loop {
+        match op().await {
+            Err(e) if retries > 0 && is_stale(&e) => retries -= 1,
+            res => return res,
+        }
+    }
+}
+
+/// The extensions of the files kept next to the database file, moved along
+/// with it by [`PathBackend::rename_to`].
+const SIDECARS: [&str; 3] = ["lock", "bak", "wal"];
+
+/// The path of the file with the extension `extension` added to `path`.
+fn sidecar(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// The error of renaming `from` to `to`, which failed with `e`.
+fn rename_error(e: io::Error, from: PathBuf, to: PathBuf) -> error::BackendError {
+    if e.kind() == io::ErrorKind::CrossesDevices {
+        error::BackendError::CrossDevice { from, to }
+    } else {
+        e.into()
+    }
+}
+
+/// Renames `from` to `to`, failing with an error of the kind
+/// [`AlreadyExists`](io::ErrorKind::AlreadyExists) rather than replacing a
+/// file at `to`.
+///
+/// Checking for the file and renaming are a single step, so a file created
+/// at `to` by someone else is never replaced. On Linux this is `renameat2`
+/// with `RENAME_NOREPLACE`. Elsewhere, and on file systems not supporting
+/// it, `to` is hard linked to `from`, which never replaces a file either,
+/// and `from` is removed afterwards.
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        match renameat2_noreplace(from, to) {
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) => {}
+            res => return res,
+        }
+    }
+    link_and_remove(from, to)
+}
+
+/// Renames `from` to `to` with `renameat2` and `RENAME_NOREPLACE`.
+#[cfg(target_os = "linux")]
+fn renameat2_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (from, to) = (c_path(from)?, c_path(to)?);
+    // SAFETY: both paths are NUL terminated strings which outlive the call,
+    // and `renameat2` doesn't keep them.
+    #[allow(unsafe_code)]
+    let res = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Moves `from` to `to` by hard linking it there and removing `from`, which
+/// fails if there already is a file at `to`.
+///
+/// Until `from` is removed the file is at both paths.
+fn link_and_remove(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::hard_link(from, to)?;
+    std::fs::remove_file(from)
+}
+
+/// Opens the `.lock` file of `path`, creating it, and waits for an exclusive
+/// lock on it, which is released once the file is closed.
+fn lock_exclusive(path: &Path) -> io::Result<std::fs::File> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(sidecar(path, "lock"))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// [`rename_noreplace`] on a blocking task.
+async fn move_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let (from, to) = (from.to_owned(), to.to_owned());
+    tokio::task::spawn_blocking(move || rename_noreplace(&from, &to))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Escapes the characters of `name` which have a meaning in `.gitignore`
+/// patterns.
+fn escape_gitignore(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | ']' | '!' | '#' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The data directory of the application `app_name`.
+#[cfg(feature = "directories")]
+fn app_data_dir(app_name: &str) -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", app_name).map(|dirs| dirs.data_dir().to_owned())
+}
+
+/// Opens the file at `path` and reads it to the end.
+async fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    super::read_until_eof(OpenOptions::new().read(true).open(path).await?).await
 }
 
 impl PathBackend {
@@ -26,76 +282,603 @@ impl PathBackend {
     /// Errors when the file doesn't yet exist.
     pub async fn from_path_or_fail(path: PathBuf) -> error::BackendResult<Self> {
         OpenOptions::new().read(true).open(path.as_path()).await?;
-        Ok(Self { path })
+        Ok(Self {
+            path,
+            nfs_safe: false,
+            barrier: None,
+        })
     }
 
     /// Opens a new [`PathBackend`] for a given path.
     /// Creates a file if it doesn't yet exist.
     ///
     /// Returns the [`PathBackend`] and whether the file already existed.
+    ///
+    /// The file is created empty. If the program stops before the first
+    /// save, the empty file is left behind and counts as existing the next
+    /// time. [`PathBackend::from_path_or_create_and`] creates the file with
+    /// its first contents in one step, and the
+    /// [`PathDatabase`](crate::PathDatabase) constructors only create the
+    /// file once its first contents are written.
     pub async fn from_path_or_create(path: PathBuf) -> error::BackendResult<(Self, bool)> {
         let exists = path.as_path().is_file();
         OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(false)
             .open(path.as_path())
             .await?;
-        Ok((Self { path }, exists))
+        Ok((
+            Self {
+                path,
+                nfs_safe: false,
+                barrier: None,
+            },
+            exists,
+        ))
+    }
+
+    /// Opens a new [`PathBackend`] for the file `file_name` in the data
+    /// directory of the application `app_name`, creating both if they don't
+    /// yet exist.
+    ///
+    /// The directory is the one the platform sets aside for application data,
+    /// as given by the [`directories`](https://docs.rs/directories) crate:
+    /// `$XDG_DATA_HOME/<app>` or `~/.local/share/<app>` on Linux,
+    /// `~/Library/Application Support/<app>` on macOS and
+    /// `%APPDATA%\<app>\data` on Windows.
+    ///
+    /// Returns the [`PathBackend`] and whether the file already existed. Fails
+    /// with an I/O error of the kind [`NotFound`](io::ErrorKind::NotFound) if
+    /// the home directory of the user can't be determined.
+    ///
+    /// **Important**: This is only available with the `directories` feature
+    #[cfg(feature = "directories")]
+    pub async fn from_app_dirs(
+        app_name: &str,
+        file_name: &str,
+    ) -> error::BackendResult<(Self, bool)> {
+        let dir = app_data_dir(app_name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no home directory for the user")
+        })?;
+        tokio::fs::create_dir_all(&dir).await?;
+        Self::from_path_or_create(dir.join(file_name)).await
+    }
+
+    /// Opens a new [`PathBackend`] for a given path, letting `configure` adjust
+    /// the [`OpenOptions`] used to open it.
+    ///
+    /// The options start out like in [`PathBackend::from_path_or_create`]
+    /// (read, write and create without truncating), `configure` may then add
+    /// platform specific flags or a creation mode.
+    ///
+    /// The options only apply to this first open, which creates the file.
+    /// They are not kept: reads open the file read-only, and saves write a
+    /// temporary file which then replaces it, so flags like `O_SYNC` or
+    /// `O_DIRECT` never apply to them. A creation mode does last, since saves
+    /// keep the permissions of the file. For synchronous writes use
+    /// [`PathBackend::nfs_safe`], for direct I/O the `DirectIoBackend` on Linux.
+    ///
+    /// Returns the [`PathBackend`] and whether the file already existed.
+    pub async fn from_path_with_open_options<C>(
+        path: PathBuf,
+        configure: C,
+    ) -> error::BackendResult<(Self, bool)>
+    where
+        C: FnOnce(&mut OpenOptions),
+    {
+        let exists = path.as_path().is_file();
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        configure(&mut options);
+        options.open(path.as_path()).await?;
+        Ok((
+            Self {
+                path,
+                nfs_safe: false,
+                barrier: None,
+            },
+            exists,
+        ))
     }
 
     /// Opens a new [`PathBackend`] for a given path.
     /// Creates a file if it doesn't yet exist, and calls `closure` with it.
+    ///
+    /// The new file is written like [`PathBackend::put_data`] does: `closure`
+    /// gets a temporary file next to the path, which is only moved to the
+    /// path once `closure` returned. If the program crashes or `closure`
+    /// panics, the path is left untouched and a later call starts over.
     pub async fn from_path_or_create_and<C>(path: PathBuf, closure: C) -> error::BackendResult<Self>
     where
         C: AsyncFnOnce(&mut File),
     {
-        let exists = path.as_path().is_file();
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path.as_path())
-            .await?;
-        if !exists {
-            closure(&mut file).await
+        let backend = Self {
+            path,
+            nfs_safe: false,
+            barrier: None,
+        };
+        if backend.path.as_path().is_file() {
+            return Ok(backend);
         }
-        Ok(Self { path })
+
+        let tempf = super::temp_file_in(backend.dir())?;
+        let mut file = File::from_std(tempf.reopen()?);
+        closure(&mut file).await;
+        file.flush().await?;
+        file.sync_all().await?;
+        drop(file);
+        match tempf.persist_noclobber(backend.path.as_path()) {
+            Ok(_) => {}
+            // Created by someone else in the meantime, keep theirs.
+            Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(backend)
+    }
+
+    /// Enables or disables the mode for network file systems, see
+    /// [Network file systems](PathBackend#network-file-systems).
+    #[must_use]
+    pub fn nfs_safe(mut self, enabled: bool) -> Self {
+        self.nfs_safe = enabled;
+        self
+    }
+
+    /// Stops syncing every save, syncing the file and its directory every
+    /// `period` on a background task instead, see
+    /// [Eventual durability](PathBackend#eventual-durability).
+    ///
+    /// The task only syncs if something was saved since the last sync, and
+    /// stops when the backend is dropped. A sync which fails is tried again
+    /// one `period` later.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, which the task is
+    /// spawned on.
+    #[must_use]
+    pub fn eventual_durability(mut self, period: Duration) -> Self {
+        let state = Arc::new(BarrierState {
+            location: Mutex::new((self.path.clone(), self.dir().to_owned())),
+            dirty: AtomicBool::new(false),
+            synced: AtomicUsize::new(0),
+        });
+        let shared = Arc::clone(&state);
+        let task = tokio::spawn(async move {
+            let mut ticks = interval_at(Instant::now() + period, period);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                if !shared.dirty.swap(false, Ordering::SeqCst) {
+                    continue;
+                }
+                // Syncing waits for the disk, keep it off the runtime's
+                // workers.
+                let state = Arc::clone(&shared);
+                if let Ok(Ok(())) = tokio::task::spawn_blocking(move || state.sync()).await {
+                    shared.synced.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    shared.dirty.store(true, Ordering::SeqCst);
+                }
+            }
+        });
+        self.barrier = Some(DurabilityBarrier { state, task });
+        self
     }
+
+    /// Makes git ignore the files created next to this one, if the directory
+    /// has a `.gitignore`.
+    ///
+    /// This appends patterns for the `.lock`, `.bak` and `.wal` files of the
+    /// database file and for the temporary files of atomic saves to the
+    /// `.gitignore` in the directory of the file. Patterns already in it are
+    /// not added again, so this can be called on every open. The file is only
+    /// ever appended to, and nothing happens if it doesn't exist: creating
+    /// one is left to the user.
+    ///
+    /// The database file itself is not ignored.
+    pub async fn manage_gitignore(self) -> error::BackendResult<Self> {
+        let gitignore = self.dir().join(".gitignore");
+        let existing = match tokio::fs::read_to_string(&gitignore).await {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(self),
+            Err(e) => return Err(e.into()),
+        };
+        let name = self
+            .path
+            .file_name()
+            .map(|name| escape_gitignore(&name.to_string_lossy()))
+            .unwrap_or_default();
+        let patterns = [
+            format!("/{name}.lock"),
+            format!("/{name}.bak"),
+            format!("/{name}.wal"),
+            // The names `tempfile` gives to the temporary files.
+            "/.tmp??????".to_owned(),
+        ];
+        let missing: Vec<&String> = patterns
+            .iter()
+            .filter(|pattern| {
+                !existing
+                    .lines()
+                    .any(|line| line.trim_end() == pattern.as_str())
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(self);
+        }
+
+        let mut append = String::new();
+        if !existing.is_empty() && !existing.ends_with('\n') {
+            append.push('\n');
+        }
+        append.push_str("# Files of the dropbreak database\n");
+        for pattern in missing {
+            append.push_str(pattern);
+            append.push('\n');
+        }
+        let mut file = OpenOptions::new().append(true).open(&gitignore).await?;
+        file.write_all(append.as_bytes()).await?;
+        file.sync_all().await?;
+        Ok(self)
+    }
+
+    /// Moves the file to `new_path`, along with the `.lock`, `.bak` and
+    /// `.wal` files next to it, and saves there from then on.
+    ///
+    /// The file is renamed, which is atomic: at any time the data is either
+    /// at the old path or at the new one. The files next to it are renamed
+    /// afterwards if they exist, and if one of them can't be, the files
+    /// already moved are moved back. The directories are synced before this
+    /// returns, or by the background task in
+    /// [eventual durability](PathBackend#eventual-durability) mode. Moving
+    /// the file to its own path does nothing.
+    ///
+    /// Files are never replaced: if there is already a file at `new_path`, or
+    /// next to it with one of the extensions, nothing is moved. Every file is
+    /// also moved with a rename which fails if there is a file at its
+    /// destination, checking and renaming in one step, so a file created
+    /// there in the meantime isn't replaced either: the files already moved
+    /// are moved back instead. Where the file system can't rename without
+    /// replacing, a file is hard linked to its destination and then removed,
+    /// so that after a crash in between it can be at both paths.
+    ///
+    /// # Errors
+    ///
+    /// Fails with an I/O error of the kind
+    /// [`AlreadyExists`](io::ErrorKind::AlreadyExists) if a file is in the
+    /// way, with [`BackendError::CrossDevice`](error::BackendError::CrossDevice)
+    /// if `new_path` is on another file system, which a rename can't move a
+    /// file to, and with the I/O error if a file can't be renamed. The
+    /// backend keeps its path then.
+    pub async fn rename_to(&mut self, new_path: PathBuf) -> error::BackendResult<()> {
+        if new_path == self.path {
+            return Ok(());
+        }
+        let renames: Vec<_> = std::iter::once((self.path.clone(), new_path.clone()))
+            .chain(SIDECARS.iter().map(|extension| {
+                (
+                    sidecar(&self.path, extension),
+                    sidecar(&new_path, extension),
+                )
+            }))
+            .collect();
+        // Files next to the destination would be taken for those of the
+        // database, even where it has none to move.
+        for (_, to) in &renames {
+            match tokio::fs::symlink_metadata(to).await {
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", to.display()),
+                    )
+                    .into())
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut moved = Vec::new();
+        let mut failure = None;
+        for (index, (from, to)) in renames.into_iter().enumerate() {
+            match move_noreplace(&from, &to).await {
+                Ok(()) => moved.push((from, to)),
+                // The file next to it doesn't exist.
+                Err(e) if index > 0 && e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    failure = Some(rename_error(e, from, to));
+                    break;
+                }
+            }
+        }
+        if let Some(e) = failure {
+            for (from, to) in moved.into_iter().rev() {
+                let _ = move_noreplace(&to, &from).await;
+            }
+            return Err(e);
+        }
+
+        let old_dir = self.dir().to_owned();
+        self.path = new_path;
+        if let Some(barrier) = &self.barrier {
+            *barrier
+                .state
+                .location
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) =
+                (self.path.clone(), self.dir().to_owned());
+            barrier.state.dirty.store(true, Ordering::SeqCst);
+        } else {
+            #[cfg(unix)]
+            {
+                std::fs::File::open(self.dir())?.sync_all()?;
+                if old_dir != self.dir() {
+                    std::fs::File::open(&old_dir)?.sync_all()?;
+                }
+            }
+            #[cfg(not(unix))]
+            let _ = old_dir;
+        }
+        Ok(())
+    }
+
+    /// The directory the file is in.
+    fn dir(&self) -> &Path {
+        super::dir_of(&self.path)
+    }
+
+    /// Atomically replaces the file with what `write` writes to a temporary
+    /// file next to it, returning the size of the persisted file.
+    ///
+    /// In NFS safe mode the temporary file is written with `O_SYNC`, and the
+    /// directory is synced after the rename. In eventual durability mode the
+    /// file is not synced, only marked for the next sync of the background
+    /// task. The `.lock` file is locked from the start until after the
+    /// rename.
+    fn persist_with<F, E>(&self, write: F) -> Result<u64, E>
+    where
+        F: FnOnce(&mut std::fs::File) -> Result<(), E>,
+        E: From<error::BackendError>,
+    {
+        let io = |e: io::Error| E::from(e.into());
+        let _lock = lock_exclusive(&self.path).map_err(io)?;
+        let mut tempf = NamedTempFile::new_in(self.dir()).map_err(io)?;
+        if let Ok(metadata) = std::fs::metadata(self.path.as_path()) {
+            tempf
+                .as_file()
+                .set_permissions(metadata.permissions())
+                .map_err(io)?;
+        }
+        if self.nfs_safe {
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.custom_flags(libc::O_SYNC);
+            }
+            let mut file = options.open(tempf.path()).map_err(io)?;
+            write(&mut file)?;
+            file.sync_all().map_err(io)?;
+        } else {
+            write(tempf.as_file_mut())?;
+            if self.barrier.is_none() {
+                tempf.as_file().sync_all().map_err(io)?;
+            }
+        }
+        let written = tempf.as_file().metadata().map_err(io)?.len();
+        tempf
+            .persist(self.path.as_path())
+            .map_err(|e| E::from(e.into()))?;
+        if let Some(barrier) = &self.barrier {
+            barrier.state.dirty.store(true, Ordering::SeqCst);
+        }
+        #[cfg(unix)]
+        {
+            if self.nfs_safe {
+                std::fs::File::open(self.dir())
+                    .and_then(|dir| dir.sync_all())
+                    .map_err(io)?;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Write the data produced by `write` with an atomic save, like
+    /// [`PathBackend::put_data`], without holding all of it in memory.
+    ///
+    /// `write` gets a writer to the temporary file which buffers at most
+    /// `buffer_size` bytes. Returns the size of the file that was persisted.
+    /// In NFS safe mode only the size of the file is checked after saving,
+    /// not its contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `write`, or a [`error::BackendError`] if the
+    /// file could not be written.
+    pub fn put_data_with<F, E>(&mut self, buffer_size: usize, write: F) -> Result<usize, E>
+    where
+        F: FnOnce(&mut dyn io::Write) -> Result<(), E>,
+        E: From<error::BackendError>,
+    {
+        use std::convert::TryFrom;
+
+        let written =
+            self.persist_with(|file| write_buffered(file, buffer_size, write).map(drop))?;
+        if self.nfs_safe {
+            let len = std::fs::metadata(self.path.as_path())
+                .map_err(|e| E::from(e.into()))?
+                .len();
+            if len != written {
+                return Err(E::from(error::BackendError::Internal(format!(
+                    "{} is {} bytes long after saving {} bytes",
+                    self.path.display(),
+                    len,
+                    written
+                ))));
+            }
+        }
+        usize::try_from(written).map_err(|e| E::from(error::BackendError::Internal(e.to_string())))
+    }
+}
+
+/// Runs `write` on a writer to `sink` buffering at most `buffer_size` bytes,
+/// and flushes it.
+fn write_buffered<W, F, E>(sink: W, buffer_size: usize, write: F) -> Result<W, E>
+where
+    W: io::Write,
+    F: FnOnce(&mut dyn io::Write) -> Result<(), E>,
+    E: From<error::BackendError>,
+{
+    let mut writer = io::BufWriter::with_capacity(buffer_size, sink);
+    write(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| E::from(e.into_error().into()))
 }
 
 impl Backend for PathBackend {
     async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .open(self.path.as_path())
-            .await?;
-        let mut buffer = vec![];
-        file.read_to_end(&mut buffer).await?;
-        Ok(buffer)
+        let retries = if self.nfs_safe { STALE_RETRIES } else { 0 };
+        let path = self.path.as_path();
+        Ok(retry_stale(retries, || read_file(path)).await?)
+    }
+
+    /// Copies the file to `writer` as it is read. With
+    /// [`PathBackend::nfs_safe`], only the opening of the file is retried on
+    /// a stale file handle, since the bytes already copied can't be taken
+    /// back.
+    async fn get_data_into(
+        &mut self,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> error::BackendResult<u64> {
+        let retries = if self.nfs_safe { STALE_RETRIES } else { 0 };
+        let path = self.path.as_path();
+        let mut file = retry_stale(retries, || File::open(path)).await?;
+        let copied = tokio::io::copy(&mut file, writer).await?;
+        writer.flush().await?;
+        Ok(copied)
     }
 
     /// Write the byte slice to the backend. This uses and atomic save.
     ///
     /// This won't corrupt the existing database file if the program panics
-    /// during the save.
+    /// during the save. The permissions of the existing file are kept.
     async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        self.put_data_counted(data).await.map(|_| ())
+    }
+
+    /// Like [`PathBackend::put_data`], returning the size of the file that
+    /// was persisted.
+    async fn put_data_counted(&mut self, data: &[u8]) -> error::BackendResult<usize> {
+        self.put_chunked(data, &mut |_, _| {}).await
+    }
+
+    /// Like [`PathBackend::put_data`], reporting the progress after every
+    /// chunk of [`PROGRESS_CHUNK`] bytes written to the temporary file.
+    async fn put_data_with_progress(
+        &mut self,
+        data: &[u8],
+        progress: &mut (dyn FnMut(u64, u64) + Send),
+    ) -> error::BackendResult<()> {
+        self.put_chunked(data, progress).await.map(drop)
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::ATOMIC | BackendCapabilities::LOCKING
+    }
+}
+
+/// How many bytes [`PathBackend`] writes between two progress reports.
+const PROGRESS_CHUNK: usize = 64 * 1024;
+
+impl PathBackend {
+    /// Atomically replace the file with `data`, written in chunks with
+    /// `progress` called after each, returning the size of the file.
+    async fn put_chunked(
+        &mut self,
+        data: &[u8],
+        progress: &mut (dyn FnMut(u64, u64) + Send),
+    ) -> error::BackendResult<usize> {
+        use std::convert::TryFrom;
         use std::io::Write;
 
-        #[allow(clippy::or_fun_call)] // `Path::new` is a zero cost conversion
-        let mut tempf = NamedTempFile::new_in(self.path.parent().unwrap_or(Path::new(".")))?;
-        tempf.write_all(data)?;
-        tempf.as_file().sync_all()?;
-        tempf.persist(self.path.as_path())?;
-        Ok(())
+        let total = data.len() as u64;
+        let written = self.persist_with(|file| {
+            let mut done = 0;
+            for chunk in data.chunks(PROGRESS_CHUNK) {
+                file.write_all(chunk)?;
+                done += chunk.len() as u64;
+                progress(done, total);
+            }
+            if data.is_empty() {
+                progress(0, 0);
+            }
+            Ok::<_, error::BackendError>(())
+        })?;
+        if self.nfs_safe {
+            let path = self.path.as_path();
+            if retry_stale(STALE_RETRIES, || read_file(path)).await? != data {
+                return Err(error::BackendError::Internal(format!(
+                    "the contents of {} don't match what was just saved",
+                    path.display()
+                )));
+            }
+        }
+        usize::try_from(written).map_err(|e| error::BackendError::Internal(e.to_string()))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Backend, PathBackend};
+    use super::{link_and_remove, rename_error, rename_noreplace, Backend, PathBackend};
+    #[cfg(unix)]
+    use super::{read_file, retry_stale, STALE_RETRIES};
+    use crate::error::BackendError;
+    use std::io;
+    use std::path::PathBuf;
     use tempfile::NamedTempFile;
     use tokio::io::AsyncWriteExt;
 
+    #[tokio::test(flavor = "multi_thread")]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_saves_wait_for_lock() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("db");
+        let (mut backend, _) = PathBackend::from_path_or_create(path.clone())
+            .await
+            .expect("could not create backend");
+        backend
+            .put_data(b"first")
+            .await
+            .expect("could not put data");
+
+        // Another process in the middle of a save.
+        let lock = std::fs::File::open(dir.path().join("db.lock")).expect("no lock file");
+        lock.lock().expect("could not lock");
+        let save = tokio::spawn(async move { backend.put_data(b"second").await.map(|()| backend) });
+        // The save blocks the worker it runs on, so this doesn't yield to it.
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!save.is_finished());
+        assert_eq!(
+            b"first".to_vec(),
+            std::fs::read(&path).expect("could not read")
+        );
+
+        lock.unlock().expect("could not unlock");
+        let mut backend = save
+            .await
+            .expect("the save panicked")
+            .expect("could not put data");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            b"second"
+        );
+        assert!(lock.try_lock().is_ok());
+    }
+
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn test_path_backend_existing() {
@@ -108,6 +891,15 @@ mod tests {
 
         backend.put_data(&data).await.expect("could not put data");
         assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        let mut streamed = Vec::new();
+        assert_eq!(
+            6,
+            backend
+                .get_data_into(&mut streamed)
+                .await
+                .expect("could not get data")
+        );
+        assert_eq!(streamed, data);
     }
 
     #[tokio::test]
@@ -116,8 +908,9 @@ mod tests {
         let dir = tempfile::tempdir().expect("could not create temporary directory");
         let mut file_path = dir.path().to_owned();
         file_path.push("rustbreak_path_db.db");
-        let (mut backend, existed) =
-            PathBackend::from_path_or_create(file_path).await.expect("could not create backend");
+        let (mut backend, existed) = PathBackend::from_path_or_create(file_path)
+            .await
+            .expect("could not create backend");
         assert!(!existed);
         let data = [4, 5, 1, 6, 8, 1];
 
@@ -126,12 +919,69 @@ mod tests {
         dir.close().expect("Error while deleting temp directory!");
     }
 
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_manage_gitignore() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let gitignore = dir.path().join(".gitignore");
+        let file_path = dir.path().join("my db[1].ron");
+
+        // Without a .gitignore, none is created.
+        PathBackend::from_path_or_create(file_path.clone())
+            .await
+            .expect("could not create backend")
+            .0
+            .manage_gitignore()
+            .await
+            .expect("could not manage .gitignore");
+        assert!(!gitignore.exists());
+
+        // The last line has no newline yet, and one pattern is already there.
+        std::fs::write(&gitignore, "target/\n/.tmp??????").expect("could not write .gitignore");
+        for _ in 0..3 {
+            PathBackend::from_path_or_create(file_path.clone())
+                .await
+                .expect("could not create backend")
+                .0
+                .manage_gitignore()
+                .await
+                .expect("could not manage .gitignore");
+        }
+        assert_eq!(
+            "target/\n/.tmp??????\n\
+             # Files of the dropbreak database\n\
+             /my\\ db\\[1\\].ron.lock\n\
+             /my\\ db\\[1\\].ron.bak\n\
+             /my\\ db\\[1\\].ron.wal\n",
+            std::fs::read_to_string(&gitignore).expect("could not read .gitignore")
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_put_data_counted() {
+        let file = NamedTempFile::new().expect("could not create temporary file");
+        let mut backend = PathBackend::from_path_or_fail(file.path().to_owned())
+            .await
+            .expect("could not create backend");
+        let data = [4, 5, 1, 6, 8, 1];
+
+        let written = backend
+            .put_data_counted(&data)
+            .await
+            .expect("could not put data");
+        assert_eq!(data.len(), written);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+    }
+
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn test_path_backend_nofail() {
         let file = NamedTempFile::new().expect("could not create temporary file");
         let file_path = file.path().to_owned();
-        let mut backend = PathBackend::from_path_or_fail(file_path).await.expect("should not fail");
+        let mut backend = PathBackend::from_path_or_fail(file_path)
+            .await
+            .expect("should not fail");
         let data = [4, 5, 1, 6, 8, 1];
 
         backend.put_data(&data).await.expect("could not put data");
@@ -144,13 +994,47 @@ mod tests {
         let dir = tempfile::tempdir().expect("could not create temporary directory");
         let mut file_path = dir.path().to_owned();
         file_path.push("rustbreak_path_db.db");
-        let err =
-            PathBackend::from_path_or_fail(file_path).await.expect_err("should fail with file not found");
+        let err = PathBackend::from_path_or_fail(file_path)
+            .await
+            .expect_err("should fail with file not found");
         if let crate::error::BackendError::Io(io_err) = &err {
             assert_eq!(std::io::ErrorKind::NotFound, io_err.kind());
         } else {
             panic!("Wrong kind of error returned: {}", err);
+        }
+        dir.close().expect("Error while deleting temp directory!");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_open_options_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut file_path = dir.path().to_owned();
+        file_path.push("rustbreak_path_db.db");
+        let (mut backend, existed) =
+            PathBackend::from_path_with_open_options(file_path.clone(), |options| {
+                options.mode(0o640);
+            })
+            .await
+            .expect("could not create backend");
+        assert!(!existed);
+        let mode = |path: &std::path::Path| {
+            std::fs::metadata(path)
+                .expect("could not read metadata")
+                .permissions()
+                .mode()
+                & 0o777
         };
+        assert_eq!(0o640, mode(&file_path));
+
+        // Saving replaces the file, but has to keep the mode it was created with.
+        let data = [4, 5, 1, 6, 8, 1];
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(0o640, mode(&file_path));
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
         dir.close().expect("Error while deleting temp directory!");
     }
 
@@ -159,16 +1043,59 @@ mod tests {
     #[cfg_attr(miri, ignore)]
     async fn test_path_backend_create_and_existing_nocall() {
         let file = NamedTempFile::new().expect("could not create temporary file");
-        let mut backend = PathBackend::from_path_or_create_and(file.path().to_owned(), async |_| {
-            panic!("Closure called but file already existed");
-        })
-        .await.expect("could not create backend");
+        let mut backend =
+            PathBackend::from_path_or_create_and(file.path().to_owned(), async |_| {
+                panic!("Closure called but file already existed");
+            })
+            .await
+            .expect("could not create backend");
         let data = [4, 5, 1, 6, 8, 1];
 
         backend.put_data(&data).await.expect("could not put data");
         assert_eq!(backend.get_data().await.expect("could not get data"), data);
     }
 
+    // A panic in the closure must not leave a half-written file behind.
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_create_and_panic() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let file_path = dir.path().join("rustbreak_path_db.db");
+
+        let path = file_path.clone();
+        let result = tokio::spawn(async move {
+            PathBackend::from_path_or_create_and(path, async |f| {
+                f.write_all(b"half of the ")
+                    .await
+                    .expect("could not write to file");
+                panic!("initialisation failed");
+            })
+            .await
+        })
+        .await;
+        assert!(result.expect_err("closure did not panic").is_panic());
+        assert!(!file_path.exists());
+        assert_eq!(
+            0,
+            std::fs::read_dir(dir.path())
+                .expect("could not read directory")
+                .count()
+        );
+
+        // The next attempt starts over.
+        let mut backend = PathBackend::from_path_or_create_and(file_path, async |f| {
+            f.write_all(b"a whole file")
+                .await
+                .expect("could not write to file");
+        })
+        .await
+        .expect("could not create backend");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            b"a whole file"
+        );
+    }
+
     // If the file does not yet exist, the closure should be called.
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
@@ -178,9 +1105,11 @@ mod tests {
         file_path.push("rustbreak_path_db.db");
         let mut backend = PathBackend::from_path_or_create_and(file_path, async |f| {
             f.write_all(b"this is a new file")
-                .await.expect("could not write to file")
+                .await
+                .expect("could not write to file");
         })
-        .await.expect("could not create backend");
+        .await
+        .expect("could not create backend");
         assert_eq!(
             backend.get_data().await.expect("could not get data"),
             b"this is a new file"
@@ -191,4 +1120,483 @@ mod tests {
         assert_eq!(backend.get_data().await.expect("could not get data"), data);
         dir.close().expect("Error while deleting temp directory!");
     }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_nfs_safe() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut file_path = dir.path().to_owned();
+        file_path.push("rustbreak_path_db.db");
+        let (backend, _) = PathBackend::from_path_or_create(file_path)
+            .await
+            .expect("could not create backend");
+        let mut backend = backend.nfs_safe(true);
+        let data = [4, 5, 1, 6, 8, 1];
+
+        let written = backend
+            .put_data_counted(&data)
+            .await
+            .expect("could not put data");
+        assert_eq!(data.len(), written);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        dir.close().expect("Error while deleting temp directory!");
+    }
+
+    // A read failing with `ESTALE` reopens the file and tries again.
+    #[cfg(unix)]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_retry_stale_reopens() {
+        use std::cell::Cell;
+
+        let mut file = NamedTempFile::new().expect("could not create temporary file");
+        std::io::Write::write_all(&mut file, b"fresh").expect("could not write to file");
+        let path = file.path();
+        let attempts = Cell::new(0);
+        let read = || {
+            attempts.set(attempts.get() + 1);
+            let stale = attempts.get() == 1;
+            async move {
+                let data = read_file(path).await?;
+                if stale {
+                    Err(std::io::Error::from_raw_os_error(libc::ESTALE))
+                } else {
+                    Ok(data)
+                }
+            }
+        };
+
+        let data = retry_stale(STALE_RETRIES, read)
+            .await
+            .expect("could not read after retrying");
+        assert_eq!(b"fresh", &data[..]);
+        assert_eq!(2, attempts.get());
+
+        // Without retries the error is returned.
+        attempts.set(0);
+        let err = retry_stale(0, read).await.expect_err("retried anyway");
+        assert_eq!(Some(libc::ESTALE), err.raw_os_error());
+        assert_eq!(1, attempts.get());
+    }
+
+    /// Writes `data` to the pipe at `path` one to three bytes at a time, with
+    /// a pause in between, so that every read only gets a fragment.
+    #[cfg(unix)]
+    fn trickle_into_pipe(path: PathBuf, data: Vec<u8>) -> std::thread::JoinHandle<()> {
+        use std::io::Write;
+
+        std::thread::spawn(move || {
+            let mut pipe = std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .expect("could not open pipe");
+            let mut rest = &data[..];
+            let mut len = 0;
+            while !rest.is_empty() {
+                len = len % 3 + 1;
+                let (chunk, tail) = rest.split_at(len.min(rest.len()));
+                pipe.write_all(chunk).expect("could not write to pipe");
+                rest = tail;
+                std::thread::sleep(std::time::Duration::from_micros(50));
+            }
+        })
+    }
+
+    // A pipe hands out what was written so far, the reads have to assemble
+    // the fragments until the writer closes it.
+    #[cfg(unix)]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_reads_pipe_in_fragments() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("pipe");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .expect("could not run mkfifo");
+        assert!(status.success(), "could not create pipe");
+        // Opening a pipe for reading waits for a writer, so the backend is
+        // built without checking the file.
+        let mut backend = PathBackend {
+            path: path.clone(),
+            nfs_safe: false,
+            barrier: None,
+        };
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+
+        let writer = trickle_into_pipe(path.clone(), data.clone());
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        writer.join().expect("writer panicked");
+
+        let writer = trickle_into_pipe(path, data.clone());
+        let mut streamed = Vec::new();
+        let read = backend
+            .get_data_into(&mut streamed)
+            .await
+            .expect("could not get data");
+        writer.join().expect("writer panicked");
+        assert_eq!(data.len() as u64, read);
+        assert_eq!(streamed, data);
+    }
+
+    // Other errors are not retried, and retries are bounded.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_retry_stale_bounded() {
+        use std::cell::Cell;
+
+        let attempts = Cell::new(0);
+        let err = retry_stale(STALE_RETRIES, || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>(std::io::Error::from_raw_os_error(libc::ESTALE)) }
+        })
+        .await
+        .expect_err("stale forever");
+        assert_eq!(Some(libc::ESTALE), err.raw_os_error());
+        assert_eq!(STALE_RETRIES + 1, attempts.get());
+
+        attempts.set(0);
+        let err = retry_stale(STALE_RETRIES, || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound)) }
+        })
+        .await
+        .expect_err("not found");
+        assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+        assert_eq!(1, attempts.get());
+    }
+
+    /// Remembers the largest write it received.
+    #[cfg(feature = "ron_enc")]
+    #[derive(Debug, Default)]
+    struct ChunkCounter {
+        total: usize,
+        largest: usize,
+    }
+
+    #[cfg(feature = "ron_enc")]
+    impl std::io::Write for ChunkCounter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.total += buf.len();
+            self.largest = self.largest.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Serializing through the buffered writer never hands more than the
+    // buffer size to the sink at once, so the whole value is never held in
+    // memory.
+    #[cfg(feature = "ron_enc")]
+    #[test]
+    fn test_write_buffered_bounded() {
+        use super::write_buffered;
+        use crate::deser::{DeSerializer, Ron};
+
+        let data: Vec<u64> = (0..100_000).collect();
+        let expected = Ron.serialize(&data).expect("could not serialize").len();
+        let buffer_size = 4096;
+
+        let sink = write_buffered(ChunkCounter::default(), buffer_size, |w| {
+            Ron.serialize_into(&data, w)
+                .map_err(|e| crate::error::BackendError::Internal(e.to_string()))
+        })
+        .expect("could not serialize");
+        assert_eq!(expected, sink.total);
+        assert!(expected > 100 * buffer_size);
+        assert!(
+            sink.largest <= buffer_size,
+            "wrote {} bytes at once",
+            sink.largest
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_put_data_with() {
+        let file = NamedTempFile::new().expect("could not create temporary file");
+        let mut backend = PathBackend::from_path_or_fail(file.path().to_owned())
+            .await
+            .expect("could not create backend");
+        let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+
+        let written = backend
+            .put_data_with(64, |w| {
+                for chunk in data.chunks(100) {
+                    w.write_all(chunk)?;
+                }
+                Ok::<_, crate::error::BackendError>(())
+            })
+            .expect("could not put data");
+        assert_eq!(data.len(), written);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+    }
+
+    // Saves are visible right away, and synced once per period if there were
+    // any.
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_eventual_durability() {
+        use std::sync::atomic::Ordering;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let file_path = dir.path().join("rustbreak_path_db.db");
+        let (backend, _) = PathBackend::from_path_or_create(file_path)
+            .await
+            .expect("could not create backend");
+        let mut backend = backend.eventual_durability(Duration::from_millis(100));
+        let state = std::sync::Arc::clone(&backend.barrier.as_ref().expect("no barrier").state);
+        let synced = || state.synced.load(Ordering::SeqCst);
+
+        let data = [4, 5, 1, 6, 8, 1];
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(0, synced());
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(1, synced());
+
+        // Nothing was saved, so nothing is synced.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(1, synced());
+
+        let data2 = [3, 99, 127, 6];
+        backend.put_data(&data2).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data2);
+        assert_eq!(1, synced());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(2, synced());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_put_data_with_progress() {
+        let file = NamedTempFile::new().expect("could not create temporary file");
+        let mut backend = PathBackend::from_path_or_fail(file.path().to_owned())
+            .await
+            .expect("could not create backend");
+        let data: Vec<u8> = (0..=255).cycle().take(200_000).collect();
+
+        let mut reports = Vec::new();
+        backend
+            .put_data_with_progress(&data, &mut |written, total| {
+                reports.push((written, total));
+            })
+            .await
+            .expect("could not put data");
+        assert!(reports.len() > 1, "only reported {:?}", reports);
+        assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(reports.iter().all(|&(_, total)| total == 200_000));
+        assert_eq!(Some(&(200_000, 200_000)), reports.last());
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+    }
+
+    // Only resolves the directory, so that the tests don't write to the home
+    // directory.
+    #[test]
+    #[cfg(feature = "directories")]
+    #[cfg_attr(miri, ignore)]
+    fn test_app_data_dir_under_platform_base() {
+        let base = directories::BaseDirs::new().expect("no home directory");
+        let dir = super::app_data_dir("Dropbreak Test").expect("no data directory");
+        assert!(
+            dir.starts_with(base.data_dir()),
+            "{} is not below {}",
+            dir.display(),
+            base.data_dir().display()
+        );
+        #[cfg(target_os = "linux")]
+        assert_eq!(base.data_dir().join("dropbreaktest"), dir);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_rename_to() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let old_path = dir.path().join("old.db");
+        let new_dir = dir.path().join("moved");
+        std::fs::create_dir(&new_dir).expect("could not create directory");
+        let new_path = new_dir.join("new.db");
+        let (mut backend, _) = PathBackend::from_path_or_create(old_path.clone())
+            .await
+            .expect("could not create backend");
+        backend
+            .put_data(&[1, 2, 3])
+            .await
+            .expect("could not put data");
+        std::fs::write(dir.path().join("old.db.bak"), [1]).expect("could not write backup");
+
+        backend
+            .rename_to(new_path.clone())
+            .await
+            .expect("could not rename");
+        assert!(!old_path.exists());
+        assert!(!dir.path().join("old.db.bak").exists());
+        assert_eq!(
+            vec![1],
+            std::fs::read(new_dir.join("new.db.bak")).expect("could not read backup")
+        );
+        assert!(!dir.path().join("old.db.lock").exists());
+        assert!(new_dir.join("new.db.lock").exists());
+        assert_eq!(
+            vec![1, 2, 3],
+            std::fs::read(&new_path).expect("could not read file")
+        );
+
+        backend.put_data(&[4, 5]).await.expect("could not put data");
+        assert!(!old_path.exists());
+        assert_eq!(
+            vec![4, 5],
+            std::fs::read(&new_path).expect("could not read file")
+        );
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [4, 5]
+        );
+
+        // A failed move leaves everything in place.
+        let missing = dir.path().join("missing").join("db");
+        assert!(backend.rename_to(missing).await.is_err());
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [4, 5]
+        );
+        assert!(new_dir.join("new.db.bak").exists());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_rename_to_refuses_to_replace() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let old_path = dir.path().join("old.db");
+        let (mut backend, _) = PathBackend::from_path_or_create(old_path.clone())
+            .await
+            .expect("could not create backend");
+        backend
+            .put_data(&[1, 2, 3])
+            .await
+            .expect("could not put data");
+
+        let taken = dir.path().join("taken.db");
+        std::fs::write(&taken, [9]).expect("could not write file");
+        let sidecar_taken = dir.path().join("other.db");
+        std::fs::write(dir.path().join("other.db.wal"), [8]).expect("could not write file");
+        for new_path in [taken.clone(), sidecar_taken.clone()] {
+            match backend.rename_to(new_path).await {
+                Err(BackendError::Io(e)) => assert_eq!(io::ErrorKind::AlreadyExists, e.kind()),
+                res => panic!("expected AlreadyExists, got {:?}", res),
+            }
+        }
+        assert_eq!(vec![9], std::fs::read(&taken).expect("could not read file"));
+        assert!(!sidecar_taken.exists());
+        assert_eq!(
+            vec![8],
+            std::fs::read(dir.path().join("other.db.wal")).expect("could not read file")
+        );
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [1, 2, 3]
+        );
+
+        backend
+            .rename_to(old_path.clone())
+            .await
+            .expect("could not rename to the same path");
+        assert!(old_path.exists());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_rename_noreplace() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        for rename in [rename_noreplace, link_and_remove] {
+            std::fs::write(&from, [1]).expect("could not write file");
+            std::fs::write(&to, [2]).expect("could not write file");
+            let err = rename(&from, &to).expect_err("replaced the file");
+            assert_eq!(io::ErrorKind::AlreadyExists, err.kind());
+            assert_eq!(vec![1], std::fs::read(&from).expect("could not read file"));
+            assert_eq!(vec![2], std::fs::read(&to).expect("could not read file"));
+
+            std::fs::remove_file(&to).expect("could not remove file");
+            rename(&from, &to).expect("could not rename");
+            assert!(!from.exists());
+            assert_eq!(vec![1], std::fs::read(&to).expect("could not read file"));
+            std::fs::remove_file(&to).expect("could not remove file");
+        }
+    }
+
+    #[test]
+    fn test_rename_error_cross_device() {
+        let from = PathBuf::from("/a/db");
+        let to = PathBuf::from("/b/db");
+        match rename_error(
+            io::ErrorKind::CrossesDevices.into(),
+            from.clone(),
+            to.clone(),
+        ) {
+            BackendError::CrossDevice {
+                from: failed_from,
+                to: failed_to,
+            } => assert_eq!((from, to), (failed_from, failed_to)),
+            e => panic!("expected CrossDevice, got {:?}", e),
+        }
+        assert!(matches!(
+            rename_error(
+                io::ErrorKind::NotFound.into(),
+                PathBuf::new(),
+                PathBuf::new()
+            ),
+            BackendError::Io(_)
+        ));
+    }
+
+    /// Moves a database from the temporary directory to `/dev/shm`, if they
+    /// are on different file systems.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_path_backend_rename_across_devices() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let Ok(other) = tempfile::tempdir_in("/dev/shm") else {
+            return;
+        };
+        let device = |path: &std::path::Path| std::fs::metadata(path).map(|m| m.dev()).ok();
+        if device(dir.path()) == device(other.path()) {
+            return;
+        }
+        let old_path = dir.path().join("old.db");
+        let (mut backend, _) = PathBackend::from_path_or_create(old_path.clone())
+            .await
+            .expect("could not create backend");
+        backend
+            .put_data(&[1, 2, 3])
+            .await
+            .expect("could not put data");
+
+        let new_path = other.path().join("new.db");
+        match backend.rename_to(new_path.clone()).await {
+            Err(BackendError::CrossDevice { from, to }) => {
+                assert_eq!((old_path.clone(), new_path.clone()), (from, to));
+            }
+            res => panic!("expected CrossDevice, got {:?}", res),
+        }
+        assert!(!new_path.exists());
+        assert_eq!(
+            vec![1, 2, 3],
+            std::fs::read(&old_path).expect("could not read file")
+        );
+        backend.put_data(&[4]).await.expect("could not put data");
+        assert_eq!(
+            vec![4],
+            std::fs::read(&old_path).expect("could not read file")
+        );
+    }
 }
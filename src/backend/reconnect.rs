@@ -0,0 +1,244 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`ReconnectingBackend`], rebuilding a network
+//! backend that lost its connection.
+
+use super::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError};
+use std::future::Future;
+use std::io;
+
+/// A [`Backend`] rebuilding a network backend, such as a
+/// [`StreamBackend`](super::StreamBackend) or a `GrpcBackend`, when an
+/// operation fails because its connection is gone.
+///
+/// When a read or write fails with a connection error, the backend is
+/// replaced by a new one from the `connect` closure and the operation is
+/// tried once more. If connecting fails, that error is returned. Any other
+/// error, like a missing key, is returned right away.
+///
+/// Connection errors are I/O errors of the kinds
+/// [`ConnectionRefused`](io::ErrorKind::ConnectionRefused),
+/// [`ConnectionReset`](io::ErrorKind::ConnectionReset),
+/// [`ConnectionAborted`](io::ErrorKind::ConnectionAborted),
+/// [`NotConnected`](io::ErrorKind::NotConnected),
+/// [`BrokenPipe`](io::ErrorKind::BrokenPipe) and
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof), and with the `grpc`
+/// feature transport errors and the `Unavailable` status.
+///
+/// A write that failed may still have reached the other side, so it is
+/// repeated as a whole. This is fine for backends replacing all of the data
+/// on every write, which is what [`Backend`] asks of them.
+#[derive(Debug)]
+pub struct ReconnectingBackend<B, C> {
+    inner: B,
+    connect: C,
+}
+
+impl<B, C, Fut> ReconnectingBackend<B, C>
+where
+    C: FnMut() -> Fut,
+    Fut: Future<Output = error::BackendResult<B>>,
+{
+    /// Use `inner`, replacing it with the result of `connect` when its
+    /// connection is lost.
+    pub fn new(inner: B, connect: C) -> Self {
+        Self { inner, connect }
+    }
+
+    /// Connect with `connect` and use the new backend.
+    pub async fn connect(mut connect: C) -> error::BackendResult<Self> {
+        let inner = connect().await?;
+        Ok(Self { inner, connect })
+    }
+
+    /// Return the current inner backend.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+/// Whether `err` means that the connection of the backend is gone, rather
+/// than that the operation itself failed.
+fn is_connection_error(err: &BackendError) -> bool {
+    match err {
+        BackendError::Io(e) => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+        ),
+        #[cfg(feature = "grpc")]
+        BackendError::GrpcTransport(_) => true,
+        #[cfg(feature = "grpc")]
+        BackendError::Grpc(status) => status.code() == tonic::Code::Unavailable,
+        _ => false,
+    }
+}
+
+impl<B, C, Fut> Backend for ReconnectingBackend<B, C>
+where
+    B: Backend + Send,
+    C: FnMut() -> Fut + Send,
+    Fut: Future<Output = error::BackendResult<B>> + Send,
+{
+    async fn init(&mut self) -> error::BackendResult<()> {
+        match self.inner.init().await {
+            Err(e) if is_connection_error(&e) => {
+                self.inner = (self.connect)().await?;
+                self.inner.init().await
+            }
+            res => res,
+        }
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        match self.inner.get_data().await {
+            Err(e) if is_connection_error(&e) => {
+                self.inner = (self.connect)().await?;
+                self.inner.get_data().await
+            }
+            res => res,
+        }
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        match self.inner.put_data(data).await {
+            Err(e) if is_connection_error(&e) => {
+                self.inner = (self.connect)().await?;
+                self.inner.put_data(data).await
+            }
+            res => res,
+        }
+    }
+
+    /// Appending is not retried, so it is not supported.
+    fn capabilities(&self) -> BackendCapabilities {
+        self.inner
+            .capabilities()
+            .difference(BackendCapabilities::APPEND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReconnectingBackend;
+    use crate::backend::Backend;
+    use crate::error::{self, BackendError};
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// A client of a shared store, failing its next operations with `errors`.
+    struct MockClient {
+        store: Arc<Mutex<Vec<u8>>>,
+        errors: Vec<io::ErrorKind>,
+    }
+
+    impl MockClient {
+        fn check(&mut self) -> error::BackendResult<()> {
+            match self.errors.pop() {
+                Some(kind) => Err(io::Error::from(kind).into()),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Backend for MockClient {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.check()?;
+            let store = self.store.lock().expect("store poisoned");
+            Ok(store.clone())
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.check()?;
+            let mut store = self.store.lock().expect("store poisoned");
+            data.clone_into(&mut *store);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_once_on_connection_error() {
+        let store = Arc::new(Mutex::new(Vec::new()));
+        let connects = Arc::new(AtomicUsize::new(0));
+        let broken = MockClient {
+            store: Arc::clone(&store),
+            errors: vec![io::ErrorKind::ConnectionReset],
+        };
+        let (shared, counter) = (Arc::clone(&store), Arc::clone(&connects));
+        let mut backend = ReconnectingBackend::new(broken, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let store = Arc::clone(&shared);
+            async move {
+                Ok(MockClient {
+                    store,
+                    errors: Vec::new(),
+                })
+            }
+        });
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(1, connects.load(Ordering::SeqCst));
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(1, connects.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_logical_errors_are_not_retried() {
+        let store = Arc::new(Mutex::new(Vec::new()));
+        let connects = Arc::new(AtomicUsize::new(0));
+        let client = MockClient {
+            store: Arc::clone(&store),
+            errors: vec![io::ErrorKind::NotFound],
+        };
+        let counter = Arc::clone(&connects);
+        let mut backend = ReconnectingBackend::new(client, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let store = Arc::clone(&store);
+            async move {
+                Ok(MockClient {
+                    store,
+                    errors: Vec::new(),
+                })
+            }
+        });
+
+        match backend.get_data().await {
+            Err(BackendError::Io(e)) => assert_eq!(io::ErrorKind::NotFound, e.kind()),
+            res => panic!("expected NotFound, got {:?}", res),
+        }
+        assert_eq!(0, connects.load(Ordering::SeqCst));
+    }
+
+    // Only one retry: a connection that is still broken surfaces its error.
+    #[tokio::test]
+    async fn test_retries_only_once() {
+        let store = Arc::new(Mutex::new(Vec::new()));
+        let client = MockClient {
+            store: Arc::clone(&store),
+            errors: vec![io::ErrorKind::BrokenPipe],
+        };
+        let mut backend = ReconnectingBackend::new(client, move || {
+            let store = Arc::clone(&store);
+            async move {
+                Ok(MockClient {
+                    store,
+                    errors: vec![io::ErrorKind::ConnectionRefused],
+                })
+            }
+        });
+
+        match backend.put_data(b"data").await {
+            Err(BackendError::Io(e)) => assert_eq!(io::ErrorKind::ConnectionRefused, e.kind()),
+            res => panic!("expected ConnectionRefused, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`RegistryBackend`], storing data in a value
+//! of the Windows registry.
+
+use super::{Backend, BackendCapabilities};
+use crate::error;
+use std::io;
+use winreg::enums::{RegType, HKEY_CURRENT_USER};
+use winreg::{RegKey, RegValue};
+
+/// A [`Backend`] storing the data as a `REG_BINARY` value of a registry key.
+///
+/// Writing a single value is atomic, so a save either replaces the data
+/// completely or not at all. The registry is meant for small amounts of
+/// data: values over a few kilobytes should go into a file instead.
+///
+/// Registry errors are returned as [`error::BackendError::Io`]. Reading a
+/// value that doesn't exist fails with [`io::ErrorKind::NotFound`], reading
+/// a value of another type with [`io::ErrorKind::InvalidData`].
+///
+/// **Important**: This is only available on Windows with the `windows`
+/// feature
+#[derive(Debug)]
+pub struct RegistryBackend {
+    key: RegKey,
+    value: String,
+}
+
+impl RegistryBackend {
+    /// Use the value `value` of an already opened registry `key`.
+    ///
+    /// The key has to be opened with read and write access.
+    #[must_use]
+    pub fn from_key(key: RegKey, value: impl Into<String>) -> Self {
+        Self {
+            key,
+            value: value.into(),
+        }
+    }
+
+    /// Use the value `value` of the key at `path` under
+    /// `HKEY_CURRENT_USER`, creating the key if it doesn't exist yet.
+    ///
+    /// Returns the [`RegistryBackend`] and whether the value already existed.
+    pub fn current_user(
+        path: &str,
+        value: impl Into<String>,
+    ) -> error::BackendResult<(Self, bool)> {
+        let (key, _) = RegKey::predef(HKEY_CURRENT_USER).create_subkey(path)?;
+        let backend = Self::from_key(key, value);
+        let exists = match backend.key.get_raw_value(&backend.value) {
+            Ok(_) => true,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+        Ok((backend, exists))
+    }
+
+    /// The key the value is stored in.
+    #[must_use]
+    pub fn key(&self) -> &RegKey {
+        &self.key
+    }
+
+    /// The name of the value the data is stored in.
+    #[must_use]
+    pub fn value_name(&self) -> &str {
+        &self.value
+    }
+}
+
+impl Backend for RegistryBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let value = self.key.get_raw_value(&self.value)?;
+        match value.vtype {
+            RegType::REG_BINARY => Ok(value.bytes),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "registry value {} is {:?}, not REG_BINARY",
+                    self.value, other
+                ),
+            )
+            .into()),
+        }
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let value = RegValue {
+            bytes: data.to_vec(),
+            vtype: RegType::REG_BINARY,
+        };
+        self.key.set_raw_value(&self.value, &value)?;
+        Ok(())
+    }
+
+    /// A registry value is replaced as a whole.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::ATOMIC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, RegistryBackend};
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    const TEST_KEY: &str = r"Software\dropbreak\test";
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_registry_backend() {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        // Leftovers of an earlier run that failed halfway.
+        let _ = hkcu.delete_subkey_all(TEST_KEY);
+
+        let (mut backend, existed) =
+            RegistryBackend::current_user(TEST_KEY, "db").expect("could not open key");
+        assert!(!existed);
+        let err = backend
+            .get_data()
+            .await
+            .expect_err("value should not exist");
+        if let crate::error::BackendError::Io(io_err) = &err {
+            assert_eq!(std::io::ErrorKind::NotFound, io_err.kind());
+        } else {
+            panic!("Wrong kind of error returned: {}", err);
+        }
+
+        let data = [4, 5, 1, 6, 8, 1];
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        drop(backend);
+
+        let (mut backend, existed) =
+            RegistryBackend::current_user(TEST_KEY, "db").expect("could not open key");
+        assert!(existed);
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+
+        drop(backend);
+        hkcu.delete_subkey_all(TEST_KEY)
+            .expect("could not clean up the test key");
+    }
+}
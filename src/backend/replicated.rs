@@ -0,0 +1,263 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`ReplicatedBackend`], copying the data of a
+//! primary backend to replicas.
+
+use super::{data_hash, Backend, BackendCapabilities};
+use crate::error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// A [`Backend`] writing to a primary backend and copying the data to any
+/// number of replicas.
+///
+/// The primary is the source of truth: reads only go to it, and a write
+/// fails if it fails on the primary. Writes to the replicas are best effort,
+/// their errors are ignored, so a replica can fall behind.
+///
+/// Replicas are caught up by read repair. Every read of the primary starts a
+/// background task per replica, which compares the hash of the data of the
+/// replica with the data just read and writes that data to the replica if
+/// they differ. The read itself doesn't wait for these tasks, and a repair
+/// is dropped if a newer write reached the replica first. Errors of the
+/// repairs are ignored as well.
+///
+/// Repairs run on the tokio runtime, reads have to be made from within it.
+#[derive(Debug)]
+pub struct ReplicatedBackend<P, R> {
+    primary: P,
+    replicas: Vec<Arc<Mutex<R>>>,
+    /// Incremented by every write, so that repairs with older data can tell.
+    generation: Arc<AtomicU64>,
+    repairs: Vec<JoinHandle<()>>,
+    /// Whether replicas are compared by the canonical form of their data.
+    #[cfg(feature = "json_enc")]
+    canonical: bool,
+}
+
+impl<P, R> ReplicatedBackend<P, R>
+where
+    P: Backend + Send,
+    R: Backend + Send + 'static,
+{
+    /// Use `primary` as the source of truth and copy its data to `replicas`.
+    pub fn new(primary: P, replicas: Vec<R>) -> Self {
+        Self {
+            primary,
+            replicas: replicas
+                .into_iter()
+                .map(|replica| Arc::new(Mutex::new(replica)))
+                .collect(),
+            generation: Arc::new(AtomicU64::new(0)),
+            repairs: Vec::new(),
+            #[cfg(feature = "json_enc")]
+            canonical: false,
+        }
+    }
+
+    /// Compare the data of the replicas with the primary by its canonical
+    /// form, which has to be JSON, instead of byte by byte.
+    ///
+    /// A replica holding the same JSON as the primary, only formatted
+    /// differently, is then not rewritten by read repair. Data which isn't
+    /// JSON is still compared byte by byte.
+    ///
+    /// **Important**: This is only available with the `json_enc` feature
+    #[cfg(feature = "json_enc")]
+    #[must_use]
+    pub fn canonical_json(mut self, enabled: bool) -> Self {
+        self.canonical = enabled;
+        self
+    }
+
+    /// Whether replicas are compared by the canonical form of their data.
+    #[cfg_attr(not(feature = "json_enc"), allow(clippy::unused_self))]
+    fn canonical(&self) -> bool {
+        #[cfg(feature = "json_enc")]
+        {
+            self.canonical
+        }
+        #[cfg(not(feature = "json_enc"))]
+        {
+            false
+        }
+    }
+
+    /// Wait for the repairs started by earlier reads to finish.
+    pub async fn wait_for_repairs(&mut self) {
+        for repair in self.repairs.drain(..) {
+            // A repair that panicked has nothing left to do either.
+            let _ = repair.await;
+        }
+    }
+
+    /// Wait for the repairs, and return the primary and the replicas.
+    pub async fn into_parts(mut self) -> (P, Vec<R>) {
+        self.wait_for_repairs().await;
+        let replicas = self
+            .replicas
+            .into_iter()
+            .filter_map(|replica| Arc::try_unwrap(replica).ok())
+            .map(Mutex::into_inner)
+            .collect();
+        (self.primary, replicas)
+    }
+
+    /// Start a repair of every replica with `data`, read from the primary.
+    fn repair(&mut self, data: Vec<u8>) {
+        self.repairs.retain(|repair| !repair.is_finished());
+        let data = Arc::new(data);
+        let canonical = self.canonical();
+        let hash = data_hash(&data, canonical);
+        let generation = self.generation.load(Ordering::SeqCst);
+        for replica in &self.replicas {
+            let replica = Arc::clone(replica);
+            let latest = Arc::clone(&self.generation);
+            let data = Arc::clone(&data);
+            self.repairs.push(tokio::spawn(async move {
+                let mut replica = replica.lock().await;
+                if latest.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                let stale = match replica.get_data().await {
+                    Ok(current) => data_hash(&current, canonical) != hash,
+                    Err(_) => true,
+                };
+                if stale {
+                    let _ = replica.put_data(&data).await;
+                }
+            }));
+        }
+    }
+}
+
+impl<P, R> Backend for ReplicatedBackend<P, R>
+where
+    P: Backend + Send,
+    R: Backend + Send + 'static,
+{
+    async fn init(&mut self) -> error::BackendResult<()> {
+        self.primary.init().await?;
+        for replica in &self.replicas {
+            replica.lock().await.init().await?;
+        }
+        Ok(())
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let data = self.primary.get_data().await?;
+        if !self.replicas.is_empty() {
+            self.repair(data.clone());
+        }
+        Ok(data)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        self.primary.put_data(data).await?;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        for replica in &self.replicas {
+            let _ = replica.lock().await.put_data(data).await;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        self.primary
+            .capabilities()
+            .difference(BackendCapabilities::APPEND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplicatedBackend;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::error::{self, BackendError};
+
+    /// A replica whose writes can be made to fail.
+    #[derive(Debug, Default)]
+    struct Flaky {
+        inner: MemoryBackend,
+        fail: bool,
+    }
+
+    impl Backend for Flaky {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.inner.get_data().await
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            if self.fail {
+                return Err(BackendError::Internal("replica is down".to_owned()));
+            }
+            self.inner.put_data(data).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_reach_replicas() {
+        let mut backend = ReplicatedBackend::new(
+            MemoryBackend::new(),
+            vec![Flaky::default(), Flaky::default()],
+        );
+        backend.put_data(b"data").await.expect("could not put data");
+
+        let (mut primary, replicas) = backend.into_parts().await;
+        assert_eq!(b"data", &primary.get_data().await.expect("no data")[..]);
+        for mut replica in replicas {
+            assert_eq!(b"data", &replica.get_data().await.expect("no data")[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_repairs_stale_replica() {
+        let mut backend = ReplicatedBackend::new(
+            MemoryBackend::new(),
+            vec![Flaky::default(), Flaky::default()],
+        );
+        backend
+            .put_data(b"first")
+            .await
+            .expect("could not put data");
+        // The next write is lost on the second replica.
+        backend.replicas[1].lock().await.fail = true;
+        backend
+            .put_data(b"second")
+            .await
+            .expect("could not put data");
+        backend.replicas[1].lock().await.fail = false;
+        assert_eq!(
+            b"first",
+            &backend.replicas[1]
+                .lock()
+                .await
+                .get_data()
+                .await
+                .expect("no data")[..]
+        );
+
+        assert_eq!(b"second", &backend.get_data().await.expect("no data")[..]);
+        backend.wait_for_repairs().await;
+        let (_, replicas) = backend.into_parts().await;
+        for mut replica in replicas {
+            assert_eq!(b"second", &replica.get_data().await.expect("no data")[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repair_does_not_undo_newer_write() {
+        let mut backend = ReplicatedBackend::new(MemoryBackend::new(), vec![Flaky::default()]);
+        backend.put_data(b"old").await.expect("could not put data");
+        // On the single threaded test runtime, the repair only runs once the
+        // test waits for it, after the next write.
+        backend.get_data().await.expect("could not get data");
+        backend.put_data(b"new").await.expect("could not put data");
+
+        let (_, mut replicas) = backend.into_parts().await;
+        assert_eq!(b"new", &replicas[0].get_data().await.expect("no data")[..]);
+    }
+}
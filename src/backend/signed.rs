@@ -0,0 +1,272 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`SignedBackend`], storing an HMAC of the
+//! data next to it.
+
+use super::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::io;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A [`Backend`] signing the data written to another backend, and verifying
+/// it when it is read back.
+///
+/// Every write computes an HMAC-SHA256 of the data with a secret key and
+/// stores it in a second backend, the sidecar, usually a file next to the
+/// data file. Every read checks the data against the stored signature and
+/// fails with [`BackendError::SignatureInvalid`] if the signature is missing
+/// or doesn't match. This detects changes made by anyone who can write the
+/// data but doesn't know the key, including the same user through another
+/// program.
+///
+/// The data is written before its signature. If writing the signature fails
+/// the data can't be read anymore until it is written again, it is never
+/// accepted unverified.
+///
+/// The signature covers the bytes as they are stored. With the `json_enc`
+/// feature, [`SignedBackend::canonical_json`] signs the
+/// [canonical form](crate::deser::canonicalize) of JSON data instead, so
+/// that reformatting the file doesn't invalidate it.
+///
+/// **Important**: This is only available with the `signed` feature
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use dropbreak::backend::{PathBackend, SignedBackend};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let (data, _) = PathBackend::from_path_or_create("db.ron".into()).await?;
+/// let (signature, _) = PathBackend::from_path_or_create("db.ron.sig".into()).await?;
+/// let backend = SignedBackend::new(data, signature, b"a secret key");
+/// # Ok(())
+/// # }
+/// ```
+pub struct SignedBackend<B, S = B> {
+    inner: B,
+    signature: S,
+    key: Vec<u8>,
+    #[cfg(feature = "json_enc")]
+    canonical: bool,
+}
+
+// Manual so that the key isn't printed.
+impl<B: fmt::Debug, S: fmt::Debug> fmt::Debug for SignedBackend<B, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignedBackend")
+            .field("inner", &self.inner)
+            .field("signature", &self.signature)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B, S> SignedBackend<B, S> {
+    /// Store the data in `inner` and its signature with `key` in
+    /// `signature`.
+    pub fn new(inner: B, signature: S, key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            inner,
+            signature,
+            key: key.into(),
+            #[cfg(feature = "json_enc")]
+            canonical: false,
+        }
+    }
+
+    /// Sign the canonical form of the data, which has to be JSON, instead of
+    /// the data itself.
+    ///
+    /// The stored data is left as it is, only the signature is computed
+    /// from its canonical form. Signatures written without this option don't
+    /// match anymore, unless the data already was in canonical form.
+    ///
+    /// **Important**: This is only available with the `json_enc` feature
+    #[cfg(feature = "json_enc")]
+    #[must_use]
+    pub fn canonical_json(mut self, enabled: bool) -> Self {
+        self.canonical = enabled;
+        self
+    }
+
+    /// Return the backends of the data and of the signature.
+    pub fn into_inner(self) -> (B, S) {
+        (self.inner, self.signature)
+    }
+
+    #[cfg_attr(not(feature = "json_enc"), allow(clippy::unnecessary_wraps))]
+    fn mac(&self, data: &[u8]) -> error::BackendResult<HmacSha256> {
+        // HMAC takes keys of any length, this can't fail.
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.key)
+            .unwrap_or_else(|_| unreachable!("HMAC accepts keys of any length"));
+        #[cfg(feature = "json_enc")]
+        {
+            if self.canonical {
+                let canonical = crate::deser::canonicalize(data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                mac.update(&canonical);
+                return Ok(mac);
+            }
+        }
+        mac.update(data);
+        Ok(mac)
+    }
+}
+
+impl<B, S> Backend for SignedBackend<B, S>
+where
+    B: Backend + Send,
+    S: Backend + Send,
+{
+    async fn init(&mut self) -> error::BackendResult<()> {
+        self.inner.init().await?;
+        self.signature.init().await
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let data = self.inner.get_data().await?;
+        let signature = match self.signature.get_data().await {
+            Ok(signature) => signature,
+            Err(BackendError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(BackendError::SignatureInvalid)
+            }
+            Err(e) => return Err(e),
+        };
+        self.mac(&data)?
+            .verify_slice(&signature)
+            .map_err(|_| BackendError::SignatureInvalid)?;
+        Ok(data)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let signature = self.mac(data)?.finalize().into_bytes();
+        self.inner.put_data(data).await?;
+        self.signature.put_data(&signature).await
+    }
+
+    /// The signature has to be computed over all of the data, it can't be
+    /// appended to.
+    fn capabilities(&self) -> BackendCapabilities {
+        self.inner
+            .capabilities()
+            .difference(BackendCapabilities::APPEND)
+            | BackendCapabilities::AUTHENTICATED
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SignedBackend;
+    use crate::backend::{Backend, MemoryBackend, PathBackend};
+    use crate::error::BackendError;
+    use std::path::Path;
+
+    async fn open(dir: &Path, key: &[u8]) -> SignedBackend<PathBackend> {
+        let (data, _) = PathBackend::from_path_or_create(dir.join("db"))
+            .await
+            .expect("could not open data file");
+        let (signature, _) = PathBackend::from_path_or_create(dir.join("db.sig"))
+            .await
+            .expect("could not open signature file");
+        SignedBackend::new(data, signature, key)
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_signed_backend_verifies() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut backend = open(dir.path(), b"key").await;
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        assert_eq!(
+            32,
+            std::fs::read(dir.path().join("db.sig"))
+                .expect("could not read signature")
+                .len()
+        );
+
+        let mut other = open(dir.path(), b"key").await;
+        assert_eq!(other.get_data().await.expect("could not get data"), data);
+        let mut wrong_key = open(dir.path(), b"other key").await;
+        assert!(matches!(
+            wrong_key.get_data().await,
+            Err(BackendError::SignatureInvalid)
+        ));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_signed_backend_detects_tampering() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut backend = open(dir.path(), b"key").await;
+        backend
+            .put_data(&[4, 5, 1, 6, 8, 1])
+            .await
+            .expect("could not put data");
+
+        std::fs::write(dir.path().join("db"), [4, 5, 1, 6, 8, 2]).expect("could not tamper");
+        assert!(matches!(
+            backend.get_data().await,
+            Err(BackendError::SignatureInvalid)
+        ));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_signed_backend_missing_signature() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut backend = open(dir.path(), b"key").await;
+        backend
+            .put_data(&[4, 5, 1, 6, 8, 1])
+            .await
+            .expect("could not put data");
+
+        std::fs::remove_file(dir.path().join("db.sig")).expect("could not remove signature");
+        assert!(matches!(
+            backend.get_data().await,
+            Err(BackendError::SignatureInvalid)
+        ));
+
+        // An empty sidecar, as in a backend that was never written, is
+        // missing a signature as well.
+        let mut memory = SignedBackend::new(MemoryBackend::new(), MemoryBackend::new(), "key");
+        assert!(matches!(
+            memory.get_data().await,
+            Err(BackendError::SignatureInvalid)
+        ));
+    }
+
+    // Reformatting JSON data keeps a canonical signature valid.
+    #[cfg(feature = "json_enc")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_signed_backend_canonical_json() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut backend = open(dir.path(), b"key").await.canonical_json(true);
+        backend
+            .put_data(b"{\n  \"b\": 1,\n  \"a\": [2.50]\n}")
+            .await
+            .expect("could not put data");
+
+        let reformatted = b"{\"a\":[2.5],\"b\":1}";
+        std::fs::write(dir.path().join("db"), reformatted).expect("could not reformat");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            reformatted
+        );
+
+        std::fs::write(dir.path().join("db"), b"{\"a\":[2.5],\"b\":2}").expect("could not tamper");
+        assert!(matches!(
+            backend.get_data().await,
+            Err(BackendError::SignatureInvalid)
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`SledBackend`], storing data in an embedded
+//! sled database.
+
+use super::{Backend, BackendCapabilities};
+use crate::error;
+use ::sled::Db;
+use std::io;
+use std::path::Path;
+
+/// The key the data is stored under.
+const DATA_KEY: &[u8] = b"dropbreak";
+
+/// A [`Backend`] storing the data as one value of a
+/// [`sled`](https://docs.rs/sled) database.
+///
+/// The data is stored under a fixed key of the default tree, so the
+/// application can keep its own keys and trees in the same database. Every
+/// write inserts the value, which sled applies atomically, then flushes the
+/// database, so the data is durable once [`Backend::put_data`] returns. Sled
+/// recovers its log after a crash, a write that didn't return left either
+/// the earlier data or the new data.
+///
+/// Reading data that was never written fails with an I/O error of the kind
+/// [`NotFound`](io::ErrorKind::NotFound).
+///
+/// **Important**: This is only available with the `sled` feature
+#[derive(Debug, Clone)]
+pub struct SledBackend {
+    db: Db,
+}
+
+impl SledBackend {
+    /// Store the data in `db`.
+    #[must_use]
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+
+    /// Open the sled database at `path`, creating it if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be opened, like when another
+    /// process has it open.
+    pub fn open<P: AsRef<Path>>(path: P) -> error::BackendResult<Self> {
+        Ok(Self::new(::sled::open(path)?))
+    }
+
+    /// The sled database.
+    #[must_use]
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    /// Return the inner database.
+    #[must_use]
+    pub fn into_inner(self) -> Db {
+        self.db
+    }
+}
+
+impl Backend for SledBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        match self.db.get(DATA_KEY)? {
+            Some(data) => Ok(data.to_vec()),
+            None => {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no data in the sled database").into())
+            }
+        }
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        self.db.insert(DATA_KEY, data)?;
+        // The flush waits for the disk, keep it off the runtime's workers.
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || db.flush())
+            .await
+            .map_err(|e| error::BackendError::Internal(e.to_string()))??;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::ATOMIC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SledBackend;
+    use crate::backend::Backend;
+    use crate::error::BackendError;
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_sled_backend_persists() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("db.sled");
+        let mut backend = SledBackend::open(&path).expect("could not open database");
+        match backend.get_data().await {
+            Err(BackendError::Io(e)) => assert_eq!(std::io::ErrorKind::NotFound, e.kind()),
+            res => panic!("expected NotFound, got {:?}", res),
+        }
+
+        backend
+            .put_data(&[4, 5, 1, 6, 8, 1])
+            .await
+            .expect("could not put data");
+        backend
+            .put_data(&[4, 5, 1])
+            .await
+            .expect("could not put data");
+        backend
+            .db()
+            .insert("app", "own key")
+            .expect("could not insert");
+        drop(backend);
+
+        let mut backend = SledBackend::open(&path).expect("could not reopen database");
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [4, 5, 1]
+        );
+        assert!(backend.db().get("app").expect("could not get").is_some());
+    }
+}
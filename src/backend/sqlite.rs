@@ -0,0 +1,220 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`SqliteBackend`], storing data in a row of a
+//! SQLite table.
+
+use super::{Backend, BackendCapabilities};
+use crate::error;
+use rusqlite::{Connection, OptionalExtension};
+use std::io;
+
+/// A [`Backend`] storing the data as a blob in one row of a SQLite table.
+///
+/// The table has a `key` text column as its primary key and a `data` blob
+/// column. The data is stored in the row whose key is the key of the
+/// backend, so several databases can share one table, each under its own
+/// key.
+///
+/// Creating the backend doesn't touch the database. The table is created if
+/// it doesn't exist by [`Backend::init`], or by the first read or write if
+/// `init` wasn't called.
+///
+/// Every write replaces the row in a single statement, which SQLite runs
+/// atomically. The connection belongs to the backend but can be borrowed
+/// with [`SqliteBackend::connection`], for the application to keep its own
+/// tables next to the data. Reading a key that was never written fails with
+/// an I/O error of the kind [`NotFound`](io::ErrorKind::NotFound).
+///
+/// **Important**: This is only available with the `sqlite` feature
+#[derive(Debug)]
+pub struct SqliteBackend {
+    conn: Connection,
+    table: String,
+    key: String,
+    initialized: bool,
+}
+
+/// Quote `name` as a SQL identifier.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl SqliteBackend {
+    /// Store the data in the row of `table` whose key is `key`.
+    pub fn new(conn: Connection, table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            conn,
+            table: table.into(),
+            key: key.into(),
+            initialized: false,
+        }
+    }
+
+    /// The connection to the SQLite database.
+    #[must_use]
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// The table the data is stored in.
+    #[must_use]
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// The key of the row the data is stored in.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Return the inner connection.
+    #[must_use]
+    pub fn into_inner(self) -> Connection {
+        self.conn
+    }
+}
+
+impl Backend for SqliteBackend {
+    /// Create the table if it doesn't exist.
+    async fn init(&mut self) -> error::BackendResult<()> {
+        if !self.initialized {
+            self.conn.execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} \
+                     (key TEXT PRIMARY KEY NOT NULL, data BLOB NOT NULL)",
+                    quote_identifier(&self.table)
+                ),
+                [],
+            )?;
+            self.initialized = true;
+        }
+        Ok(())
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        self.init().await?;
+        let data = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT data FROM {} WHERE key = ?1",
+                    quote_identifier(&self.table)
+                ),
+                [&self.key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        data.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no row with the key {:?}", self.key),
+            )
+            .into()
+        })
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        self.init().await?;
+        self.conn.execute(
+            &format!(
+                "INSERT INTO {} (key, data) VALUES (?1, ?2) \
+                 ON CONFLICT (key) DO UPDATE SET data = excluded.data",
+                quote_identifier(&self.table)
+            ),
+            rusqlite::params![&self.key, data],
+        )?;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::ATOMIC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteBackend;
+    use crate::backend::Backend;
+    use crate::error::BackendError;
+    use rusqlite::Connection;
+    use std::path::Path;
+
+    fn open(path: &Path, key: &str) -> SqliteBackend {
+        let conn = Connection::open(path).expect("could not open database");
+        SqliteBackend::new(conn, "dropbreak \"data\"", key)
+    }
+
+    fn table_exists(backend: &SqliteBackend) -> bool {
+        backend
+            .connection()
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                [backend.table()],
+                |row| row.get::<_, u32>(0),
+            )
+            .expect("could not query tables")
+            == 1
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_sqlite_init_creates_table() {
+        let conn = Connection::open_in_memory().expect("could not open database");
+        let mut backend = SqliteBackend::new(conn, "data", "key");
+        assert!(!table_exists(&backend));
+        backend.init().await.expect("could not init");
+        assert!(table_exists(&backend));
+        backend.init().await.expect("could not init again");
+
+        // Without `init`, the first operation creates the table.
+        let conn = Connection::open_in_memory().expect("could not open database");
+        let mut lazy = SqliteBackend::new(conn, "data", "key");
+        assert!(matches!(
+            lazy.get_data().await,
+            Err(BackendError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+        assert!(table_exists(&lazy));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_sqlite_backend_keys() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("app.sqlite");
+        let mut settings = open(&path, "settings");
+        let mut cache = open(&path, "cache");
+        match cache.get_data().await {
+            Err(BackendError::Io(e)) => assert_eq!(std::io::ErrorKind::NotFound, e.kind()),
+            res => panic!("expected NotFound, got {:?}", res),
+        }
+
+        settings
+            .put_data(&[4, 5, 1, 6, 8, 1])
+            .await
+            .expect("could not put data");
+        cache
+            .put_data(&[3, 99, 127, 6])
+            .await
+            .expect("could not put data");
+        settings
+            .put_data(&[4, 5, 1])
+            .await
+            .expect("could not put data");
+        drop(settings);
+        drop(cache);
+
+        let mut settings = open(&path, "settings");
+        let mut cache = open(&path, "cache");
+        assert_eq!(
+            settings.get_data().await.expect("could not get data"),
+            [4, 5, 1]
+        );
+        assert_eq!(
+            cache.get_data().await.expect("could not get data"),
+            [3, 99, 127, 6]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`StreamBackend`], talking to a storage
+//! daemon over a single bidirectional stream.
+
+use super::Backend;
+use crate::error;
+use std::convert::TryFrom;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The kind byte of a request for the data.
+const GET: u8 = 0x01;
+/// The kind byte of a request replacing the data.
+const PUT: u8 = 0x02;
+/// The kind byte of a response to a request that succeeded.
+const OK: u8 = 0x00;
+/// The kind byte of a response to a request that failed.
+const ERR: u8 = 0x01;
+
+/// A [`Backend`] sending its reads and writes as requests over a stream, such
+/// as a unix socket to a storage daemon.
+///
+/// # Wire format
+///
+/// Requests and responses are frames of a kind byte, the length of the
+/// payload as a big endian `u64`, and the payload:
+///
+/// | Frame           | Kind   | Payload                          |
+/// |-----------------|--------|----------------------------------|
+/// | Get request     | `0x01` | empty                            |
+/// | Put request     | `0x02` | the data to store                |
+/// | Ok response     | `0x00` | the data for a Get, else empty   |
+/// | Error response  | `0x01` | a UTF-8 description of the error |
+///
+/// Every request is answered by exactly one response, in order. Only one
+/// request is in flight at a time. Frames may arrive in any number of pieces,
+/// a stream that ends within a frame fails with
+/// [`io::ErrorKind::UnexpectedEof`].
+#[derive(Debug)]
+pub struct StreamBackend<S> {
+    stream: S,
+}
+
+impl<S> StreamBackend<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    /// Use `stream`, connected to a storage daemon, as the backend.
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Return the inner stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Send a request and wait for the payload of its response.
+    async fn request(&mut self, kind: u8, payload: &[u8]) -> error::BackendResult<Vec<u8>> {
+        write_frame(&mut self.stream, kind, payload).await?;
+        match read_frame(&mut self.stream).await? {
+            (OK, payload) => Ok(payload),
+            (ERR, message) => Err(io::Error::other(String::from_utf8_lossy(&message)).into()),
+            (kind, _) => Err(error::BackendError::Internal(format!(
+                "unknown response kind {kind:#04x}"
+            ))),
+        }
+    }
+}
+
+/// Write a frame of `kind` with `payload` to `writer`, and flush it.
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    kind: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    let len = u64::try_from(payload.len()).map_err(io::Error::other)?;
+    let mut header = [0; 9];
+    header[0] = kind;
+    header[1..].copy_from_slice(&len.to_be_bytes());
+    writer.write_all(&header).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+/// Read a frame from `reader`, returning its kind and payload.
+///
+/// The payload is only allocated as it arrives, so a corrupted length can't
+/// make this allocate more than the stream actually sends.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 9];
+    reader.read_exact(&mut header).await?;
+    let mut len = [0; 8];
+    len.copy_from_slice(&header[1..]);
+    let len = u64::from_be_bytes(len);
+
+    let mut payload = Vec::new();
+    let read = reader.take(len).read_to_end(&mut payload).await?;
+    if u64::try_from(read).map_err(io::Error::other)? != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok((header[0], payload))
+}
+
+impl<S> Backend for StreamBackend<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        self.request(GET, &[]).await
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        self.request(PUT, data).await.map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_frame, write_frame, StreamBackend, ERR, GET, OK, PUT};
+    use crate::backend::Backend;
+    use crate::error::BackendError;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+
+    /// Returns one to three bytes per read of `stream`, and is not ready
+    /// every other time.
+    struct ShortReads {
+        stream: DuplexStream,
+        reads: usize,
+    }
+
+    impl AsyncRead for ShortReads {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            self.reads += 1;
+            if self.reads.is_multiple_of(2) {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = (self.reads / 2 % 3 + 1).min(buf.remaining());
+            let mut short = ReadBuf::new(buf.initialize_unfilled_to(len));
+            let polled = Pin::new(&mut self.stream).poll_read(cx, &mut short);
+            let filled = short.filled().len();
+            buf.advance(filled);
+            polled
+        }
+    }
+
+    impl AsyncWrite for ShortReads {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.stream).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.stream).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.stream).poll_shutdown(cx)
+        }
+    }
+
+    /// Answers requests on `stream` from memory until the stream is closed.
+    ///
+    /// Payloads are written a byte at a time, so that the client has to
+    /// assemble them from many reads. A Put of an empty payload fails.
+    async fn serve(mut stream: DuplexStream) {
+        let mut stored = Vec::new();
+        while let Ok((kind, payload)) = read_frame(&mut stream).await {
+            let (kind, response) = match kind {
+                GET => (OK, stored.clone()),
+                PUT if payload.is_empty() => (ERR, b"refusing to store nothing".to_vec()),
+                PUT => {
+                    stored = payload;
+                    (OK, Vec::new())
+                }
+                _ => (ERR, b"unknown request".to_vec()),
+            };
+            let mut frame = Vec::new();
+            write_frame(&mut frame, kind, &response)
+                .await
+                .expect("could not encode frame");
+            for byte in frame {
+                stream.write_all(&[byte]).await.expect("could not respond");
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_backend_roundtrip() {
+        // A buffer smaller than a frame forces partial reads and writes.
+        let (client, server) = tokio::io::duplex(4);
+        tokio::spawn(serve(server));
+        let mut backend = StreamBackend::new(client);
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+
+        assert!(backend
+            .get_data()
+            .await
+            .expect("could not get data")
+            .is_empty());
+        backend.put_data(&data).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+
+        match backend.put_data(&[]).await {
+            Err(BackendError::Io(e)) => assert_eq!("refusing to store nothing", e.to_string()),
+            res => panic!("expected the error of the server, got {:?}", res),
+        }
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+    }
+
+    #[tokio::test]
+    async fn test_stream_backend_short_reads() {
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(serve(server));
+        let mut backend = StreamBackend::new(ShortReads {
+            stream: client,
+            reads: 0,
+        });
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        backend.put_data(&data).await.expect("could not put data");
+
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+        let mut streamed = Vec::new();
+        let read = backend
+            .get_data_into(&mut streamed)
+            .await
+            .expect("could not get data");
+        assert_eq!(data.len() as u64, read);
+        assert_eq!(streamed, data);
+        assert!(backend.into_inner().reads > 2 * data.len() / 3);
+    }
+
+    #[tokio::test]
+    async fn test_stream_backend_closed_mid_frame() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut backend = StreamBackend::new(client);
+        tokio::spawn(async move {
+            read_frame(&mut server).await.expect("no request");
+            // Announces ten bytes, but only sends three.
+            server
+                .write_all(&[OK, 0, 0, 0, 0, 0, 0, 0, 10, 1, 2, 3])
+                .await
+                .expect("could not respond");
+        });
+
+        match backend.get_data().await {
+            Err(BackendError::Io(e)) => assert_eq!(std::io::ErrorKind::UnexpectedEof, e.kind()),
+            res => panic!("expected an unexpected EOF, got {:?}", res),
+        }
+    }
+}
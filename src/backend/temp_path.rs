@@ -0,0 +1,81 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`TempPathBackend`], a [`PathBackend`] in a
+//! temporary directory for tests.
+
+use super::PathBackend;
+use crate::error;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// The name of the file in the temporary directory.
+const FILE_NAME: &str = "db";
+
+/// A [`PathBackend`] storing its file in a fresh temporary directory, which
+/// is deleted with everything in it when the backend is dropped.
+///
+/// This gives tests a real file system path, including the atomic saves of
+/// the [`PathBackend`], without having to clean up after them.
+///
+/// **Important**: This is only available with the `testing` feature
+#[derive(Debug)]
+pub struct TempPathBackend {
+    // Declared first so that it is dropped before the directory.
+    inner: PathBackend,
+    dir: TempDir,
+}
+
+impl TempPathBackend {
+    /// Create a temporary directory and a [`PathBackend`] for a new, empty
+    /// file in it.
+    pub async fn new() -> error::BackendResult<Self> {
+        let dir = tempfile::tempdir()?;
+        let (inner, _) = PathBackend::from_path_or_create(dir.path().join(FILE_NAME)).await?;
+        Ok(Self { inner, dir })
+    }
+
+    /// The temporary directory.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// The path of the file in the temporary directory.
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().join(FILE_NAME)
+    }
+}
+
+crate::delegate_backend! {
+    impl Backend for TempPathBackend => inner {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TempPathBackend;
+    use crate::backend::Backend;
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_dir_removed_on_drop() {
+        let mut backend = TempPathBackend::new()
+            .await
+            .expect("could not create backend");
+        let dir = backend.dir().to_owned();
+        let data = [4, 5, 1, 6, 8, 1];
+
+        backend.put_data(&data).await.expect("could not put data");
+        assert!(dir.is_dir());
+        assert_eq!(
+            data,
+            &std::fs::read(backend.path()).expect("could not read file")[..]
+        );
+        assert_eq!(backend.get_data().await.expect("could not get data"), data);
+
+        drop(backend);
+        assert!(!dir.exists());
+    }
+}
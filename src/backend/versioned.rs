@@ -0,0 +1,163 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which defines the [`VersionedBackend`] trait, for backends over
+//! storage keeping the earlier versions of the data.
+
+use super::Backend;
+use crate::error;
+use std::time::SystemTime;
+
+/// A version of the data kept by a [`VersionedBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectVersion {
+    /// The identifier of the version, as given by the storage.
+    pub id: String,
+    /// When the version was written, if the storage knows.
+    pub modified: Option<SystemTime>,
+    /// The size of the data of the version, in bytes.
+    pub size: u64,
+    /// Whether this is the current version, the one
+    /// [`Backend::get_data`] reads.
+    pub latest: bool,
+}
+
+/// A [`Backend`] whose storage keeps the data of earlier writes, like an
+/// object store with versioning enabled, and can read it back.
+///
+/// This gives point-in-time recovery using the versioning of the storage
+/// itself: [`Database::load_version`](crate::Database::load_version) loads
+/// the data of an earlier version, which the next save writes as the newest
+/// one. Implementations map the version APIs of their storage, errors
+/// included, to [`BackendResult`](error::BackendResult).
+pub trait VersionedBackend: Backend {
+    /// List the versions of the data the storage still has, newest first.
+    fn list_versions(
+        &mut self,
+    ) -> impl std::future::Future<Output = error::BackendResult<Vec<ObjectVersion>>> + Send;
+
+    /// Read the data of the version `version_id`, one of the ids returned by
+    /// [`VersionedBackend::list_versions`].
+    ///
+    /// Fails with an I/O error of the kind
+    /// [`NotFound`](std::io::ErrorKind::NotFound) if there is no such
+    /// version.
+    fn get_data_version(
+        &mut self,
+        version_id: &str,
+    ) -> impl std::future::Future<Output = error::BackendResult<Vec<u8>>> + Send;
+}
+
+impl<T: VersionedBackend + Send> VersionedBackend for Box<T> {
+    async fn list_versions(&mut self) -> error::BackendResult<Vec<ObjectVersion>> {
+        use std::ops::DerefMut;
+        self.deref_mut().list_versions().await
+    }
+
+    async fn get_data_version(&mut self, version_id: &str) -> error::BackendResult<Vec<u8>> {
+        use std::ops::DerefMut;
+        self.deref_mut().get_data_version(version_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ObjectVersion, VersionedBackend};
+    use crate::backend::Backend;
+    use crate::error;
+    use std::io;
+
+    /// An object store with versioning enabled, keeping every write.
+    #[derive(Debug, Default)]
+    struct MockStore {
+        versions: Vec<Vec<u8>>,
+    }
+
+    impl Backend for MockStore {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.versions
+                .last()
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound).into())
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.versions.push(data.to_vec());
+            Ok(())
+        }
+    }
+
+    impl VersionedBackend for MockStore {
+        async fn list_versions(&mut self) -> error::BackendResult<Vec<ObjectVersion>> {
+            let latest = self.versions.len().saturating_sub(1);
+            Ok(self
+                .versions
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, data)| ObjectVersion {
+                    id: format!("v{i}"),
+                    modified: None,
+                    size: data.len() as u64,
+                    latest: i == latest,
+                })
+                .collect())
+        }
+
+        async fn get_data_version(&mut self, version_id: &str) -> error::BackendResult<Vec<u8>> {
+            version_id
+                .strip_prefix('v')
+                .and_then(|i| i.parse::<usize>().ok())
+                .and_then(|i| self.versions.get(i))
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound).into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_and_read_versions() {
+        let mut store = MockStore::default();
+        store.put_data(b"old").await.expect("could not put data");
+        store.put_data(b"newer").await.expect("could not put data");
+
+        let versions = store.list_versions().await.expect("could not list");
+        assert_eq!(
+            vec![("v1", 5, true), ("v0", 3, false)],
+            versions
+                .iter()
+                .map(|v| (v.id.as_str(), v.size, v.latest))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            store
+                .get_data_version(&versions[1].id)
+                .await
+                .expect("could not get version"),
+            b"old"
+        );
+        assert!(store.get_data_version("v7").await.is_err());
+    }
+
+    #[cfg(feature = "ron_enc")]
+    #[tokio::test]
+    async fn test_database_restores_version() {
+        use crate::deser::Ron;
+        use crate::Database;
+
+        let db = Database::<Vec<u32>, _, Ron>::from_parts(vec![1], MockStore::default(), Ron);
+        db.save().await.expect("could not save");
+        db.put_data(vec![1, 2], true)
+            .await
+            .expect("could not put data");
+
+        let versions = db.list_versions().await.expect("could not list");
+        assert_eq!(2, versions.len());
+        db.load_version(&versions[1].id)
+            .await
+            .expect("could not load version");
+        assert_eq!(vec![1], db.get_data(false).await.expect("no data"));
+        db.save().await.expect("could not save");
+        assert_eq!(3, db.list_versions().await.expect("could not list").len());
+    }
+}
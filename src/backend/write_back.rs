@@ -0,0 +1,379 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`WriteBackCacheBackend`], writing to a fast
+//! backend right away and to a durable one in the background.
+
+use super::{Backend, BackendCapabilities};
+use crate::error;
+use std::sync::{Arc, PoisonError};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// The write-backs still to do.
+#[derive(Debug, Default)]
+struct Queue {
+    /// The data written to the fast backend but not yet to the durable one.
+    data: Option<Vec<u8>>,
+    /// Whether a background task is writing back.
+    running: bool,
+    /// Whether the write-backs wait for [`WriteBackCacheBackend::resume`].
+    paused: bool,
+}
+
+type Pending = Arc<std::sync::Mutex<Queue>>;
+
+fn lock(pending: &Pending) -> std::sync::MutexGuard<'_, Queue> {
+    pending.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A [`Backend`] keeping the data in a fast backend, like a local file, and
+/// writing it back to a durable backend, like a remote store, in the
+/// background.
+///
+/// Reads are served by the fast backend. Until it was written or filled by
+/// this backend, if it fails, or holds no data at all, the data is read from
+/// the durable backend and copied to the fast one. Once it was, the fast
+/// backend has the latest data, even empty data, and its errors are
+/// returned since the durable backend may be behind.
+///
+/// Writes go to the fast backend, and return once it has the data. The write
+/// to the durable backend happens on a background task. Writes made while a
+/// write-back is running are coalesced: only the last one is written back
+/// once it completed. [`WriteBackCacheBackend::sync`] waits until the durable
+/// backend has the last data written. [`WriteBackCacheBackend::pause`]
+/// holds the write-backs back, during a bulk import for instance.
+///
+/// Until then the durable backend is behind. A write-back that fails is
+/// retried by the next write or by `sync`, which returns the error if it
+/// fails again. If the backend is dropped, a running write-back still
+/// completes, but data it didn't pick up yet is only in the fast backend.
+///
+/// Writes have to be made from within a tokio runtime.
+#[derive(Debug)]
+pub struct WriteBackCacheBackend<F, D> {
+    fast: F,
+    /// Whether the fast backend holds the latest data, since this backend
+    /// wrote it or filled it from the durable one.
+    cached: bool,
+    durable: Arc<Mutex<D>>,
+    pending: Pending,
+    write_back: Option<JoinHandle<()>>,
+}
+
+impl<F, D> WriteBackCacheBackend<F, D>
+where
+    F: Backend + Send,
+    D: Backend + Send + 'static,
+{
+    /// Cache the data of `durable` in `fast`.
+    pub fn new(fast: F, durable: D) -> Self {
+        Self {
+            fast,
+            cached: false,
+            durable: Arc::new(Mutex::new(durable)),
+            pending: Pending::default(),
+            write_back: None,
+        }
+    }
+
+    /// Whether some data was written to the fast backend but not yet to the
+    /// durable one.
+    #[must_use]
+    pub fn has_pending(&self) -> bool {
+        let queue = lock(&self.pending);
+        queue.running || queue.data.is_some()
+    }
+
+    /// Stop writing back in the background until
+    /// [`WriteBackCacheBackend::resume`].
+    ///
+    /// Writes still go to the fast backend, the last one is written back
+    /// when the write-backs resume. A write-back already running finishes
+    /// the write it started, and leaves the data written after it for the
+    /// resume.
+    pub fn pause(&mut self) {
+        lock(&self.pending).paused = true;
+    }
+
+    /// Write back in the background again, starting with the last data
+    /// written while paused, if any.
+    ///
+    /// Does nothing if the write-backs are not paused. This has to be called
+    /// from within a tokio runtime.
+    pub fn resume(&mut self) {
+        let mut queue = lock(&self.pending);
+        if std::mem::replace(&mut queue.paused, false) && queue.data.is_some() && !queue.running {
+            drop(queue);
+            self.spawn_write_back();
+        }
+    }
+
+    /// Whether the write-backs are paused.
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        lock(&self.pending).paused
+    }
+
+    /// Wait for the running write-back, and write the data not yet written
+    /// back to the durable backend, even while paused.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the durable backend if the data could not be
+    /// written back. It is retried by the next write or call to `sync`.
+    pub async fn sync(&mut self) -> error::BackendResult<()> {
+        if let Some(task) = self.write_back.take() {
+            // A failed write-back put its data back into the queue.
+            task.await
+                .map_err(|e| error::BackendError::Internal(e.to_string()))?;
+        }
+        write_back(&self.durable, &self.pending, true).await
+    }
+
+    /// Wait for the write-backs, and return the fast and durable backends.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of [`WriteBackCacheBackend::sync`], along with the
+    /// backend.
+    pub async fn into_parts(mut self) -> Result<(F, D), (error::BackendError, Self)> {
+        if let Err(e) = self.sync().await {
+            return Err((e, self));
+        }
+        match Arc::try_unwrap(self.durable) {
+            Ok(durable) => Ok((self.fast, durable.into_inner())),
+            Err(durable) => {
+                self.durable = durable;
+                Err((
+                    error::BackendError::Internal("the durable backend is still in use".into()),
+                    self,
+                ))
+            }
+        }
+    }
+
+    /// Write back the pending data on a background task.
+    fn spawn_write_back(&mut self) {
+        lock(&self.pending).running = true;
+        let durable = Arc::clone(&self.durable);
+        let pending = Arc::clone(&self.pending);
+        self.write_back = Some(tokio::spawn(async move {
+            // Retried by the next write or `sync`.
+            let _ = write_back(&durable, &pending, false).await;
+        }));
+    }
+}
+
+/// Write the pending data to `durable` until there is none left, or until
+/// the write-backs are paused unless `forced`, and mark the write-back as not
+/// running anymore.
+async fn write_back<D: Backend + Send>(
+    durable: &Mutex<D>,
+    pending: &Pending,
+    forced: bool,
+) -> error::BackendResult<()> {
+    let mut durable = durable.lock().await;
+    loop {
+        let data = {
+            let mut queue = lock(pending);
+            let next = if queue.paused && !forced {
+                None
+            } else {
+                queue.data.take()
+            };
+            let Some(data) = next else {
+                queue.running = false;
+                return Ok(());
+            };
+            data
+        };
+        if let Err(e) = durable.put_data(&data).await {
+            let mut queue = lock(pending);
+            // Unless newer data came in meanwhile, retry this data later.
+            queue.data.get_or_insert(data);
+            queue.running = false;
+            return Err(e);
+        }
+    }
+}
+
+impl<F, D> Backend for WriteBackCacheBackend<F, D>
+where
+    F: Backend + Send,
+    D: Backend + Send + 'static,
+{
+    async fn init(&mut self) -> error::BackendResult<()> {
+        self.fast.init().await?;
+        self.durable.lock().await.init().await
+    }
+
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        if self.cached {
+            return self.fast.get_data().await;
+        }
+        match self.fast.get_data().await {
+            Ok(data) if !data.is_empty() => return Ok(data),
+            _ => {}
+        }
+        let data = self.durable.lock().await.get_data().await?;
+        // The cache is filled again by the next write if this fails.
+        self.cached = self.fast.put_data(&data).await.is_ok();
+        Ok(data)
+    }
+
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        // Until the write is known to have succeeded, the fast backend may
+        // hold anything.
+        self.cached = false;
+        self.fast.put_data(data).await?;
+        self.cached = true;
+        let mut queue = lock(&self.pending);
+        queue.data = Some(data.to_vec());
+        if !queue.running && !queue.paused {
+            drop(queue);
+            self.spawn_write_back();
+        }
+        Ok(())
+    }
+
+    /// The durable backend lags behind, only the fast backend can vouch for
+    /// the writes.
+    fn capabilities(&self) -> BackendCapabilities {
+        self.fast
+            .capabilities()
+            .difference(BackendCapabilities::APPEND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteBackCacheBackend;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// A remote store taking a second per write, and counting its reads and
+    /// writes.
+    #[derive(Debug, Default, Clone)]
+    struct Remote {
+        data: Arc<Mutex<Vec<u8>>>,
+        reads: Arc<AtomicUsize>,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl Backend for Remote {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            Ok(self.data.lock().expect("poisoned").clone())
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            *self.data.lock().expect("poisoned") = data.to_vec();
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_back_cache() {
+        let remote = Remote::default();
+        *remote.data.lock().expect("poisoned") = vec![1, 2, 3];
+        let mut backend = WriteBackCacheBackend::new(MemoryBackend::new(), remote.clone());
+
+        // A miss fills the cache, later reads don't reach the remote.
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            backend.get_data().await.expect("could not get data"),
+            [1, 2, 3]
+        );
+        assert_eq!(1, remote.reads.load(Ordering::SeqCst));
+
+        let start = tokio::time::Instant::now();
+        for i in 4..8 {
+            backend.put_data(&[i]).await.expect("could not put data");
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(start, tokio::time::Instant::now());
+        assert!(backend.has_pending());
+        assert_eq!(backend.get_data().await.expect("could not get data"), [7]);
+        assert_eq!(0, remote.writes.load(Ordering::SeqCst));
+
+        backend.sync().await.expect("could not sync");
+        assert!(!backend.has_pending());
+        assert_eq!(vec![7], *remote.data.lock().expect("poisoned"));
+        // The first write-back, then the coalesced rest.
+        assert_eq!(2, remote.writes.load(Ordering::SeqCst));
+        assert_eq!(1, remote.reads.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_empty_write_is_cached() {
+        let remote = Remote::default();
+        *remote.data.lock().expect("poisoned") = vec![1, 2, 3];
+        let mut backend = WriteBackCacheBackend::new(MemoryBackend::new(), remote.clone());
+
+        backend.put_data(&[]).await.expect("could not put data");
+        assert!(backend
+            .get_data()
+            .await
+            .expect("could not get data")
+            .is_empty());
+        assert!(backend.has_pending());
+        assert_eq!(0, remote.reads.load(Ordering::SeqCst));
+
+        backend.sync().await.expect("could not sync");
+        assert!(remote.data.lock().expect("poisoned").is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_back_paused() {
+        let remote = Remote::default();
+        let mut backend = WriteBackCacheBackend::new(MemoryBackend::new(), remote.clone());
+
+        backend.pause();
+        assert!(backend.is_paused());
+        for i in 0..4 {
+            backend.put_data(&[i]).await.expect("could not put data");
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+        assert!(backend.has_pending());
+        assert_eq!(backend.get_data().await.expect("could not get data"), [3]);
+        assert_eq!(0, remote.writes.load(Ordering::SeqCst));
+
+        backend.resume();
+        assert!(!backend.is_paused());
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(!backend.has_pending());
+        assert_eq!(vec![3], *remote.data.lock().expect("poisoned"));
+        assert_eq!(1, remote.writes.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_during_write_back() {
+        let remote = Remote::default();
+        let mut backend = WriteBackCacheBackend::new(MemoryBackend::new(), remote.clone());
+
+        backend.put_data(&[1]).await.expect("could not put data");
+        tokio::task::yield_now().await;
+        backend.put_data(&[2]).await.expect("could not put data");
+        backend.pause();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(backend.has_pending());
+        assert_eq!(vec![1], *remote.data.lock().expect("poisoned"));
+        assert_eq!(1, remote.writes.load(Ordering::SeqCst));
+
+        backend.resume();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(!backend.has_pending());
+        assert_eq!(vec![2], *remote.data.lock().expect("poisoned"));
+        assert_eq!(2, remote.writes.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,199 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Module which implements the [`ZipBackend`], storing data in an entry of a
+//! zip archive.
+
+use super::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// A [`Backend`] storing the data in a single named entry of a zip archive,
+/// next to any other entries in it.
+///
+/// Entries of a zip archive can't be updated in place, so every write
+/// rewrites the whole archive: the other entries are copied as they are,
+/// without recompressing them, and the entry of the database is written
+/// last, compressed with deflate. Like with the
+/// [`PathBackend`](super::PathBackend) the new archive is written to a
+/// temporary file next to it and moved into place once complete, keeping
+/// the permissions of the old one.
+///
+/// The archive is created on the first write if it doesn't exist. Reading
+/// from a missing archive, or from an archive without the entry, fails with
+/// an I/O error of the kind [`NotFound`](io::ErrorKind::NotFound).
+///
+/// **Important**: This is only available with the `zip` feature
+#[derive(Debug, Clone)]
+pub struct ZipBackend {
+    path: PathBuf,
+    entry: String,
+}
+
+impl ZipBackend {
+    /// Store the data in the entry `entry` of the archive at `path`.
+    pub fn new(path: impl Into<PathBuf>, entry: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            entry: entry.into(),
+        }
+    }
+
+    /// The path of the archive.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The name of the entry the data is stored in.
+    #[must_use]
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    /// The directory the archive is in.
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Open the archive, if it exists.
+    fn open(&self) -> error::BackendResult<Option<ZipArchive<std::fs::File>>> {
+        match std::fs::File::open(&self.path) {
+            Ok(file) => Ok(Some(ZipArchive::new(file).map_err(backend_error)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Turn the errors of the archive into the errors of the other backends
+/// where there is one.
+fn backend_error(err: ZipError) -> BackendError {
+    match err {
+        ZipError::Io(e) => BackendError::Io(e),
+        ZipError::FileNotFound => BackendError::Io(io::Error::new(io::ErrorKind::NotFound, err)),
+        err => BackendError::Zip(err),
+    }
+}
+
+impl Backend for ZipBackend {
+    async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+        let mut archive = self.open()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist", self.path.display()),
+            )
+        })?;
+        let mut entry = archive.by_name(&self.entry).map_err(backend_error)?;
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Rewrite the archive with `data` in the entry, atomically replacing
+    /// the old one.
+    async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+        let tempf = NamedTempFile::new_in(self.dir())?;
+        if let Ok(metadata) = std::fs::metadata(&self.path) {
+            tempf.as_file().set_permissions(metadata.permissions())?;
+        }
+        let mut writer = ZipWriter::new(tempf);
+        if let Some(mut archive) = self.open()? {
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i).map_err(backend_error)?;
+                if file.name() != self.entry {
+                    writer.raw_copy_file(file).map_err(backend_error)?;
+                }
+            }
+        }
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer
+            .start_file(self.entry.as_str(), options)
+            .map_err(backend_error)?;
+        writer.write_all(data)?;
+        let tempf = writer.finish().map_err(backend_error)?;
+        tempf.as_file().sync_all()?;
+        tempf.persist(&self.path)?;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::ATOMIC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ZipBackend;
+    use crate::backend::Backend;
+    use crate::error::BackendError;
+    use std::io::{Read, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::{ZipArchive, ZipWriter};
+
+    /// Read the entry `name` of the archive at `path`.
+    fn read_entry(path: &std::path::Path, name: &str) -> Vec<u8> {
+        let file = std::fs::File::open(path).expect("could not open archive");
+        let mut archive = ZipArchive::new(file).expect("not a zip archive");
+        let mut entry = archive.by_name(name).expect("entry missing");
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).expect("could not read entry");
+        data
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_zip_backend_keeps_other_entries() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("bundle.zip");
+        let mut writer =
+            ZipWriter::new(std::fs::File::create(&path).expect("could not create archive"));
+        writer
+            .start_file("assets/logo.txt", SimpleFileOptions::default())
+            .expect("could not start entry");
+        writer.write_all(b"a logo").expect("could not write entry");
+        writer.finish().expect("could not finish archive");
+
+        let mut backend = ZipBackend::new(&path, "db.ron");
+        match backend.get_data().await {
+            Err(BackendError::Io(e)) => assert_eq!(std::io::ErrorKind::NotFound, e.kind()),
+            res => panic!("expected NotFound, got {:?}", res),
+        }
+
+        let data = [4, 5, 1, 6, 8, 1];
+        backend.put_data(&data).await.expect("could not put data");
+        let data2 = [3, 99, 127, 6];
+        backend.put_data(&data2).await.expect("could not put data");
+        assert_eq!(backend.get_data().await.expect("could not get data"), data2);
+
+        assert_eq!(b"a logo", &read_entry(&path, "assets/logo.txt")[..]);
+        assert_eq!(data2, &read_entry(&path, "db.ron")[..]);
+        let archive = ZipArchive::new(std::fs::File::open(&path).expect("could not open archive"))
+            .expect("not a zip archive");
+        assert_eq!(2, archive.len());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_zip_backend_creates_archive() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("new.zip");
+        let mut backend = ZipBackend::new(&path, "db");
+        match backend.get_data().await {
+            Err(BackendError::Io(e)) => assert_eq!(std::io::ErrorKind::NotFound, e.kind()),
+            res => panic!("expected NotFound, got {:?}", res),
+        }
+
+        backend.put_data(b"data").await.expect("could not put data");
+        assert_eq!(b"data", &read_entry(&path, "db")[..]);
+    }
+}
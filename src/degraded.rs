@@ -0,0 +1,233 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The read-only mode a [`Database`] falls back to when its backend can't be
+//! written to anymore.
+
+use std::error::Error as _;
+use std::sync::{Mutex, PoisonError};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backend::Backend;
+use crate::error::{self, BackendError, RustbreakError};
+use crate::{Database, DeSerializer};
+
+/// Whether saves are refused since one failed.
+#[derive(Debug, Default)]
+pub(crate) struct Degraded {
+    enabled: bool,
+    /// The error of the save that failed, while degraded.
+    cause: Mutex<Option<String>>,
+}
+
+/// Whether `error` means the backend can't be written to, rather than that
+/// this data can't be.
+fn is_storage_failure(error: &RustbreakError) -> bool {
+    match error {
+        RustbreakError::Backend(e) => {
+            !matches!(
+                e,
+                BackendError::Unsupported { .. }
+                    | BackendError::StaleGeneration { .. }
+                    | BackendError::Degraded { .. }
+            ) && !is_validation_failure(e)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(feature = "schema_validation")]
+fn is_validation_failure(error: &BackendError) -> bool {
+    matches!(
+        error,
+        BackendError::ValidationFailed { .. } | BackendError::InvalidSchema(_)
+    )
+}
+
+#[cfg(not(feature = "schema_validation"))]
+fn is_validation_failure(_error: &BackendError) -> bool {
+    false
+}
+
+/// `error` and its sources, from the outermost.
+fn describe(error: &RustbreakError) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        description.push_str(": ");
+        description.push_str(&e.to_string());
+        source = e.source();
+    }
+    description
+}
+
+impl Degraded {
+    fn cause(&self) -> std::sync::MutexGuard<'_, Option<String>> {
+        self.cause.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Fail with [`BackendError::Degraded`] while degraded.
+    pub(crate) fn check(&self) -> error::Result<()> {
+        match &*self.cause() {
+            Some(cause) => Err(BackendError::Degraded {
+                cause: cause.clone(),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Degrade, if enabled, after a save failed with `error`.
+    pub(crate) fn record_failure(&self, error: &RustbreakError) {
+        if self.enabled && is_storage_failure(error) {
+            *self.cause() = Some(describe(error));
+        }
+    }
+
+    /// The same mode, not degraded, for another backend.
+    pub(crate) fn forget_failure(self) -> Self {
+        Self {
+            enabled: self.enabled,
+            cause: Mutex::default(),
+        }
+    }
+}
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Make the database read-only, rather than retrying every save, once a
+    /// save fails because the backend can't be written to, like when the
+    /// disk is full or was remounted read-only.
+    ///
+    /// The save that failed returns its error. From then on the database is
+    /// degraded: saves fail with [`BackendError::Degraded`] without reaching
+    /// the backend, until [`Database::recover`] succeeds. Reads keep working
+    /// on the data in memory, which is the last data written. Writes still
+    /// change it, and are saved by `recover`.
+    ///
+    /// Only failures of the backend degrade the database. Data that doesn't
+    /// serialize or match its schema, or a stale [`FencedBackend`] generation,
+    /// fails that save alone.
+    ///
+    /// [`FencedBackend`]: crate::backend::FencedBackend
+    #[must_use]
+    pub fn with_degraded_mode(mut self) -> Self {
+        self.degraded.enabled = true;
+        self
+    }
+
+    /// Whether the database is read-only since a save failed, see
+    /// [`Database::with_degraded_mode`].
+    #[must_use]
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.cause().is_some()
+    }
+
+    /// Try to save the data, leaving the degraded mode if it succeeds.
+    ///
+    /// This is a save that isn't refused while degraded. If the database
+    /// isn't degraded, it is a plain save.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Database::save`]. The database stays
+    /// degraded if the backend still can't be written to.
+    pub async fn recover(&self) -> error::Result<()> {
+        let data = self.data.read().await;
+        self.persist(data, None).await?;
+        *self.degraded.cause() = None;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use crate::backend::Backend;
+    use crate::deser::Ron;
+    use crate::error::{self, BackendError, RustbreakError};
+    use crate::Database;
+    use std::io;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// A disk that can fill up, counting the writes reaching it.
+    #[derive(Debug, Default, Clone)]
+    struct Disk {
+        data: Arc<Mutex<Vec<u8>>>,
+        full: Arc<AtomicBool>,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl Backend for Disk {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            Ok(self.data.lock().expect("poisoned").clone())
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            if self.full.load(Ordering::SeqCst) {
+                return Err(io::Error::other("no space left on device").into());
+            }
+            *self.data.lock().expect("poisoned") = data.to_vec();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_save_degrades_until_recovered() {
+        let disk = Disk::default();
+        let db = Database::<Vec<u32>, _, Ron>::from_parts(vec![1], disk.clone(), Ron)
+            .with_degraded_mode();
+        db.save().await.expect("could not save");
+
+        disk.full.store(true, Ordering::SeqCst);
+        db.write(|data| data.push(2))
+            .await
+            .expect("could not write");
+        assert!(matches!(
+            db.save().await,
+            Err(RustbreakError::Backend(BackendError::Io(_)))
+        ));
+        assert!(db.is_degraded());
+        assert_eq!(vec![1, 2], db.get_data(false).await.expect("no data"));
+
+        let writes = disk.writes.load(Ordering::SeqCst);
+        match db.save().await {
+            Err(RustbreakError::Backend(BackendError::Degraded { cause })) => {
+                assert!(cause.contains("no space left on device"), "{}", cause);
+            }
+            res => panic!("expected Degraded, got {:?}", res),
+        }
+        assert_eq!(writes, disk.writes.load(Ordering::SeqCst));
+        assert_eq!(2, db.read(Vec::len).await.expect("no data"));
+
+        assert!(db.recover().await.is_err());
+        assert!(db.is_degraded());
+        disk.full.store(false, Ordering::SeqCst);
+        db.recover().await.expect("could not recover");
+        assert!(!db.is_degraded());
+        db.save().await.expect("could not save");
+        db.load().await.expect("could not load");
+        assert_eq!(vec![1, 2], db.get_data(false).await.expect("no data"));
+    }
+
+    #[tokio::test]
+    async fn failures_do_not_degrade_by_default() {
+        let disk = Disk::default();
+        disk.full.store(true, Ordering::SeqCst);
+        let db = Database::<Vec<u32>, _, Ron>::from_parts(vec![1], disk.clone(), Ron);
+        assert!(db.save().await.is_err());
+        assert!(!db.is_degraded());
+        assert!(matches!(
+            db.save().await,
+            Err(RustbreakError::Backend(BackendError::Io(_)))
+        ));
+    }
+}
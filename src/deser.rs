@@ -2,20 +2,36 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use crate::error;
-use std::io::Read;
+use std::io::{Read, Write};
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 #[cfg(feature = "ron_enc")]
-pub use self::ron::Ron;
+pub use self::ron::{Ron, RonOptions};
 
 #[cfg(feature = "yaml_enc")]
-pub use self::yaml::Yaml;
+pub use self::yaml::{Yaml, YamlOptions};
 
 #[cfg(feature = "bin_enc")]
 pub use self::bincode::Bincode;
 
+#[cfg(feature = "json_enc")]
+pub(crate) use self::json::parse_error;
+#[cfg(feature = "json_enc")]
+pub use self::json::{canonicalize, EnumTagging, Json};
+
+#[cfg(feature = "json_enc")]
+mod json;
+
+#[cfg(feature = "object_store")]
+pub(crate) use self::framed::crc32;
+pub use self::framed::{Framed, SkippedElement};
+
+mod framed;
+#[cfg(any(feature = "ron_enc", feature = "yaml_enc"))]
+mod limits;
+
 /// A trait to bundle serializer and deserializer in a simple struct
 ///
 /// It should preferably be an struct: one that does not have any members.
@@ -26,7 +42,7 @@ pub use self::bincode::Bincode;
 /// implementation can look like this:
 ///
 /// ```rust
-/// extern crate rustbreak;
+/// extern crate dropbreak;
 /// extern crate thiserror;
 /// extern crate serde;
 /// #[macro_use]
@@ -35,8 +51,8 @@ pub use self::bincode::Bincode;
 /// use serde::Serialize;
 /// use std::io::Read;
 ///
-/// use rustbreak::deser::DeSerializer;
-/// use rustbreak::error;
+/// use dropbreak::deser::DeSerializer;
+/// use dropbreak::error;
 ///
 /// #[derive(Clone, Debug, thiserror::Error)]
 /// #[error("A frobnarizer could not splagrle.")]
@@ -53,15 +69,16 @@ pub use self::bincode::Bincode;
 /// #[derive(Debug, Default, Clone)]
 /// struct Frobnar;
 ///
+/// # #[cfg(feature = "other_errors")]
 /// impl<T: Serialize> DeSerializer<T> for Frobnar
 /// where
 ///     for<'de> T: Deserialize<'de>,
 /// {
-///     fn serialize(&self, val: &T) -> rustbreak::DeSerResult<Vec<u8>> {
+///     fn serialize(&self, val: &T) -> dropbreak::DeSerResult<Vec<u8>> {
 ///         Ok(to_frobnar(val))
 ///     }
 ///
-///     fn deserialize<R: Read>(&self, s: R) -> rustbreak::DeSerResult<T> {
+///     fn deserialize<R: Read>(&self, s: R) -> dropbreak::DeSerResult<T> {
 ///         Ok(from_frobnar(&s).map_err(|e| error::DeSerError::Other(e.into()))?)
 ///     }
 /// }
@@ -77,66 +94,232 @@ pub trait DeSerializer<T: Serialize + DeserializeOwned>:
     fn serialize(&self, val: &T) -> error::DeSerResult<Vec<u8>>;
     /// Deserializes a [`String`] to a value.
     fn deserialize<R: Read>(&self, s: R) -> error::DeSerResult<T>;
+
+    /// Serializes a given value straight into `writer`.
+    ///
+    /// This avoids holding the whole serialized value in memory. The default
+    /// implementation writes the result of [`DeSerializer::serialize`].
+    fn serialize_into<W: Write>(&self, val: &T, mut writer: W) -> error::DeSerResult<()> {
+        writer.write_all(&self.serialize(val)?)?;
+        Ok(())
+    }
+
+    /// Checks the checksums the format stores alongside the data in `bytes`,
+    /// without deserializing it.
+    ///
+    /// Returns `None` for formats storing no checksums, which is what the
+    /// default implementation does. Used by [`Database::verify_integrity`](crate::Database::verify_integrity).
+    fn verify_checksum(&self, bytes: &[u8]) -> Option<error::DeSerResult<()>> {
+        let _ = bytes;
+        None
+    }
 }
 
 #[cfg(feature = "ron_enc")]
 mod ron {
-    use std::io::Read;
+    use std::io::{Read, Write};
 
     use serde::de::DeserializeOwned;
     use serde::Serialize;
 
-    use ron::de::from_reader as from_ron_string;
+    use ron::de::from_bytes as from_ron_bytes;
+    use ron::error::ErrorCode;
     use ron::ser::to_string_pretty as to_ron_string;
+    use ron::ser::to_writer_pretty as to_ron_writer;
     use ron::ser::PrettyConfig;
 
-    use crate::deser::DeSerializer;
+    use crate::deser::{limits, DeSerializer};
     use crate::error;
 
+    /// Turns errors that know their position into [`error::DeSerError::Parse`].
+    fn parse_error(e: ron::Error) -> error::DeSerError {
+        match e.code {
+            ErrorCode::Io(_) => e.into(),
+            _ if e.position.line == 0 => e.into(),
+            code => error::DeSerError::Parse {
+                line: e.position.line,
+                column: e.position.col,
+                message: code.to_string(),
+            },
+        }
+    }
+
     /// The Struct that allows you to use `ron` the Rusty Object Notation.
+    ///
+    /// Deserializing is limited like with [`RonOptions::default`].
     #[derive(Debug, Default, Clone)]
     pub struct Ron;
 
     impl<T: Serialize + DeserializeOwned> DeSerializer<T> for Ron {
         fn serialize(&self, val: &T) -> error::DeSerResult<Vec<u8>> {
-            Ok(to_ron_string(val, PrettyConfig::default()).map(String::into_bytes)?)
+            RonOptions::default().serialize(val)
         }
         fn deserialize<R: Read>(&self, s: R) -> error::DeSerResult<T> {
-            Ok(from_ron_string(s)?)
+            RonOptions::default().deserialize(s)
+        }
+        fn serialize_into<W: Write>(&self, val: &T, writer: W) -> error::DeSerResult<()> {
+            RonOptions::default().serialize_into(val, writer)
+        }
+    }
+
+    /// [`Ron`] with configurable limits on the data it deserializes.
+    ///
+    /// Data nested deeper than [`RonOptions::max_depth`] fails with
+    /// [`DepthExceeded`](error::DeSerError::DepthExceeded) before it is
+    /// parsed, instead of overflowing the stack. The default depth is 128.
+    /// Use it with [`Database::with_deser`](crate::Database::with_deser).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RonOptions {
+        max_depth: usize,
+    }
+
+    impl Default for RonOptions {
+        fn default() -> Self {
+            Self {
+                max_depth: limits::DEFAULT_MAX_DEPTH,
+            }
+        }
+    }
+
+    impl RonOptions {
+        /// Allow structs, tuples, lists and maps nested `max_depth` deep.
+        #[must_use]
+        pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+            self.max_depth = max_depth;
+            self
+        }
+
+        /// The nesting allowed.
+        #[must_use]
+        pub fn max_depth(&self) -> usize {
+            self.max_depth
+        }
+    }
+
+    impl<T: Serialize + DeserializeOwned> DeSerializer<T> for RonOptions {
+        fn serialize(&self, val: &T) -> error::DeSerResult<Vec<u8>> {
+            Ok(to_ron_string(val, PrettyConfig::default()).map(String::into_bytes)?)
+        }
+        fn deserialize<R: Read>(&self, mut s: R) -> error::DeSerResult<T> {
+            let mut data = Vec::new();
+            s.read_to_end(&mut data)?;
+            limits::check_ron(&data, self.max_depth)?;
+            from_ron_bytes(&data).map_err(parse_error)
+        }
+        fn serialize_into<W: Write>(&self, val: &T, writer: W) -> error::DeSerResult<()> {
+            Ok(to_ron_writer(writer, val, PrettyConfig::default())?)
         }
     }
 }
 
 #[cfg(feature = "yaml_enc")]
 mod yaml {
-    use std::io::Read;
+    use std::io::{Read, Write};
 
     use serde::de::DeserializeOwned;
     use serde::Serialize;
-    use serde_yaml::{from_reader as from_yaml_string, to_string as to_yaml_string};
+    use serde_yaml::{
+        from_slice as from_yaml_slice, to_string as to_yaml_string, to_writer as to_yaml_writer,
+    };
 
-    use crate::deser::DeSerializer;
+    use crate::deser::{limits, DeSerializer};
     use crate::error;
 
     /// The struct that allows you to use yaml.
+    ///
+    /// Deserializing is limited like with [`YamlOptions::default`].
     #[derive(Debug, Default, Clone)]
     pub struct Yaml;
 
     impl<T: Serialize + DeserializeOwned> DeSerializer<T> for Yaml {
         fn serialize(&self, val: &T) -> error::DeSerResult<Vec<u8>> {
-            Ok(to_yaml_string(val).map(String::into_bytes)?)
+            YamlOptions::default().serialize(val)
         }
         fn deserialize<R: Read>(&self, s: R) -> error::DeSerResult<T> {
-            Ok(from_yaml_string(s)?)
+            YamlOptions::default().deserialize(s)
+        }
+        fn serialize_into<W: Write>(&self, val: &T, writer: W) -> error::DeSerResult<()> {
+            YamlOptions::default().serialize_into(val, writer)
+        }
+    }
+
+    /// [`Yaml`] with configurable limits on the data it deserializes.
+    ///
+    /// Data nested deeper than [`YamlOptions::max_depth`] fails with
+    /// [`DepthExceeded`](error::DeSerError::DepthExceeded), and data whose
+    /// aliases expand to more than [`YamlOptions::max_aliases`] nodes with
+    /// [`AliasesExceeded`](error::DeSerError::AliasesExceeded), before it is
+    /// deserialized. An alias counts once plus the aliases within the node it
+    /// refers to, so that small documents expanding exponentially are
+    /// rejected. The defaults allow a depth of 128 and 10 000 aliases. Use it
+    /// with [`Database::with_deser`](crate::Database::with_deser).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct YamlOptions {
+        max_depth: usize,
+        max_aliases: u64,
+    }
+
+    impl Default for YamlOptions {
+        fn default() -> Self {
+            Self {
+                max_depth: limits::DEFAULT_MAX_DEPTH,
+                max_aliases: limits::DEFAULT_MAX_ALIASES,
+            }
+        }
+    }
+
+    impl YamlOptions {
+        /// Allow sequences and mappings nested `max_depth` deep.
+        #[must_use]
+        pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+            self.max_depth = max_depth;
+            self
+        }
+
+        /// Allow aliases expanding to `max_aliases` nodes.
+        #[must_use]
+        pub fn with_max_aliases(mut self, max_aliases: u64) -> Self {
+            self.max_aliases = max_aliases;
+            self
+        }
+
+        /// The nesting allowed.
+        #[must_use]
+        pub fn max_depth(&self) -> usize {
+            self.max_depth
+        }
+
+        /// The alias expansions allowed.
+        #[must_use]
+        pub fn max_aliases(&self) -> u64 {
+            self.max_aliases
+        }
+    }
+
+    impl<T: Serialize + DeserializeOwned> DeSerializer<T> for YamlOptions {
+        fn serialize(&self, val: &T) -> error::DeSerResult<Vec<u8>> {
+            Ok(to_yaml_string(val).map(String::into_bytes)?)
+        }
+        fn deserialize<R: Read>(&self, mut s: R) -> error::DeSerResult<T> {
+            let mut data = Vec::new();
+            s.read_to_end(&mut data)?;
+            // Text that isn't UTF-8 is reported by `serde_yaml`.
+            if let Ok(text) = std::str::from_utf8(&data) {
+                limits::check_yaml(text, self.max_depth, self.max_aliases)?;
+            }
+            Ok(from_yaml_slice(&data)?)
+        }
+        fn serialize_into<W: Write>(&self, val: &T, writer: W) -> error::DeSerResult<()> {
+            Ok(to_yaml_writer(writer, val)?)
         }
     }
 }
 
 #[cfg(feature = "bin_enc")]
 mod bincode {
-    use std::io::Read;
+    use std::io::{Read, Write};
 
-    use bincode::{deserialize_from, serialize};
+    use bincode::{deserialize_from, serialize, serialize_into};
     use serde::de::DeserializeOwned;
     use serde::Serialize;
 
@@ -154,5 +337,8 @@ mod bincode {
         fn deserialize<R: Read>(&self, s: R) -> error::DeSerResult<T> {
             Ok(deserialize_from(s)?)
         }
+        fn serialize_into<W: Write>(&self, val: &T, writer: W) -> error::DeSerResult<()> {
+            Ok(serialize_into(writer, val)?)
+        }
     }
 }
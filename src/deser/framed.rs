@@ -0,0 +1,367 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A `DeSerializer` for sequences storing every element in its own frame, so
+//! that corrupt elements can be skipped.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backend::Backend;
+use crate::deser::DeSerializer;
+use crate::error;
+use crate::stats::to_u64;
+use crate::Database;
+
+/// The size of the length in front of every frame.
+const LEN_SIZE: usize = 8;
+/// The size of the checksum following the length.
+const CHECKSUM_SIZE: usize = 4;
+
+/// The CRC-32 (IEEE) lookup table, by the low byte of the running value.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut byte: u32 = 0;
+    while byte < 256 {
+        let mut crc = byte;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte as usize] = crc;
+        byte += 1;
+    }
+    table
+}
+
+/// The CRC-32 checksum of `data`.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Stores a `Vec<T>` as a sequence of frames, each holding one element
+/// serialized by the `DeSerializer` `D`.
+///
+/// Every frame starts with the length of the element as a little endian
+/// `u64`, followed by the CRC-32 checksum of the element as a little endian
+/// `u32`. Deserializing fails if any element does or doesn't match its
+/// checksum, like any other `DeSerializer`, but
+/// [`Database::load_skip_invalid`] can skip these elements and keep the
+/// others. The checksums are also checked by
+/// [`Database::verify_integrity`].
+///
+/// Skipping only helps when the content of an element is damaged: a damaged
+/// length makes everything after it unreadable.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[cfg(feature = "ron_enc")]
+/// # {
+/// use dropbreak::deser::{Framed, Ron};
+/// use dropbreak::MemoryDatabase;
+///
+/// let db = MemoryDatabase::<Vec<u32>, Framed<Ron>>::memory(vec![1, 2, 3]);
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Framed<D>(pub D);
+
+/// An element skipped by [`Database::load_skip_invalid`].
+#[derive(Debug)]
+pub struct SkippedElement {
+    /// The index of the element in the stored sequence.
+    pub index: usize,
+    /// Why it could not be read.
+    pub error: error::DeSerError,
+}
+
+impl<D> Framed<D> {
+    /// Deserializes the elements in the frames of `bytes`, skipping those
+    /// which can't be deserialized.
+    ///
+    /// Returns the elements which could be read, and the skipped ones. A
+    /// frame cut short ends the sequence and is skipped as well.
+    pub fn deserialize_skip_invalid<T>(&self, mut bytes: &[u8]) -> (Vec<T>, Vec<SkippedElement>)
+    where
+        T: Serialize + DeserializeOwned,
+        D: DeSerializer<T>,
+    {
+        let mut elements = Vec::new();
+        let mut skipped = Vec::new();
+        let mut index = 0;
+        while !bytes.is_empty() {
+            match next_frame(&mut bytes) {
+                Ok((frame, checksum)) => {
+                    match checked(frame, checksum, index)
+                        .and_then(|frame| self.0.deserialize(frame))
+                    {
+                        Ok(element) => elements.push(element),
+                        Err(error) => skipped.push(SkippedElement { index, error }),
+                    }
+                }
+                Err(error) => {
+                    skipped.push(SkippedElement { index, error });
+                    break;
+                }
+            }
+            index += 1;
+        }
+        (elements, skipped)
+    }
+}
+
+/// Splits the next frame off `bytes`, returning it with its checksum.
+fn next_frame<'a>(bytes: &mut &'a [u8]) -> error::DeSerResult<(&'a [u8], u32)> {
+    let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame");
+    if bytes.len() < LEN_SIZE + CHECKSUM_SIZE {
+        return Err(truncated().into());
+    }
+    let (len, rest) = bytes.split_at(LEN_SIZE);
+    let (checksum, rest) = rest.split_at(CHECKSUM_SIZE);
+    let mut len_bytes = [0; LEN_SIZE];
+    len_bytes.copy_from_slice(len);
+    let mut checksum_bytes = [0; CHECKSUM_SIZE];
+    checksum_bytes.copy_from_slice(checksum);
+    let len = usize::try_from(u64::from_le_bytes(len_bytes)).map_err(|_| truncated())?;
+    if rest.len() < len {
+        return Err(truncated().into());
+    }
+    let (frame, rest) = rest.split_at(len);
+    *bytes = rest;
+    Ok((frame, u32::from_le_bytes(checksum_bytes)))
+}
+
+/// Returns the frame of the element at `index` if it matches `checksum`.
+fn checked(frame: &[u8], checksum: u32, index: usize) -> error::DeSerResult<&[u8]> {
+    if crc32(frame) == checksum {
+        Ok(frame)
+    } else {
+        Err(error::DeSerError::ChecksumMismatch { index })
+    }
+}
+
+impl<T, D> DeSerializer<Vec<T>> for Framed<D>
+where
+    T: Serialize + DeserializeOwned,
+    D: DeSerializer<T>,
+{
+    fn serialize(&self, val: &Vec<T>) -> error::DeSerResult<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.serialize_into(val, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn deserialize<R: Read>(&self, mut s: R) -> error::DeSerResult<Vec<T>> {
+        let mut bytes = Vec::new();
+        s.read_to_end(&mut bytes)?;
+        let mut bytes = &bytes[..];
+        let mut elements = Vec::new();
+        while !bytes.is_empty() {
+            let (frame, checksum) = next_frame(&mut bytes)?;
+            let frame = checked(frame, checksum, elements.len())?;
+            elements.push(self.0.deserialize(frame)?);
+        }
+        Ok(elements)
+    }
+
+    fn serialize_into<W: Write>(&self, val: &Vec<T>, mut writer: W) -> error::DeSerResult<()> {
+        for element in val {
+            let frame = self.0.serialize(element)?;
+            writer.write_all(&to_u64(frame.len()).to_le_bytes())?;
+            writer.write_all(&crc32(&frame).to_le_bytes())?;
+            writer.write_all(&frame)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the checksum of every frame. A frame cut short fails the check
+    /// as well.
+    fn verify_checksum(&self, mut bytes: &[u8]) -> Option<error::DeSerResult<()>> {
+        let mut index = 0;
+        while !bytes.is_empty() {
+            let frame = next_frame(&mut bytes)
+                .and_then(|(frame, checksum)| checked(frame, checksum, index));
+            if let Err(e) = frame {
+                return Some(Err(e));
+            }
+            index += 1;
+        }
+        Some(Ok(()))
+    }
+}
+
+impl<T, Back, D> Database<Vec<T>, Back, Framed<D>>
+where
+    T: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    D: DeSerializer<T> + Send + Sync + Clone,
+{
+    /// Load the data from the backend like [`Database::load`], skipping the
+    /// elements which can't be deserialized instead of failing.
+    ///
+    /// The data becomes the elements which could be read, in their order.
+    /// Returns the skipped elements with their errors, which is empty if the
+    /// whole sequence was read. The skipped elements are gone from the
+    /// backend with the next save.
+    ///
+    /// The hook of [`Database::on_after_load`] runs on the elements read, and
+    /// the load is counted in [`Database::stats`] and the operation log. The
+    /// base of a merge strategy is not updated by this load.
+    ///
+    /// # Errors
+    ///
+    /// Only fails if the backend can't be read.
+    pub async fn load_skip_invalid(&self) -> error::Result<Vec<SkippedElement>> {
+        let mut backend = self.backend.lock().await;
+        let bytes = backend.get_data_cow().await?;
+        let (mut elements, skipped) = self.deser.deserialize_skip_invalid(&bytes);
+        self.stats.record_load(bytes.len());
+        self.oplog.record_load(bytes.len());
+        drop(bytes);
+        drop(backend);
+
+        self.hooks.after_load(&mut elements);
+        *self.data.write().await = elements;
+        Ok(skipped)
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use super::{Framed, CHECKSUM_SIZE, LEN_SIZE};
+    use crate::backend::MemoryBackend;
+    use crate::deser::{DeSerializer, Ron};
+    use crate::error::{DeSerError, RustbreakError};
+    use crate::{Database, OperationKind};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: u32,
+        name: String,
+    }
+
+    fn items() -> Vec<Item> {
+        (1..=3)
+            .map(|id| Item {
+                id,
+                name: format!("item {id}"),
+            })
+            .collect()
+    }
+
+    /// The items with the content of the frame of the second one damaged.
+    fn corrupt_items() -> Vec<u8> {
+        let mut bytes = Framed(Ron)
+            .serialize(&items())
+            .expect("could not serialize");
+        let first = Ron
+            .serialize(&items()[0])
+            .expect("could not serialize")
+            .len();
+        let header = LEN_SIZE + CHECKSUM_SIZE;
+        let second = header + first + header;
+        bytes[second..second + 4].copy_from_slice(b"!!!!");
+        bytes
+    }
+
+    #[test]
+    fn framed_roundtrip() {
+        let bytes = Framed(Ron)
+            .serialize(&items())
+            .expect("could not serialize");
+        let back: Vec<Item> = Framed(Ron)
+            .deserialize(&bytes[..])
+            .expect("could not deserialize");
+        assert_eq!(items(), back);
+        let empty: Vec<Item> = Framed(Ron)
+            .deserialize(&[][..])
+            .expect("could not deserialize");
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn changed_element_fails_checksum() {
+        let mut bytes = Framed(Ron)
+            .serialize(&items())
+            .expect("could not serialize");
+        // Still a valid item, the name of the last one is changed.
+        let name = bytes
+            .windows(6)
+            .rposition(|window| window == b"item 3")
+            .expect("no name in the data");
+        bytes[name + 5] = b'4';
+        let err = DeSerializer::<Vec<Item>>::deserialize(&Framed(Ron), &bytes[..])
+            .expect_err("changed element was read");
+        assert!(matches!(err, DeSerError::ChecksumMismatch { index: 2 }));
+    }
+
+    #[tokio::test]
+    async fn load_skip_invalid_keeps_valid_elements() {
+        let mut backend = MemoryBackend::new();
+        crate::backend::Backend::put_data(&mut backend, &corrupt_items())
+            .await
+            .expect("could not put data");
+        let db = Database::<Vec<Item>, _, Framed<Ron>>::from_parts(vec![], backend, Framed(Ron))
+            .with_operation_log(4);
+
+        match db.load().await {
+            Err(RustbreakError::DeSerialization(_)) => {}
+            res => panic!("expected a deserialization error, got {:?}", res),
+        }
+
+        let skipped = db.load_skip_invalid().await.expect("could not load");
+        assert_eq!(1, skipped.len());
+        assert_eq!(1, skipped[0].index);
+        assert!(matches!(
+            skipped[0].error,
+            DeSerError::ChecksumMismatch { index: 1 }
+        ));
+        let loaded = db.get_data(false).await.expect("no data");
+        assert_eq!(vec![items()[0].clone(), items()[2].clone()], loaded);
+        let log = db.operation_log();
+        assert_eq!(1, log.len());
+        assert_eq!(OperationKind::Load, log[0].kind);
+    }
+
+    #[tokio::test]
+    async fn load_skip_invalid_runs_after_load() {
+        let mut backend = MemoryBackend::new();
+        crate::backend::Backend::put_data(&mut backend, &corrupt_items())
+            .await
+            .expect("could not put data");
+        let db = Database::<Vec<Item>, _, Framed<Ron>>::from_parts(vec![], backend, Framed(Ron));
+        db.on_after_load(|items| items.retain(|item| item.id != 1));
+
+        db.load_skip_invalid().await.expect("could not load");
+        let loaded = db.get_data(false).await.expect("no data");
+        assert_eq!(vec![items()[2].clone()], loaded);
+    }
+
+    #[test]
+    fn truncated_frame_ends_sequence() {
+        let mut bytes = Framed(Ron)
+            .serialize(&items())
+            .expect("could not serialize");
+        bytes.truncate(bytes.len() - 2);
+        let (elements, skipped) = Framed(Ron).deserialize_skip_invalid::<Item>(&bytes);
+        assert_eq!(items()[..2], elements[..]);
+        assert_eq!(1, skipped.len());
+        assert_eq!(2, skipped[0].index);
+        assert!(matches!(skipped[0].error, DeSerError::Io(_)));
+    }
+}
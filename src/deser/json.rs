@@ -0,0 +1,938 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The JSON `DeSerializer`, with a choice of how enums are laid out on disk.
+
+use std::io::{Read, Write};
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use serde::{forward_to_deserialize_any, Deserializer};
+use serde_json::{Map, Value};
+
+use crate::deser::DeSerializer;
+use crate::error;
+
+/// How enum variants are represented in the JSON document.
+///
+/// Serde fixes the representation of an enum when it is derived, which makes
+/// it hard to read files written by another system that chose a different
+/// one. [`Json`] can rewrite the representation of every enum it encounters
+/// to match such a layout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EnumTagging {
+    /// Serde's default: `{"Variant": content}`, with unit variants written as
+    /// a plain `"Variant"` string.
+    #[default]
+    External,
+    /// `{"<tag>": "Variant", "<content>": content}`, with the content key
+    /// omitted for unit variants.
+    Adjacent {
+        /// The key holding the variant name
+        tag: &'static str,
+        /// The key holding the variant content
+        content: &'static str,
+    },
+}
+
+/// The struct that allows you to use JSON.
+///
+/// By default enums are externally tagged, exactly as `serde_json` would
+/// write them. Use [`Json::with_tagging`] together with
+/// [`Database::with_deser`](crate::Database::with_deser) to pick another
+/// layout.
+///
+/// Note that map keys are always written as strings, so enums used as map
+/// keys keep their externally tagged (unit variant) form.
+#[derive(Debug, Default, Clone)]
+pub struct Json {
+    tagging: EnumTagging,
+}
+
+impl Json {
+    /// Create a JSON `DeSerializer` using the given enum representation.
+    #[must_use]
+    pub fn with_tagging(tagging: EnumTagging) -> Self {
+        Self { tagging }
+    }
+
+    /// The enum representation used by this `DeSerializer`.
+    #[must_use]
+    pub fn tagging(&self) -> EnumTagging {
+        self.tagging
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> DeSerializer<T> for Json {
+    fn serialize(&self, val: &T) -> error::DeSerResult<Vec<u8>> {
+        match self.tagging {
+            EnumTagging::External => Ok(serde_json::to_vec_pretty(val)?),
+            EnumTagging::Adjacent { tag, content } => {
+                let value = val.serialize(AdjacentSerializer(Tags { tag, content }))?;
+                Ok(serde_json::to_vec_pretty(&value)?)
+            }
+        }
+    }
+
+    fn serialize_into<W: Write>(&self, val: &T, writer: W) -> error::DeSerResult<()> {
+        match self.tagging {
+            EnumTagging::External => Ok(serde_json::to_writer_pretty(writer, val)?),
+            EnumTagging::Adjacent { tag, content } => {
+                let value = val.serialize(AdjacentSerializer(Tags { tag, content }))?;
+                Ok(serde_json::to_writer_pretty(writer, &value)?)
+            }
+        }
+    }
+
+    fn deserialize<R: Read>(&self, s: R) -> error::DeSerResult<T> {
+        match self.tagging {
+            EnumTagging::External => serde_json::from_reader(s).map_err(parse_error),
+            EnumTagging::Adjacent { .. } => {
+                self.deserialize_value(serde_json::from_reader(s).map_err(parse_error)?)
+            }
+        }
+    }
+}
+
+/// Turns errors that know their position into [`error::DeSerError::Parse`].
+pub(crate) fn parse_error(e: serde_json::Error) -> error::DeSerError {
+    if e.is_io() || e.line() == 0 {
+        return e.into();
+    }
+    let (line, column) = (e.line(), e.column());
+    // The message ends with the position, which has its own fields.
+    let message = e.to_string();
+    let message = message
+        .strip_suffix(&format!(" at line {line} column {column}"))
+        .unwrap_or(&message)
+        .to_owned();
+    error::DeSerError::Parse {
+        line,
+        column,
+        message,
+    }
+}
+
+/// Rewrite the JSON document `data` into its canonical form.
+///
+/// Documents which only differ in formatting have the same canonical form,
+/// which makes it suitable for signing and hashing: the output is compact,
+/// object keys are sorted by their bytes, and numbers are written the way
+/// `serde_json` writes them, e.g. `1.50` and `15e-1` both become `1.5`.
+/// Integers and floats stay apart, `1` and `1.0` are different.
+///
+/// The canonical form is usually not the form [`Json`] stores, which is
+/// pretty printed and keeps the order of the fields.
+///
+/// Signatures of the `SignedBackend`, and the hashes comparing the data of a
+/// backend in [`Database::with_canonical_json`](crate::Database::with_canonical_json)
+/// and `ReplicatedBackend::canonical_json`, use it when enabled.
+///
+/// **Important**: This is only available with the `json_enc` feature
+pub fn canonicalize(data: &[u8]) -> error::DeSerResult<Vec<u8>> {
+    let value: Value = serde_json::from_slice(data).map_err(parse_error)?;
+    let mut canonical = Vec::with_capacity(data.len());
+    write_canonical(&value, &mut canonical)?;
+    Ok(canonical)
+}
+
+/// Write `value` compactly, with the keys of every object sorted.
+///
+/// `serde_json` keeps the keys in insertion order if its `preserve_order`
+/// feature is enabled by any crate, so they are sorted here.
+fn write_canonical(value: &Value, out: &mut Vec<u8>) -> serde_json::Result<()> {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push(b'{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, key)?;
+                out.push(b':');
+                write_canonical(value, out)?;
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out)?;
+            }
+            out.push(b']');
+        }
+        scalar => serde_json::to_writer(&mut *out, scalar)?,
+    }
+    Ok(())
+}
+
+impl Json {
+    /// Deserializes an already parsed JSON value, honouring the tagging.
+    pub(crate) fn deserialize_value<T: DeserializeOwned>(
+        &self,
+        value: Value,
+    ) -> error::DeSerResult<T> {
+        match self.tagging {
+            EnumTagging::External => Ok(serde_json::from_value(value)?),
+            EnumTagging::Adjacent { tag, content } => Ok(T::deserialize(AdjacentValue {
+                value,
+                tags: Tags { tag, content },
+            })?),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tags {
+    tag: &'static str,
+    content: &'static str,
+}
+
+impl Tags {
+    fn variant(self, variant: &'static str, content: Option<Value>) -> Value {
+        let mut map = Map::new();
+        map.insert(self.tag.to_owned(), Value::String(variant.to_owned()));
+        if let Some(content) = content {
+            map.insert(self.content.to_owned(), content);
+        }
+        Value::Object(map)
+    }
+}
+
+/// Serializes into a [`Value`], writing enums adjacently tagged.
+///
+/// Everything that is not an enum is handed to `serde_json`'s own value
+/// serializer, so the output only differs in the enum layout.
+struct AdjacentSerializer(Tags);
+
+macro_rules! serialize_with_json {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method(self, v: $ty) -> Result<Value, serde_json::Error> {
+                ser::Serializer::$method(serde_json::value::Serializer, v)
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for AdjacentSerializer {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVariant<SerializeVec>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeVariant<SerializeMap>;
+
+    serialize_with_json! {
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+    }
+
+    fn serialize_none(self) -> Result<Value, Self::Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Self::Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Self::Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Self::Error> {
+        Ok(self.0.variant(variant, None))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Self::Error> {
+        let content = value.serialize(AdjacentSerializer(self.0))?;
+        Ok(self.0.variant(variant, Some(content)))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(SerializeVec {
+            tags: self.0,
+            vec: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(SerializeMap {
+            tags: self.0,
+            map: Map::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+struct SerializeVec {
+    tags: Tags,
+    vec: Vec<Value>,
+}
+
+impl SerializeVec {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), serde_json::Error> {
+        self.vec
+            .push(value.serialize(AdjacentSerializer(self.tags))?);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Self::Error> {
+        Ok(Value::Array(self.vec))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Self::Error> {
+        Ok(Value::Array(self.vec))
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Self::Error> {
+        Ok(Value::Array(self.vec))
+    }
+}
+
+struct SerializeMap {
+    tags: Tags,
+    map: Map<String, Value>,
+    next_key: Option<String>,
+}
+
+impl SerializeMap {
+    fn key<T: ?Sized + Serialize>(key: &T) -> Result<String, serde_json::Error> {
+        // Keys are left to `serde_json`, which also turns numbers and unit
+        // variants into strings.
+        match serde_json::to_value(key)? {
+            Value::String(s) => Ok(s),
+            Value::Number(n) => Ok(n.to_string()),
+            Value::Bool(b) => Ok(b.to_string()),
+            _ => Err(ser::Error::custom("key must be a string")),
+        }
+    }
+
+    fn insert<T: ?Sized + Serialize>(
+        &mut self,
+        key: String,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        let value = value.serialize(AdjacentSerializer(self.tags))?;
+        self.map.insert(key, value);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.next_key = Some(Self::key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| ser::Error::custom("serialize_value called before serialize_key"))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Value, Self::Error> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.insert(key.to_owned(), value)
+    }
+
+    fn end(self) -> Result<Value, Self::Error> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+/// Wraps the serializer of a variant's content, tagging it on `end`.
+struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeVec> {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<Value, Self::Error> {
+        let tags = self.inner.tags;
+        Ok(tags.variant(self.variant, Some(Value::Array(self.inner.vec))))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap> {
+    type Ok = Value;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.inner.insert(key.to_owned(), value)
+    }
+
+    fn end(self) -> Result<Value, Self::Error> {
+        let tags = self.inner.tags;
+        Ok(tags.variant(self.variant, Some(Value::Object(self.inner.map))))
+    }
+}
+
+/// Deserializes from a [`Value`], reading enums adjacently tagged.
+struct AdjacentValue {
+    value: Value,
+    tags: Tags,
+}
+
+impl IntoDeserializer<'_, serde_json::Error> for AdjacentValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for AdjacentValue {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let tags = self.tags;
+        match self.value {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Number(n) => n.deserialize_any(visitor),
+            Value::String(s) => visitor.visit_string(s),
+            Value::Array(vec) => {
+                let mut seq = de::value::SeqDeserializer::new(
+                    vec.into_iter().map(|value| AdjacentValue { value, tags }),
+                );
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Object(map) => {
+                let mut map = de::value::MapDeserializer::new(
+                    map.into_iter()
+                        .map(|(key, value)| (MapKey(key), AdjacentValue { value, tags })),
+                );
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let tags = self.tags;
+        let mut map = match self.value {
+            Value::Object(map) => map,
+            other => {
+                return Err(de::Error::invalid_type(
+                    unexpected(&other),
+                    &"an adjacently tagged enum",
+                ))
+            }
+        };
+        let variant = match map.remove(tags.tag) {
+            Some(Value::String(variant)) => variant,
+            Some(other) => {
+                return Err(de::Error::invalid_type(
+                    unexpected(&other),
+                    &"a variant name",
+                ))
+            }
+            None => return Err(de::Error::missing_field(tags.tag)),
+        };
+        let content = map.remove(tags.content);
+        if let Some(key) = map.keys().next() {
+            return Err(de::Error::custom(format_args!(
+                "unknown field `{}`, expected `{}` or `{}`",
+                key, tags.tag, tags.content
+            )));
+        }
+        visitor.visit_enum(AdjacentEnum {
+            variant,
+            content: VariantContent(content.map(|value| AdjacentValue { value, tags })),
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+fn unexpected(value: &Value) -> de::Unexpected<'_> {
+    match value {
+        Value::Null => de::Unexpected::Unit,
+        Value::Bool(b) => de::Unexpected::Bool(*b),
+        Value::Number(_) => de::Unexpected::Other("number"),
+        Value::String(s) => de::Unexpected::Str(s),
+        Value::Array(_) => de::Unexpected::Seq,
+        Value::Object(_) => de::Unexpected::Map,
+    }
+}
+
+struct AdjacentEnum {
+    variant: String,
+    content: VariantContent,
+}
+
+/// The content of a variant, absent for unit variants.
+struct VariantContent(Option<AdjacentValue>);
+
+impl<'de> de::EnumAccess<'de> for AdjacentEnum {
+    type Error = serde_json::Error;
+    type Variant = VariantContent;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.content))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantContent {
+    type Error = serde_json::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        match self.0 {
+            None
+            | Some(AdjacentValue {
+                value: Value::Null, ..
+            }) => Ok(()),
+            Some(content) => Err(de::Error::invalid_type(
+                unexpected(&content.value),
+                &"unit variant",
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        match self.0 {
+            Some(content) => seed.deserialize(content),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Some(content) => content.deserialize_seq(visitor),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Some(content) => content.deserialize_map(visitor),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
+
+/// A JSON object key, which may stand in for a number, a bool or a unit
+/// variant.
+struct MapKey(String);
+
+impl IntoDeserializer<'_, serde_json::Error> for MapKey {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed_key {
+    ($($method:ident => $visit:ident),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(parsed) => visitor.$visit(parsed),
+                    Err(_) => visitor.visit_string(self.0),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for MapKey {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.0)
+    }
+
+    deserialize_parsed_key! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let key: de::value::StringDeserializer<serde_json::Error> = self.0.into_deserializer();
+        key.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonicalize, EnumTagging, Json};
+    use crate::deser::DeSerializer;
+    use crate::MemoryDatabase;
+    use serde_derive::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Line(i32, i32),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        shapes: Vec<Shape>,
+        fill: Option<Shape>,
+        by_id: BTreeMap<u32, Shape>,
+    }
+
+    fn drawing() -> Drawing {
+        let mut by_id = BTreeMap::new();
+        by_id.insert(7, Shape::Circle(3));
+        Drawing {
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(1),
+                Shape::Line(-1, 2),
+                Shape::Rect { w: 4, h: 5 },
+            ],
+            fill: Some(Shape::Empty),
+            by_id,
+        }
+    }
+
+    fn adjacent() -> Json {
+        Json::with_tagging(EnumTagging::Adjacent {
+            tag: "t",
+            content: "c",
+        })
+    }
+
+    fn as_value(bytes: &[u8]) -> serde_json::Value {
+        serde_json::from_slice(bytes).expect("output is not valid JSON")
+    }
+
+    #[test]
+    fn external_tagging_shape() {
+        let bytes = Json::default()
+            .serialize(&drawing())
+            .expect("could not serialize");
+        assert_eq!(
+            as_value(&bytes),
+            json!({
+                "shapes": ["Empty", {"Circle": 1}, {"Line": [-1, 2]}, {"Rect": {"w": 4, "h": 5}}],
+                "fill": "Empty",
+                "by_id": {"7": {"Circle": 3}},
+            })
+        );
+        let back: Drawing = Json::default()
+            .deserialize(&bytes[..])
+            .expect("could not deserialize");
+        assert_eq!(drawing(), back);
+    }
+
+    #[test]
+    fn adjacent_tagging_shape() {
+        let bytes = adjacent()
+            .serialize(&drawing())
+            .expect("could not serialize");
+        assert_eq!(
+            as_value(&bytes),
+            json!({
+                "shapes": [
+                    {"t": "Empty"},
+                    {"t": "Circle", "c": 1},
+                    {"t": "Line", "c": [-1, 2]},
+                    {"t": "Rect", "c": {"w": 4, "h": 5}},
+                ],
+                "fill": {"t": "Empty"},
+                "by_id": {"7": {"t": "Circle", "c": 3}},
+            })
+        );
+        let back: Drawing = adjacent()
+            .deserialize(&bytes[..])
+            .expect("could not deserialize");
+        assert_eq!(drawing(), back);
+    }
+
+    #[test]
+    fn adjacent_tagging_rejects_external_layout() {
+        let bytes = Json::default()
+            .serialize(&drawing())
+            .expect("could not serialize");
+        let res: crate::DeSerResult<Drawing> = adjacent().deserialize(&bytes[..]);
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn database_with_adjacent_tagging() {
+        let db = MemoryDatabase::<Vec<Shape>, Json>::memory(vec![Shape::Line(3, 4)])
+            .expect("could not create database")
+            .with_deser(adjacent());
+        db.save().await.expect("could not save");
+        db.write(Vec::clear).await.expect("could not write");
+        db.load().await.expect("could not load");
+        assert_eq!(
+            vec![Shape::Line(3, 4)],
+            db.get_data(false).await.expect("could not get data")
+        );
+    }
+
+    #[test]
+    fn canonical_form_is_stable() {
+        let mut first = serde_json::Map::new();
+        first.insert("name".to_owned(), json!("db"));
+        first.insert("sizes".to_owned(), json!([1.5, 2, {"z": null, "a": true}]));
+        let mut second = serde_json::Map::new();
+        second.insert("sizes".to_owned(), json!([1.5, 2, {"a": true, "z": null}]));
+        second.insert("name".to_owned(), json!("db"));
+
+        let first = Json::default()
+            .serialize(&serde_json::Value::Object(first))
+            .expect("could not serialize");
+        let second =
+            serde_json::to_vec(&serde_json::Value::Object(second)).expect("could not serialize");
+        let reformatted =
+            b"{ \"sizes\": [15e-1, 2, {\"a\": true, \"z\": null}],\n \"name\": \"db\" }";
+
+        let canonical = canonicalize(&first).expect("could not canonicalize");
+        assert_eq!(
+            r#"{"name":"db","sizes":[1.5,2,{"a":true,"z":null}]}"#,
+            String::from_utf8(canonical.clone()).expect("not UTF-8")
+        );
+        assert_eq!(
+            canonical,
+            canonicalize(&second).expect("could not canonicalize")
+        );
+        assert_eq!(
+            canonical,
+            canonicalize(reformatted).expect("could not canonicalize")
+        );
+        assert!(canonicalize(b"{\"a\": 1").is_err());
+    }
+}
@@ -0,0 +1,341 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checks of the nesting and aliases of text documents, run before they are
+//! parsed so that hostile input fails with an error instead of overflowing
+//! the stack or expanding without end.
+
+use crate::error;
+
+/// The nesting allowed by default, the limit of `serde_yaml` itself.
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 128;
+/// The aliases allowed by default, counting each nested expansion.
+#[cfg(feature = "yaml_enc")]
+pub(crate) const DEFAULT_MAX_ALIASES: u64 = 10_000;
+
+/// Check that the brackets of the RON document `data` are nested at most
+/// `max_depth` deep.
+///
+/// Strings, characters and comments are skipped. Unbalanced brackets are left
+/// for the parser to report.
+#[cfg(feature = "ron_enc")]
+pub(crate) fn check_ron(data: &[u8], max_depth: usize) -> error::DeSerResult<()> {
+    let exceeded = || error::DeSerError::DepthExceeded { limit: max_depth };
+    let mut depth = 0_usize;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(exceeded());
+                }
+            }
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            quote @ (b'"' | b'\'') => i = skip_quoted(data, i + 1, quote),
+            b'r' if !data[..i].last().is_some_and(|&b| is_ident(b)) => {
+                let hashes = data[i + 1..].iter().take_while(|&&b| b == b'#').count();
+                if data.get(i + 1 + hashes) == Some(&b'"') {
+                    i = skip_raw(data, i + 2 + hashes, hashes);
+                }
+            }
+            b'/' if data.get(i + 1) == Some(&b'/') => {
+                i += data[i..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .unwrap_or(data.len() - i);
+            }
+            b'/' if data.get(i + 1) == Some(&b'*') => {
+                i = data[i + 2..]
+                    .windows(2)
+                    .position(|w| w == b"*/")
+                    .map_or(data.len(), |end| i + 2 + end + 1);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "ron_enc")]
+fn is_ident(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// The index of the `quote` closing the string or character starting at
+/// `start`, honoring escapes.
+#[cfg(any(feature = "ron_enc", feature = "yaml_enc"))]
+fn skip_quoted(data: &[u8], mut i: usize, quote: u8) -> usize {
+    while i < data.len() {
+        match data[i] {
+            b'\\' => i += 1,
+            b if b == quote => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    data.len()
+}
+
+/// The index of the last byte of the raw string starting at `start`, closed
+/// by a quote followed by `hashes` hashes.
+#[cfg(feature = "ron_enc")]
+fn skip_raw(data: &[u8], start: usize, hashes: usize) -> usize {
+    let mut i = start;
+    while i < data.len() {
+        if data[i] == b'"'
+            && data[i + 1..]
+                .iter()
+                .take(hashes)
+                .filter(|&&b| b == b'#')
+                .count()
+                == hashes
+        {
+            return i + hashes;
+        }
+        i += 1;
+    }
+    data.len()
+}
+
+/// Check that the collections of the YAML document `text` are nested at most
+/// `max_depth` deep, and that resolving its aliases expands at most
+/// `max_aliases` of them.
+///
+/// An alias to a node containing aliases counts those as well, every time it
+/// is resolved, which is what makes "billion laughs" documents explode.
+/// Documents that don't parse are left for the parser to report.
+#[cfg(feature = "yaml_enc")]
+pub(crate) fn check_yaml(text: &str, max_depth: usize, max_aliases: u64) -> error::DeSerResult<()> {
+    use std::collections::HashMap;
+    use yaml_rust::parser::{Event, Parser};
+
+    // The scanner looks ahead past the events and gives up on flow
+    // collections nested more than 255 deep, before they are seen.
+    check_yaml_flow(text.as_bytes(), max_depth.min(u8::MAX.into()))?;
+
+    // The aliases expanded by each open collection, and its anchor.
+    let mut open: Vec<(usize, u64)> = Vec::new();
+    // The aliases expanded by resolving each anchor.
+    let mut anchors: HashMap<usize, u64> = HashMap::new();
+    let mut expanded = 0_u64;
+    let mut parser = Parser::new(text.chars());
+    loop {
+        let Ok((event, _)) = parser.next() else {
+            return Ok(());
+        };
+        match event {
+            Event::StreamEnd => return Ok(()),
+            Event::SequenceStart(anchor) | Event::MappingStart(anchor) => {
+                open.push((anchor, 0));
+                if open.len() > max_depth {
+                    return Err(error::DeSerError::DepthExceeded { limit: max_depth });
+                }
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((anchor, aliases)) = open.pop() {
+                    if anchor != 0 {
+                        anchors.insert(anchor, aliases);
+                    }
+                    if let Some((_, parent)) = open.last_mut() {
+                        *parent = parent.saturating_add(aliases);
+                    }
+                }
+            }
+            Event::Scalar(_, _, anchor, _) if anchor != 0 => {
+                anchors.insert(anchor, 0);
+            }
+            Event::Alias(anchor) => {
+                let cost = anchors.get(&anchor).copied().unwrap_or(0).saturating_add(1);
+                expanded = expanded.saturating_add(cost);
+                if expanded > max_aliases {
+                    return Err(error::DeSerError::AliasesExceeded { limit: max_aliases });
+                }
+                if let Some((_, aliases)) = open.last_mut() {
+                    *aliases = aliases.saturating_add(cost);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Check that the flow collections of the YAML document `text`, in brackets
+/// and braces, are nested at most `max_depth` deep.
+///
+/// Quoted scalars, comments and block scalars are skipped. This only
+/// approximates the grammar of YAML: brackets in plain scalars are counted as
+/// well, and what it misjudges is left for the parser to report.
+#[cfg(feature = "yaml_enc")]
+fn check_yaml_flow(text: &[u8], max_depth: usize) -> error::DeSerResult<()> {
+    let mut depth = 0_usize;
+    let mut i = 0;
+    while i < text.len() {
+        // Where a quoted scalar, a comment or a block scalar can start.
+        let separated = i == 0 || b" \t\r\n[{,:".contains(&text[i - 1]);
+        match text[i] {
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(error::DeSerError::DepthExceeded { limit: max_depth });
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            b'"' if separated => i = skip_quoted(text, i + 1, b'"'),
+            b'\'' if separated => i = skip_single_quoted(text, i + 1),
+            b'#' if separated => {
+                i += text[i..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .unwrap_or(text.len() - i);
+            }
+            b'|' | b'>' if separated && depth == 0 => i = skip_block_scalar(text, i),
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// The index of the quote closing the single-quoted scalar starting at
+/// `start`, where two quotes stand for one.
+#[cfg(feature = "yaml_enc")]
+fn skip_single_quoted(text: &[u8], mut i: usize) -> usize {
+    while i < text.len() {
+        if text[i] == b'\'' {
+            if text.get(i + 1) != Some(&b'\'') {
+                return i;
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+    text.len()
+}
+
+/// The index of the last byte of the block scalar whose indicator is at `i`:
+/// the lines after it which are blank or indented deeper than its own.
+#[cfg(feature = "yaml_enc")]
+fn skip_block_scalar(text: &[u8], i: usize) -> usize {
+    let indent = |line: &[u8]| line.iter().take_while(|&&b| b == b' ').count();
+    let line_start = text[..i]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let own = indent(&text[line_start..]);
+    let mut end = text[i..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(text.len(), |newline| i + newline);
+    while end < text.len() {
+        let line = &text[end + 1..];
+        let line = &line[..line.iter().position(|&b| b == b'\n').unwrap_or(line.len())];
+        let blank = line.iter().all(|&b| b == b' ' || b == b'\r');
+        if !blank && indent(line) <= own {
+            break;
+        }
+        end += 1 + line.len();
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::DeSerError;
+
+    #[cfg(feature = "ron_enc")]
+    #[test]
+    fn ron_depth_skips_strings_and_comments() {
+        use super::check_ron;
+
+        let nested = b"(a: [\"((((\", '[', r#\"{{\"#], // ((((\n /* [[[[ */ b: {})";
+        assert!(check_ron(nested, 2).is_ok());
+        assert!(matches!(
+            check_ron(nested, 1),
+            Err(DeSerError::DepthExceeded { limit: 1 })
+        ));
+    }
+
+    #[cfg(feature = "ron_enc")]
+    #[test]
+    fn ron_deeply_nested_input_is_bounded() {
+        use crate::deser::{DeSerializer, Ron, RonOptions};
+
+        let hostile = "[".repeat(1_000_000);
+        let res: Result<Vec<u32>, _> = Ron.deserialize(hostile.as_bytes());
+        assert!(matches!(res, Err(DeSerError::DepthExceeded { limit: 128 })));
+
+        let shallow = RonOptions::default().with_max_depth(2);
+        let res: Result<Vec<Vec<u32>>, _> = shallow.deserialize(&b"[[1], [2, 3]]"[..]);
+        assert_eq!(
+            vec![vec![1], vec![2, 3]],
+            res.expect("could not deserialize")
+        );
+        let res: Result<Vec<Vec<Vec<u32>>>, _> = shallow.deserialize(&b"[[[1]]]"[..]);
+        assert!(matches!(res, Err(DeSerError::DepthExceeded { limit: 2 })));
+    }
+
+    #[cfg(feature = "yaml_enc")]
+    #[test]
+    fn yaml_flow_depth_skips_scalars_and_comments() {
+        use super::check_yaml_flow;
+
+        let nested = b"a: [\"[[[[\", '{{''{{', x] # [[[[\nb: |\n  [[[[\n\n  {{\nc: {d: [1]}\n";
+        assert!(check_yaml_flow(nested, 2).is_ok());
+        assert!(matches!(
+            check_yaml_flow(nested, 1),
+            Err(DeSerError::DepthExceeded { limit: 1 })
+        ));
+    }
+
+    #[cfg(feature = "yaml_enc")]
+    #[test]
+    fn yaml_deeply_nested_input_is_bounded() {
+        use crate::deser::{DeSerializer, Yaml, YamlOptions};
+
+        let hostile = "[".repeat(1_000_000);
+        let res: Result<Vec<u32>, _> = Yaml.deserialize(hostile.as_bytes());
+        assert!(matches!(res, Err(DeSerError::DepthExceeded { limit: 128 })));
+        let res: Result<Vec<u32>, _> = Yaml.deserialize("- ".repeat(1_000_000).as_bytes());
+        assert!(matches!(res, Err(DeSerError::DepthExceeded { limit: 128 })));
+        let deep = YamlOptions::default().with_max_depth(1_000);
+        let res: Result<Vec<u32>, _> = deep.deserialize(hostile.as_bytes());
+        assert!(matches!(res, Err(DeSerError::DepthExceeded { limit: 255 })));
+
+        let shallow = YamlOptions::default().with_max_depth(2);
+        let res: Result<Vec<Vec<u32>>, _> = shallow.deserialize(&b"- [1]\n- [2, 3]\n"[..]);
+        assert_eq!(
+            vec![vec![1], vec![2, 3]],
+            res.expect("could not deserialize")
+        );
+        let res: Result<Vec<Vec<Vec<u32>>>, _> = shallow.deserialize(&b"- - [1]\n"[..]);
+        assert!(matches!(res, Err(DeSerError::DepthExceeded { limit: 2 })));
+    }
+
+    #[cfg(feature = "yaml_enc")]
+    #[test]
+    fn yaml_billion_laughs_is_bounded() {
+        use crate::deser::{DeSerializer, Yaml};
+        use std::fmt::Write;
+
+        let mut laughs = String::from("a: &a [lol, lol, lol, lol, lol, lol, lol, lol, lol]\n");
+        for (name, previous) in ["b", "c", "d", "e", "f", "g", "h", "i"]
+            .iter()
+            .zip(["a", "b", "c", "d", "e", "f", "g", "h"])
+        {
+            let aliases = vec![format!("*{previous}"); 9].join(", ");
+            writeln!(laughs, "{name}: &{name} [{aliases}]").expect("could not write");
+        }
+        let res: Result<serde_yaml::Value, _> = Yaml.deserialize(laughs.as_bytes());
+        assert!(matches!(
+            res,
+            Err(DeSerError::AliasesExceeded { limit: 10_000 })
+        ));
+
+        let friendly = "base: &base {x: 1}\nfirst: *base\nsecond: *base\n";
+        let res: Result<serde_yaml::Value, _> = Yaml.deserialize(friendly.as_bytes());
+        assert!(res.is_ok());
+    }
+}
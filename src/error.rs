@@ -5,7 +5,7 @@
 /// An error returned by a `DeSer` implementor
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
-#[allow(clippy::empty_enum)] // This can occur when no desers have beeen enabled
+#[allow(clippy::empty_enums)] // This can occur when no desers have beeen enabled
 pub enum DeSerError {
     #[cfg(feature = "yaml_enc")]
     /// An error occured with Yaml
@@ -15,10 +15,57 @@ pub enum DeSerError {
     /// An error occured with Ron
     #[error("An error with Ron occured")]
     Ron(#[from] ron::Error),
+    #[cfg(feature = "json_enc")]
+    /// An error occured with JSON
+    #[error("An error with JSON occured")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "json_enc")]
+    /// There is no value at the JSON pointer given to `Database::read_pointer`
+    #[error("No value found at the JSON pointer {0:?}")]
+    PointerNotFound(String),
     #[cfg(feature = "bin_enc")]
     /// An error occured with Bincode
     #[error("An error with Bincode occured")]
     Bincode(#[from] std::boxed::Box<bincode::ErrorKind>),
+    /// The data could not be parsed, at the given position of the text
+    ///
+    /// Returned by the text formats that know where parsing stopped, instead
+    /// of their own error. Lines and columns start at 1.
+    #[error("Parse error at line {line}, column {column}: {message}")]
+    Parse {
+        /// The line of the error
+        line: usize,
+        /// The column of the error, within the line
+        column: usize,
+        /// What went wrong
+        message: String,
+    },
+    /// An element of a `Framed` sequence doesn't match its checksum
+    #[error("The element at index {index} does not match its checksum")]
+    ChecksumMismatch {
+        /// The index of the element in the sequence
+        index: usize,
+    },
+    /// The data is nested deeper than the deserializer allows
+    ///
+    /// See `RonOptions` and `YamlOptions`.
+    #[error("The data is nested deeper than the limit of {limit}")]
+    DepthExceeded {
+        /// The nesting allowed
+        limit: usize,
+    },
+    /// The aliases of the data expand to more nodes than the deserializer
+    /// allows
+    ///
+    /// See `YamlOptions`.
+    #[error("The aliases of the data expand beyond the limit of {limit}")]
+    AliasesExceeded {
+        /// The alias expansions allowed
+        limit: u64,
+    },
+    /// An I/O error occured while reading or writing the serialized data
+    #[error("An I/O error occured while reading or writing the serialized data")]
+    Io(#[from] std::io::Error),
     /// An internal error to Rustbreak occured
     #[error("An internal error to rustbreak occured, please report it to the maintainers")]
     Internal(String),
@@ -46,6 +93,101 @@ pub enum BackendError {
     /// An internal error to Rustbreak occured
     #[error("An internal error to rustbreak occured, please report it to the maintainers")]
     Internal(String),
+    /// The backend does not support this operation
+    ///
+    /// Returned by the default implementations of the optional methods of
+    /// `Backend`, such as `Backend::append_data`.
+    #[error("The backend does not support {operation}")]
+    Unsupported {
+        /// The name of the unsupported method
+        operation: &'static str,
+    },
+    /// The database is read-only since a save failed, see
+    /// `Database::with_degraded_mode`
+    ///
+    /// Saves fail with this error without reaching the backend until
+    /// `Database::recover` succeeds.
+    #[error("The database is read-only since a save failed: {cause}")]
+    Degraded {
+        /// The error of the save that failed
+        cause: String,
+    },
+    /// A `FencedBackend` refused to overwrite data written by a newer writer
+    ///
+    /// The data was written again since this writer last read it. Loading it
+    /// again picks up the newer generation.
+    #[error("The data was written by a newer writer (generation {theirs}, ours is {ours})")]
+    StaleGeneration {
+        /// The generation this writer last read or wrote
+        ours: u64,
+        /// The generation found in the backend
+        theirs: u64,
+    },
+    /// `PathBackend::rename_to` can't move the file to another file system
+    ///
+    /// Renaming a file is only atomic within one file system. The file was
+    /// left where it was.
+    #[error("Can't move {} to {}, which is on another file system", .from.display(), .to.display())]
+    CrossDevice {
+        /// The path of the file
+        from: std::path::PathBuf,
+        /// The path it was to be moved to
+        to: std::path::PathBuf,
+    },
+    #[cfg(feature = "schema_validation")]
+    /// The data does not match the schema given to `Database::with_validation`
+    /// and was not saved
+    #[error("The data does not match the schema")]
+    ValidationFailed {
+        /// Where and how the data does not match, one entry per mismatch
+        errors: Vec<String>,
+    },
+    #[cfg(feature = "schema_validation")]
+    /// The schema given to `Database::with_validation` is not a valid JSON Schema
+    #[error("Invalid JSON Schema: {0}")]
+    InvalidSchema(String),
+    #[cfg(feature = "signed")]
+    /// The signature stored by a `SignedBackend` is missing or does not match
+    /// the data
+    #[error("The signature of the data is missing or invalid")]
+    SignatureInvalid,
+    #[cfg(feature = "encryption")]
+    /// The data of an `EncryptedBackend` could not be decrypted, the key or
+    /// passphrase is wrong or the data was changed
+    #[error("The data could not be decrypted")]
+    Decryption,
+    #[cfg(feature = "cacache")]
+    /// The data read from the cache doesn't match its checksum
+    #[error("The data in the cache does not match its checksum")]
+    ChecksumMismatch,
+    #[cfg(feature = "cacache")]
+    /// An error occured in the cache of a `CacacheBackend`
+    #[error("An error occured in the cache")]
+    Cacache(#[source] cacache::Error),
+    #[cfg(feature = "zip")]
+    /// The archive of a `ZipBackend` could not be read or written
+    #[error("An error occured in the zip archive")]
+    Zip(#[source] zip::result::ZipError),
+    #[cfg(feature = "object_store")]
+    /// The object store of an `ObjectStoreChunkedBackend` returned an error
+    #[error("An error occured in the object store")]
+    ObjectStore(#[source] object_store::Error),
+    #[cfg(feature = "sqlite")]
+    /// An error occured in the SQLite database of a `SqliteBackend`
+    #[error("An error occured in the SQLite database")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "sled")]
+    /// An error occured in the sled database of a `SledBackend`
+    #[error("An error occured in the sled database")]
+    Sled(#[from] sled::Error),
+    #[cfg(feature = "grpc")]
+    /// The storage service answered with an error status
+    #[error("The storage service returned an error")]
+    Grpc(#[from] Box<tonic::Status>),
+    #[cfg(feature = "grpc")]
+    /// The connection to the storage service failed
+    #[error("Could not connect to the storage service")]
+    GrpcTransport(#[from] tonic::transport::Error),
     #[cfg(feature = "other_errors")]
     /// A dynamic error occured
     ///
@@ -61,7 +203,7 @@ pub enum BackendError {
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum RustbreakError {
-    /// A context error when a DeSerialization failed
+    /// A context error when a `DeSerialization` failed
     #[error("Could not deserialize the value")]
     DeSerialization(#[from] DeSerError),
     /// This error is returned if the `Database` is poisoned. See
@@ -77,6 +219,11 @@ pub enum RustbreakError {
     WritePanic,
 }
 
+/// Returned by `Database::try_write` when the data is locked by someone else
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("The data is locked")]
+pub struct WouldBlock;
+
 /// A simple type alias for errors
 pub type Result<T> = std::result::Result<T, RustbreakError>;
 /// The type alias used for backends
@@ -0,0 +1,227 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Closures run on the data of a [`Database`] around its saves and loads.
+
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backend::Backend;
+use crate::{Database, DeSerializer};
+
+/// A closure mutating the data.
+type Hook<T> = Box<dyn FnMut(&mut T) + Send>;
+
+/// The hooks registered on a [`Database`].
+pub(crate) struct Hooks<T> {
+    before_save: Mutex<Option<Hook<T>>>,
+    after_load: Mutex<Option<Hook<T>>>,
+}
+
+impl<T> Default for Hooks<T> {
+    fn default() -> Self {
+        Self {
+            before_save: Mutex::new(None),
+            after_load: Mutex::new(None),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Hooks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("before_save", &self.has_before_save())
+            .field("after_load", &is_set(&self.after_load))
+            .finish()
+    }
+}
+
+/// Whether a hook is registered in `slot`.
+fn is_set<T>(slot: &Mutex<Option<Hook<T>>>) -> bool {
+    slot.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_some()
+}
+
+/// Run the hook registered in `slot` on `data`, if any.
+fn run<T>(slot: &Mutex<Option<Hook<T>>>, data: &mut T) {
+    let mut hook = slot.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(hook) = hook.as_mut() {
+        hook(data);
+    }
+}
+
+/// Register `hook` in `slot`, replacing the one before.
+fn set<T>(slot: &Mutex<Option<Hook<T>>>, hook: Option<Hook<T>>) {
+    *slot.lock().unwrap_or_else(PoisonError::into_inner) = hook;
+}
+
+impl<T> Hooks<T> {
+    /// Whether a hook is run before saving.
+    pub(crate) fn has_before_save(&self) -> bool {
+        is_set(&self.before_save)
+    }
+
+    /// Run the hook registered with [`Database::on_before_save`] on `data`,
+    /// if any.
+    pub(crate) fn before_save(&self, data: &mut T) {
+        run(&self.before_save, data);
+    }
+
+    /// Run the hook registered with [`Database::on_after_load`] on `data`,
+    /// if any.
+    pub(crate) fn after_load(&self, data: &mut T) {
+        run(&self.after_load, data);
+    }
+}
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Run `hook` on the data right before it is serialized, on every save.
+    ///
+    /// This is the place for what has to change whenever the data is
+    /// persisted, like a revision counter or a "last modified" timestamp.
+    /// The changes are made to the data in memory, so they are seen by reads
+    /// after the save. Replaces the hook registered before, if any.
+    ///
+    /// Saves take the write lock on the data while a hook is registered.
+    /// With a merge strategy (see [`Database::with_merge_strategy`]) the hook
+    /// runs on the data in memory, before it is merged.
+    pub fn on_before_save<F>(&self, hook: F)
+    where
+        F: FnMut(&mut Data) + Send + 'static,
+    {
+        set(&self.hooks.before_save, Some(Box::new(hook)));
+    }
+
+    /// Remove the hook registered with [`Database::on_before_save`].
+    pub fn clear_before_save(&self) {
+        set(&self.hooks.before_save, None);
+    }
+
+    /// Run `hook` on the data right after it is deserialized, on every load.
+    ///
+    /// This covers [`Database::load`], [`Database::get_data`] with `load`
+    /// true, [`Database::compact_now`], [`Database::load_version`], and
+    /// with their features `Database::load_from_bytes` and
+    /// `Database::load_skip_invalid`. It is the place to normalize the
+    /// data, migrate it in memory, or fill in fields that aren't stored. The
+    /// hook runs before the loaded data replaces the data in memory, so
+    /// readers never see it unprocessed. Replaces the hook registered before,
+    /// if any.
+    ///
+    /// Loads done by the constructors, before a hook can be registered, are
+    /// not covered.
+    pub fn on_after_load<F>(&self, hook: F)
+    where
+        F: FnMut(&mut Data) + Send + 'static,
+    {
+        set(&self.hooks.after_load, Some(Box::new(hook)));
+    }
+
+    /// Remove the hook registered with [`Database::on_after_load`].
+    pub fn clear_after_load(&self) {
+        set(&self.hooks.after_load, None);
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use crate::backend::MemoryBackend;
+    use crate::deser::Ron;
+    use crate::Database;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    struct Doc {
+        revision: u32,
+        text: String,
+        /// Derived from `text`, not stored.
+        #[serde(skip)]
+        words: usize,
+    }
+
+    type Db = Database<Doc, MemoryBackend, Ron>;
+
+    /// The data last saved to the backend of `db`.
+    async fn persisted(db: &Db) -> Doc {
+        db.put_data(Doc::default(), false)
+            .await
+            .expect("could not put data");
+        db.load().await.expect("could not load");
+        db.get_data(false).await.expect("no data")
+    }
+
+    #[tokio::test]
+    async fn before_save_bumps_revision() {
+        let db = Db::memory(Doc::default()).expect("could not create db");
+        db.on_before_save(|doc| doc.revision += 1);
+
+        db.write(|doc| doc.text = "hello".to_owned())
+            .await
+            .expect("could not write");
+        db.save().await.expect("could not save");
+        assert_eq!(1, db.read(|doc| doc.revision).await.expect("no data"));
+        db.save().await.expect("could not save");
+        let saved = Doc {
+            revision: 2,
+            text: "hello".to_owned(),
+            words: 0,
+        };
+        assert_eq!(saved, persisted(&db).await);
+
+        db.clear_before_save();
+        db.save().await.expect("could not save");
+        assert_eq!(saved, persisted(&db).await);
+    }
+
+    #[tokio::test]
+    async fn after_load_fills_derived_field() {
+        let db = Db::memory(Doc::default()).expect("could not create db");
+        db.on_after_load(|doc| doc.words = doc.text.split_whitespace().count());
+        db.put_data(
+            Doc {
+                revision: 1,
+                text: "three little words".to_owned(),
+                words: 0,
+            },
+            true,
+        )
+        .await
+        .expect("could not put data");
+        assert_eq!(0, db.read(|doc| doc.words).await.expect("no data"));
+
+        db.load().await.expect("could not load");
+        assert_eq!(3, db.read(|doc| doc.words).await.expect("no data"));
+
+        db.clear_after_load();
+        db.load().await.expect("could not load");
+        assert_eq!(0, db.read(|doc| doc.words).await.expect("no data"));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[tokio::test]
+    async fn after_load_runs_on_bytes() {
+        let db = Db::memory(Doc {
+            revision: 1,
+            text: "two words".to_owned(),
+            words: 0,
+        })
+        .expect("could not create db");
+        let bytes = db.save_to_bytes().await.expect("could not save to bytes");
+        db.on_after_load(|doc| doc.words = doc.text.split_whitespace().count());
+
+        db.load_from_bytes(bytes)
+            .await
+            .expect("could not load from bytes");
+        assert_eq!(2, db.read(|doc| doc.words).await.expect("no data"));
+    }
+}
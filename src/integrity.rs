@@ -0,0 +1,330 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A full check of the data stored by the backend of a [`Database`].
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backend::{Backend, BackendCapabilities};
+use crate::error::{self, BackendError};
+use crate::{Database, DeSerializer};
+
+/// The outcome of one of the checks of an [`IntegrityReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    /// The check passed.
+    Passed,
+    /// The check failed, for the given reason.
+    Failed(String),
+    /// The check doesn't apply to the backend, or couldn't run because an
+    /// earlier check failed.
+    Skipped,
+}
+
+impl CheckOutcome {
+    /// Whether the check failed.
+    #[must_use]
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed(_))
+    }
+}
+
+/// The result of [`Database::verify_integrity`], one outcome per check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// The backend returned the data.
+    pub readable: CheckOutcome,
+    /// The data matches its checksum, for backends storing one and for
+    /// formats storing one, like [`Framed`](crate::deser::Framed).
+    pub checksum: CheckOutcome,
+    /// The data matches its signature, for backends signing or
+    /// authenticating it.
+    pub signature: CheckOutcome,
+    /// The data deserializes into the type of the database.
+    pub deserializes: CheckOutcome,
+    /// Serializing the deserialized data again gives the same bytes.
+    pub round_trip: CheckOutcome,
+}
+
+impl IntegrityReport {
+    /// The checks with their names, in the order they ran.
+    #[must_use]
+    pub fn checks(&self) -> [(&'static str, &CheckOutcome); 5] {
+        [
+            ("readable", &self.readable),
+            ("checksum", &self.checksum),
+            ("signature", &self.signature),
+            ("deserializes", &self.deserializes),
+            ("round_trip", &self.round_trip),
+        ]
+    }
+
+    /// Whether no check failed.
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.checks()
+            .iter()
+            .all(|(_, outcome)| !outcome.is_failed())
+    }
+}
+
+/// Whether `error` is a failed check of the checksum or the signature, and
+/// not of reading the data.
+fn verification_failure(error: &BackendError) -> Option<BackendCapabilities> {
+    match error {
+        #[cfg(feature = "cacache")]
+        BackendError::ChecksumMismatch => Some(BackendCapabilities::CHECKSUM),
+        #[cfg(feature = "signed")]
+        BackendError::SignatureInvalid => Some(BackendCapabilities::AUTHENTICATED),
+        #[cfg(feature = "encryption")]
+        BackendError::Decryption => Some(BackendCapabilities::AUTHENTICATED),
+        _ => None,
+    }
+}
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Check the data stored by the backend, like `fsck` checks a file
+    /// system.
+    ///
+    /// This reads the data from the backend and checks, in order, that it
+    /// could be read, that it matches its checksum and its signature if the
+    /// backend verifies them (see [`BackendCapabilities::CHECKSUM`] and
+    /// [`BackendCapabilities::AUTHENTICATED`]), that it matches the checksums
+    /// the format stores if any (see [`DeSerializer::verify_checksum`]), that
+    /// it deserializes, and
+    /// that serializing it again gives the same bytes. The last check fails
+    /// for data edited by hand or written by another version of the
+    /// serializer, which the next save will rewrite.
+    ///
+    /// Failed checks are listed in the report rather than returned as
+    /// errors. The data in memory is left as it is.
+    pub async fn verify_integrity(&self) -> error::Result<IntegrityReport> {
+        let mut report = IntegrityReport {
+            readable: CheckOutcome::Skipped,
+            checksum: CheckOutcome::Skipped,
+            signature: CheckOutcome::Skipped,
+            deserializes: CheckOutcome::Skipped,
+            round_trip: CheckOutcome::Skipped,
+        };
+        let mut backend = self.backend.lock().await;
+        let capabilities = backend.capabilities();
+        let verified = |capability| {
+            if capabilities.contains(capability) {
+                CheckOutcome::Passed
+            } else {
+                CheckOutcome::Skipped
+            }
+        };
+
+        let bytes = match backend.get_data().await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                match verification_failure(&e) {
+                    Some(check) => {
+                        report.readable = CheckOutcome::Passed;
+                        let failed = CheckOutcome::Failed(e.to_string());
+                        if check == BackendCapabilities::CHECKSUM {
+                            report.checksum = failed;
+                        } else {
+                            report.checksum = verified(BackendCapabilities::CHECKSUM);
+                            report.signature = failed;
+                        }
+                    }
+                    None => report.readable = CheckOutcome::Failed(e.to_string()),
+                }
+                return Ok(report);
+            }
+        };
+        drop(backend);
+        report.readable = CheckOutcome::Passed;
+        report.signature = verified(BackendCapabilities::AUTHENTICATED);
+        report.checksum = match self.deser.verify_checksum(&bytes) {
+            Some(Ok(())) => CheckOutcome::Passed,
+            Some(Err(e)) => CheckOutcome::Failed(e.to_string()),
+            None => verified(BackendCapabilities::CHECKSUM),
+        };
+        if report.checksum.is_failed() {
+            return Ok(report);
+        }
+
+        let data = match self.deser.deserialize(&bytes[..]) {
+            Ok(data) => data,
+            Err(e) => {
+                report.deserializes = CheckOutcome::Failed(e.to_string());
+                return Ok(report);
+            }
+        };
+        report.deserializes = CheckOutcome::Passed;
+
+        report.round_trip = match self.deser.serialize(&data) {
+            Ok(again) if again == bytes => CheckOutcome::Passed,
+            Ok(again) => CheckOutcome::Failed(format!(
+                "the data serializes to {} bytes differing from the {} bytes stored",
+                again.len(),
+                bytes.len()
+            )),
+            Err(e) => CheckOutcome::Failed(e.to_string()),
+        };
+        Ok(report)
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use super::CheckOutcome;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::deser::Ron;
+    use crate::error::{self, BackendError};
+    use crate::Database;
+
+    type Db<B> = Database<Vec<u32>, B, Ron>;
+
+    fn stored(bytes: &[u8]) -> Db<MemoryBackend> {
+        Db::from_parts(Vec::new(), MemoryBackend::from_vec(bytes.to_vec()), Ron)
+    }
+
+    #[tokio::test]
+    async fn healthy_data_passes() {
+        let db = Db::memory(vec![1, 2, 3]).expect("could not create db");
+        db.save().await.expect("could not save");
+        let report = db.verify_integrity().await.expect("could not verify");
+        assert!(report.is_healthy());
+        assert_eq!(CheckOutcome::Passed, report.readable);
+        assert_eq!(CheckOutcome::Skipped, report.checksum);
+        assert_eq!(CheckOutcome::Skipped, report.signature);
+        assert_eq!(CheckOutcome::Passed, report.deserializes);
+        assert_eq!(CheckOutcome::Passed, report.round_trip);
+    }
+
+    #[tokio::test]
+    async fn unreadable_backend_fails() {
+        #[derive(Debug)]
+        struct Broken;
+
+        impl Backend for Broken {
+            async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+                Err(BackendError::Internal("disk on fire".into()))
+            }
+
+            async fn put_data(&mut self, _data: &[u8]) -> error::BackendResult<()> {
+                Ok(())
+            }
+        }
+
+        let db = Db::from_parts(Vec::new(), Broken, Ron);
+        let report = db.verify_integrity().await.expect("could not verify");
+        assert!(report.readable.is_failed());
+        assert_eq!(CheckOutcome::Skipped, report.deserializes);
+        assert!(!report.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn invalid_payload_fails_to_deserialize() {
+        let report = stored(b"[1, 2,")
+            .verify_integrity()
+            .await
+            .expect("could not verify");
+        assert_eq!(CheckOutcome::Passed, report.readable);
+        assert!(report.deserializes.is_failed());
+        assert_eq!(CheckOutcome::Skipped, report.round_trip);
+    }
+
+    #[tokio::test]
+    async fn hand_edited_payload_fails_round_trip() {
+        let report = stored(b"[1,    2]")
+            .verify_integrity()
+            .await
+            .expect("could not verify");
+        assert_eq!(CheckOutcome::Passed, report.deserializes);
+        assert!(report.round_trip.is_failed());
+    }
+
+    #[tokio::test]
+    async fn framed_checksums_are_checked() {
+        use crate::deser::Framed;
+
+        let db = Database::<Vec<u32>, _, Framed<Ron>>::memory(vec![1, 2, 3])
+            .expect("could not create db");
+        db.save().await.expect("could not save");
+        let report = db.verify_integrity().await.expect("could not verify");
+        assert!(report.is_healthy());
+        assert_eq!(CheckOutcome::Passed, report.checksum);
+
+        // Still a valid element, only the checksum tells it was changed.
+        let (_, backend, _) = db.into_inner().expect("could not take the backend");
+        let mut bytes = crate::backend::Backend::get_data(&mut { backend })
+            .await
+            .expect("could not get data");
+        let last = bytes.len() - 1;
+        assert_eq!(b'3', bytes[last]);
+        bytes[last] = b'4';
+        let report = Database::<Vec<u32>, _, Framed<Ron>>::from_parts(
+            Vec::new(),
+            MemoryBackend::from_vec(bytes),
+            Framed(Ron),
+        )
+        .verify_integrity()
+        .await
+        .expect("could not verify");
+        assert_eq!(CheckOutcome::Passed, report.readable);
+        assert!(report.checksum.is_failed());
+        assert_eq!(CheckOutcome::Skipped, report.deserializes);
+    }
+
+    #[cfg(feature = "signed")]
+    #[tokio::test]
+    async fn bad_signature_fails() {
+        use crate::backend::SignedBackend;
+
+        let mut backend = SignedBackend::new(MemoryBackend::new(), MemoryBackend::new(), "key");
+        backend.put_data(b"[1]").await.expect("could not put data");
+        let (data, _) = backend.into_inner();
+        let forged = SignedBackend::new(data, MemoryBackend::from_vec(vec![0; 32]), "key");
+
+        let report = Db::from_parts(Vec::new(), forged, Ron)
+            .verify_integrity()
+            .await
+            .expect("could not verify");
+        assert_eq!(CheckOutcome::Passed, report.readable);
+        assert!(report.signature.is_failed());
+        assert_eq!(CheckOutcome::Skipped, report.deserializes);
+    }
+
+    #[cfg(feature = "cacache")]
+    #[tokio::test]
+    async fn bad_checksum_fails() {
+        use crate::backend::BackendCapabilities;
+
+        #[derive(Debug)]
+        struct Corrupted;
+
+        impl Backend for Corrupted {
+            async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+                Err(BackendError::ChecksumMismatch)
+            }
+
+            async fn put_data(&mut self, _data: &[u8]) -> error::BackendResult<()> {
+                Ok(())
+            }
+
+            fn capabilities(&self) -> BackendCapabilities {
+                BackendCapabilities::CHECKSUM
+            }
+        }
+
+        let report = Db::from_parts(Vec::new(), Corrupted, Ron)
+            .verify_integrity()
+            .await
+            .expect("could not verify");
+        assert_eq!(CheckOutcome::Passed, report.readable);
+        assert!(report.checksum.is_failed());
+        assert_eq!(CheckOutcome::Skipped, report.signature);
+    }
+}
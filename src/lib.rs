@@ -21,8 +21,8 @@
     clippy::print_stdout,
     clippy::todo,
     //clippy::unwrap_used, // not yet in stable
-    clippy::wrong_pub_self_convention
 )]
+#![cfg_attr(test, allow(clippy::panic))]
 #![warn(clippy::pedantic)]
 // part of `clippy::pedantic`, causing many warnings
 #![allow(clippy::missing_errors_doc, clippy::module_name_repetitions)]
@@ -74,58 +74,56 @@
 //! Add this to your `Cargo.toml`:
 //!
 //! ```toml
-//! [dependencies.rustbreak]
+//! [dependencies.dropbreak]
 //! version = "2"
 //! features = ["ron_enc"] # You can also use "yaml_enc" or "bin_enc"
 //!                        # Check the documentation to add your own!
 //! ```
 //!
 //! ```rust
-//! # extern crate rustbreak;
 //! # use std::collections::HashMap;
-//! use rustbreak::{deser::Ron, MemoryDatabase};
+//! use dropbreak::{deser::Ron, MemoryDatabase};
 //!
-//! # fn main() {
-//! # let func = || -> Result<(), Box<dyn std::error::Error>> {
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! let db = MemoryDatabase::<HashMap<u32, String>, Ron>::memory(HashMap::new())?;
 //!
 //! println!("Writing to Database");
 //! db.write(|db| {
 //!     db.insert(0, String::from("world"));
 //!     db.insert(1, String::from("bar"));
-//! });
+//! })
+//! .await?;
 //!
 //! db.read(|db| {
 //!     // db.insert("foo".into(), String::from("bar"));
 //!     // The above line will not compile since we are only reading
 //!     println!("Hello: {:?}", db.get(&0));
-//! })?;
-//! # return Ok(()); };
-//! # func().unwrap();
+//! })
+//! .await?;
+//! # Ok(())
 //! # }
 //! ```
 //!
 //! Or alternatively:
 //! ```rust
-//! # extern crate rustbreak;
 //! # use std::collections::HashMap;
-//! use rustbreak::{deser::Ron, MemoryDatabase};
+//! use dropbreak::{deser::Ron, MemoryDatabase};
 //!
-//! # fn main() {
-//! # let func = || -> Result<(), Box<dyn std::error::Error>> {
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! let db = MemoryDatabase::<HashMap<u32, String>, Ron>::memory(HashMap::new())?;
 //!
 //! println!("Writing to Database");
 //! {
-//!     let mut data = db.borrow_data_mut()?;
+//!     let mut data = db.borrow_data_mut().await;
 //!     data.insert(0, String::from("world"));
 //!     data.insert(1, String::from("bar"));
 //! }
 //!
-//! let data = db.borrow_data()?;
+//! let data = db.borrow_data().await;
 //! println!("Hello: {:?}", data.get(&0));
-//! # return Ok(()); };
-//! # func().unwrap();
+//! # Ok(())
 //! # }
 //! ```
 //!
@@ -136,7 +134,7 @@
 //! continue bubbling up said error case, or handle it yourself.
 //!
 //! ```rust
-//! use rustbreak::{deser::Ron, error::RustbreakError, MemoryDatabase};
+//! use dropbreak::{deser::Ron, error::RustbreakError, MemoryDatabase};
 //! let db = match MemoryDatabase::<usize, Ron>::memory(0) {
 //!     Ok(db) => db,
 //!     Err(e) => {
@@ -179,7 +177,33 @@
 //! - `ron_enc` which enables the [Ron][ron] de/serialization
 //! - `yaml_enc` which enables the Yaml de/serialization
 //! - `bin_enc` which enables the Bincode de/serialization
+//! - `json_enc` which enables the JSON de/serialization, with a choice of enum
+//!   tagging, and the audit trail of `Database::with_audit`
 //! - 'mmap' whhich enables memory map backend.
+//! - `grpc` which enables the [`GrpcBackend`](backend::GrpcBackend), a client
+//!   for a remote storage service
+//! - `windows` which enables the `RegistryBackend`, storing data in the
+//!   Windows registry (only on Windows)
+//! - `chrono` which enables the timestamp helpers in [`serde`](mod@serde)
+//! - `schemars` which enables [`Database::json_schema`]
+//! - `schema_validation` which enables `Database::with_validation`, checking
+//!   the data against a JSON Schema before saving
+//! - `signed` which enables the `SignedBackend`, storing an HMAC of the data
+//!   next to it to detect tampering
+//! - `debug-tee` which enables the `DebugTeeBackend`, keeping a snapshot of
+//!   every save in a directory for debugging
+//! - `sqlite` which enables the `SqliteBackend`, storing data in a row of a
+//!   SQLite table
+//! - `directories` which enables `PathBackend::from_app_dirs`, storing data in
+//!   the data directory of the platform
+//! - `encryption` which enables the `EncryptedBackend`, encrypting the data
+//!   with a key or a passphrase
+//! - `sled` which enables the `SledBackend`, storing the data in an embedded
+//!   sled database
+//! - `compression` which enables the `CompressedBackend`, compressing the
+//!   data once it is big enough
+//! - `object_store` which enables the `ObjectStoreChunkedBackend`, splitting
+//!   the data across several objects of an object store
 //!
 //! [Enable them in your `Cargo.toml` file to use them.][features] You can
 //! safely have them all turned on per-default.
@@ -191,11 +215,26 @@
 //! [ron]: https://github.com/ron-rs/ron
 //! [features]: https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html#choosing-features
 
+#[cfg(feature = "json_enc")]
+mod audit;
+mod autosave;
 pub mod backend;
+mod degraded;
 /// Different serialization and deserialization methods one can use
 pub mod deser;
 /// The rustbreak errors that can be returned
 pub mod error;
+mod hooks;
+mod integrity;
+mod map;
+mod merge;
+mod oplog;
+#[cfg(feature = "chrono")]
+pub mod serde;
+mod stats;
+#[cfg(feature = "bin_enc")]
+mod stream;
+mod view;
 
 /// The `DeSerializer` trait used by serialization structs
 pub use crate::deser::DeSerializer;
@@ -204,15 +243,27 @@ use std::fmt::Debug;
 use std::ops::Deref;
 use std::path::PathBuf;
 
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-use tokio::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use ::serde::de::DeserializeOwned;
+use ::serde::Serialize;
+use tokio::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 #[cfg(feature = "mmap")]
 use crate::backend::MmapStorage;
 use crate::backend::{Backend, FileBackend, MemoryBackend, PathBackend};
 
+#[cfg(feature = "json_enc")]
+pub use crate::audit::AuditRecord;
+pub use crate::autosave::AutosaveHandle;
 pub use crate::error::*;
+pub use crate::integrity::{CheckOutcome, IntegrityReport};
+pub use crate::merge::{LastWriteWins, MergeStrategy};
+pub use crate::oplog::{Operation, OperationKind};
+pub use crate::stats::Stats;
+pub use crate::view::{MapView, MapViewMut, VecView, VecViewMut, View, ViewMut};
+
+/// Used by [`delegate_backend!`] to name the reader and writer traits.
+#[doc(hidden)]
+pub use tokio as __tokio;
 
 /// The Central Database to Rustbreak.
 ///
@@ -234,12 +285,36 @@ pub struct Database<Data, Back, DeSer> {
     data: RwLock<Data>,
     backend: Mutex<Back>,
     deser: DeSer,
+    merge: Option<merge::Merge<Data>>,
+    hooks: hooks::Hooks<Data>,
+    stats: stats::Counters,
+    oplog: oplog::OperationLog,
+    background: autosave::BackgroundSaves,
+    degraded: degraded::Degraded,
+    #[cfg(feature = "schema_validation")]
+    validator: Option<jsonschema::Validator>,
+    #[cfg(feature = "json_enc")]
+    audit: audit::AuditTrail,
+    #[cfg(feature = "json_enc")]
+    canonical_json: bool,
 }
 
+/// The data as it was saved, for the audit trail.
+#[cfg(feature = "json_enc")]
+type AuditSnapshot = Option<serde_json::Value>;
+/// Without the `json_enc` feature there is no audit trail, nor snapshot.
+#[cfg(not(feature = "json_enc"))]
+type AuditSnapshot = Option<std::convert::Infallible>;
+
+/// What a write to the backend leaves for the audit trail: the number of
+/// bytes written, the snapshot of the data, and the backend, locked until the
+/// save was recorded.
+type Written<'a, Back> = (usize, AuditSnapshot, MutexGuard<'a, Back>);
+
 impl<Data, Back, DeSer> Database<Data, Back, DeSer>
 where
     Data: Serialize + DeserializeOwned + Clone + Send,
-    Back: Backend,
+    Back: Backend + Send,
     DeSer: DeSerializer<Data> + Send + Sync + Clone,
 {
     /// Write lock the database and get write access to the `Data` container.
@@ -263,32 +338,31 @@ where
     ///
     /// ```rust
     /// # #[macro_use] extern crate serde_derive;
-    /// # extern crate rustbreak;
+    /// # extern crate dropbreak;
     /// # extern crate serde;
     /// # extern crate tempfile;
-    /// use rustbreak::{deser::Ron, FileDatabase};
+    /// use dropbreak::{deser::Ron, FileDatabase};
     ///
     /// #[derive(Debug, Serialize, Deserialize, Clone)]
     /// struct Data {
     ///     level: u32,
     /// }
     ///
-    /// # fn main() {
-    /// # let func = || -> Result<(), Box<dyn std::error::Error>> {
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// # let file = tempfile::tempfile()?;
     /// let db = FileDatabase::<Data, Ron>::from_file(file, Data { level: 0 })?;
     ///
     /// db.write(|db| {
     ///     db.level = 42;
-    /// })?;
+    /// })
+    /// .await?;
     ///
     /// // You can also return from a `.read()`. But don't forget that you cannot return references
     /// // into the structure
-    /// let value = db.read(|db| db.level)?;
+    /// let value = db.read(|db| db.level).await?;
     /// assert_eq!(42, value);
-    /// # return Ok(());
-    /// # };
-    /// # func().unwrap();
+    /// # Ok(())
     /// # }
     /// ```
     pub async fn write<T, R>(&self, task: T) -> error::Result<R>
@@ -296,7 +370,35 @@ where
         T: FnOnce(&mut Data) -> R,
     {
         let mut lock = self.data.write().await;
-        Ok(task(&mut lock))
+        let result = task(&mut lock);
+        self.oplog.record_write();
+        Ok(result)
+    }
+
+    /// Run `task` on the data like [`Database::write`] if the data can be
+    /// write locked right away, without waiting.
+    ///
+    /// This lets latency sensitive code back off, or do something else,
+    /// instead of queueing behind the readers and writers holding the lock.
+    /// The data isn't saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`error::WouldBlock`] without running `task` if the data is
+    /// read or write locked.
+    ///
+    /// # Panics
+    ///
+    /// If you panic in the closure, the database is poisoned, like with
+    /// [`Database::write`].
+    pub fn try_write<T, R>(&self, task: T) -> std::result::Result<R, error::WouldBlock>
+    where
+        T: FnOnce(&mut Data) -> R,
+    {
+        let mut lock = self.data.try_write().map_err(|_| error::WouldBlock)?;
+        let result = task(&mut lock);
+        self.oplog.record_write();
+        Ok(result)
     }
 
     /// Write lock the database and get write access to the `Data` container in
@@ -325,10 +427,10 @@ where
     ///
     /// ```rust
     /// # #[macro_use] extern crate serde_derive;
-    /// # extern crate rustbreak;
+    /// # extern crate dropbreak;
     /// # extern crate serde;
     /// # extern crate tempfile;
-    /// use rustbreak::{
+    /// use dropbreak::{
     ///     deser::Ron,
     ///     error::RustbreakError,
     ///     FileDatabase,
@@ -339,8 +441,8 @@ where
     ///     level: u32,
     /// }
     ///
-    /// # fn main() {
-    /// # let func = || -> Result<(), Box<dyn std::error::Error>> {
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// # let file = tempfile::tempfile()?;
     /// let db = FileDatabase::<Data, Ron>::from_file(file, Data { level: 0 })?;
     ///
@@ -349,6 +451,7 @@ where
     ///         db.level = 42;
     ///         panic!("We panic inside the write code.");
     ///     })
+    ///     .await
     ///     .expect_err("This should have been caught");
     ///
     /// match result {
@@ -366,11 +469,9 @@ where
     /// }
     ///
     /// // We read it back out again, it has not changed
-    /// let value = db.read(|db| db.level)?;
+    /// let value = db.read(|db| db.level).await?;
     /// assert_eq!(0, value);
-    /// # return Ok(());
-    /// # };
-    /// # func().unwrap();
+    /// # Ok(())
     /// # }
     /// ```
     pub async fn write_safe<T>(&self, task: T) -> error::Result<()>
@@ -384,9 +485,84 @@ where
         }))
         .map_err(|_| RustbreakError::WritePanic)?;
         *lock = data;
+        self.oplog.record_write();
         Ok(())
     }
 
+    /// Write lock the database, run `task` on the data like
+    /// [`Database::write`] and save it, returning the data as it was before
+    /// `task` ran along with the result of `task`.
+    ///
+    /// The data is cloned before `task` runs, so that undo stacks or audit
+    /// logs don't have to take the snapshot themselves. The write lock is
+    /// held until the data is saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Database::save`]. The change made by `task`
+    /// stays in memory even if it could not be saved, the old data can be put
+    /// back with [`Database::put_data`].
+    ///
+    /// # Panics
+    ///
+    /// If you panic in the closure, the database is poisoned, like with
+    /// [`Database::write`].
+    pub async fn write_returning_old<T, R>(&self, task: T) -> error::Result<(Data, R)>
+    where
+        T: FnOnce(&mut Data) -> R,
+    {
+        let mut lock = self.data.write().await;
+        let old = lock.clone();
+        let result = task(&mut lock);
+        self.oplog.record_write();
+        self.save_data_locked(lock).await?;
+        Ok((old, result))
+    }
+
+    /// Exchange the data of this database with the data of `other`, and save
+    /// both.
+    ///
+    /// Both databases are write locked while the data is exchanged, so nobody
+    /// sees one of them swapped and the other not. The locks are always taken
+    /// in the same order, so swapping the same two databases from several
+    /// tasks at once can't deadlock. The databases are saved one after the
+    /// other once the locks are released, like with [`Database::save`].
+    /// Swapping a database with itself does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Database::save`], for this database first. The
+    /// data stays swapped in memory even if it could not be saved, and
+    /// `other` is not saved if saving this database failed.
+    pub async fn swap<OtherBack, OtherDeSer>(
+        &self,
+        other: &Database<Data, OtherBack, OtherDeSer>,
+    ) -> error::Result<()>
+    where
+        OtherBack: Backend + Send,
+        OtherDeSer: DeSerializer<Data> + Send + Sync + Clone,
+    {
+        let this = std::ptr::from_ref(&self.data).cast::<()>();
+        let that = std::ptr::from_ref(&other.data).cast::<()>();
+        if this == that {
+            return Ok(());
+        }
+        let (mut mine, mut theirs) = if this < that {
+            let mine = self.data.write().await;
+            (mine, other.data.write().await)
+        } else {
+            let theirs = other.data.write().await;
+            (self.data.write().await, theirs)
+        };
+        std::mem::swap(&mut *mine, &mut *theirs);
+        self.oplog.record_write();
+        other.oplog.record_write();
+        // Saving with a merge strategy takes the write lock again.
+        drop((mine, theirs));
+        self.save().await?;
+        other.save().await
+    }
+
     /// Read lock the database and get read access to the `Data` container.
     ///
     /// This gives you a read-only lock on the database. You can have as many
@@ -421,34 +597,33 @@ where
     ///
     /// ```rust
     /// # #[macro_use] extern crate serde_derive;
-    /// # extern crate rustbreak;
+    /// # extern crate dropbreak;
     /// # extern crate serde;
     /// # extern crate tempfile;
-    /// use rustbreak::{deser::Ron, FileDatabase};
+    /// use dropbreak::{deser::Ron, FileDatabase};
     ///
     /// #[derive(Debug, Serialize, Deserialize, Clone)]
     /// struct Data {
     ///     level: u32,
     /// }
     ///
-    /// # fn main() {
-    /// # let func = || -> Result<(), Box<dyn std::error::Error>> {
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// # let file = tempfile::tempfile()?;
     /// let db = FileDatabase::<Data, Ron>::from_file(file, Data { level: 0 })?;
     ///
     /// db.write(|db| {
     ///     db.level = 42;
-    /// })?;
+    /// })
+    /// .await?;
     ///
-    /// let data = db.borrow_data()?;
+    /// let data = db.borrow_data().await;
     ///
     /// assert_eq!(42, data.level);
-    /// # return Ok(());
-    /// # };
-    /// # func().unwrap();
+    /// # Ok(())
     /// # }
     /// ```
-    pub async fn borrow_data<'a>(&'a self) -> RwLockReadGuard<'a, Data> {
+    pub async fn borrow_data(&self) -> RwLockReadGuard<'_, Data> {
         self.data.read().await
     }
 
@@ -472,55 +647,111 @@ where
     ///
     /// ```rust
     /// # #[macro_use] extern crate serde_derive;
-    /// # extern crate rustbreak;
+    /// # extern crate dropbreak;
     /// # extern crate serde;
     /// # extern crate tempfile;
-    /// use rustbreak::{deser::Ron, FileDatabase};
+    /// use dropbreak::{deser::Ron, FileDatabase};
     ///
     /// #[derive(Debug, Serialize, Deserialize, Clone)]
     /// struct Data {
     ///     level: u32,
     /// }
     ///
-    /// # fn main() {
-    /// # let func = || -> Result<(), Box<dyn std::error::Error>> {
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// # let file = tempfile::tempfile()?;
     /// let db = FileDatabase::<Data, Ron>::from_file(file, Data { level: 0 })?;
     ///
     /// {
-    ///     let mut data = db.borrow_data_mut()?;
+    ///     let mut data = db.borrow_data_mut().await;
     ///     data.level = 42;
     /// }
     ///
-    /// let data = db.borrow_data()?;
+    /// let data = db.borrow_data().await;
     ///
     /// assert_eq!(42, data.level);
-    /// # return Ok(());
-    /// # };
-    /// # func().unwrap();
+    /// # Ok(())
     /// # }
     /// ```
-    pub async fn borrow_data_mut<'a>(&'a self) -> RwLockWriteGuard<'a, Data> {
+    pub async fn borrow_data_mut(&self) -> RwLockWriteGuard<'_, Data> {
         self.data.write().await
     }
 
+    /// Create the file at `path` with the data `init` returns, if there is
+    /// no file yet. Returns that data if the file was created.
+    ///
+    /// The file only appears once it holds the serialized data, see
+    /// [`FileDatabase`].
+    fn create_file_with<C>(
+        path: &std::path::Path,
+        deser: &DeSer,
+        init: C,
+    ) -> error::Result<Option<Data>>
+    where
+        C: FnOnce() -> Data,
+    {
+        if path.is_file() {
+            return Ok(None);
+        }
+        let data = init();
+        let ser = deser.serialize(&data)?;
+        // Created by someone else in the meantime, theirs is loaded.
+        let created = backend::create_file_with(path, &ser)?;
+        Ok(created.then_some(data))
+    }
+
     /// Load data from backend and return this data.
+    ///
+    /// Backends that already hold the data in memory lend it to the
+    /// deserializer instead of copying it.
     async fn load_from_backend(backend: &mut Back, deser: &DeSer) -> error::Result<Data> {
-        let new_data = deser.deserialize(&backend.get_data().await?[..])?;
+        Ok(Self::read_from_backend(backend, deser).await?.0)
+    }
+
+    /// Like [`Self::load_from_backend`], also returning the number of bytes
+    /// read.
+    async fn read_from_backend(backend: &mut Back, deser: &DeSer) -> error::Result<(Data, usize)> {
+        let bytes = backend.get_data_cow().await?;
+        let new_data = deser.deserialize(&bytes[..])?;
 
-        Ok(new_data)
+        Ok((new_data, bytes.len()))
     }
 
     /// Like [`Self::load`] but returns the write lock to data it used.
     async fn load_get_data_lock(&self) -> error::Result<RwLockWriteGuard<'_, Data>> {
+        let fresh_data = self.load_fresh().await?;
+        let mut data_write_lock = self.data.write().await;
+        *data_write_lock = fresh_data;
+        Ok(data_write_lock)
+    }
+
+    /// Read the data from the backend, recording the load and running the
+    /// hook of [`Database::on_after_load`] on it.
+    async fn load_fresh(&self) -> error::Result<Data> {
         let mut backend_lock = self.backend.lock().await;
 
-        let fresh_data = Self::load_from_backend(&mut backend_lock, &self.deser).await?;
+        let (mut fresh_data, read) = match &self.merge {
+            Some(merge) => {
+                merge
+                    .load(&mut *backend_lock, &self.deser, self.canonical_json())
+                    .await?
+            }
+            None => Self::read_from_backend(&mut backend_lock, &self.deser).await?,
+        };
         drop(backend_lock);
+        self.stats.record_load(read);
+        self.oplog.record_load(read);
+        self.hooks.after_load(&mut fresh_data);
+        Ok(fresh_data)
+    }
 
-        let mut data_write_lock = self.data.write().await;
-        *data_write_lock = fresh_data;
-        Ok(data_write_lock)
+    /// Set up the backend ahead of its first use, see [`Backend::init`].
+    ///
+    /// This is optional, backends needing a setup run it on their first read
+    /// or write otherwise.
+    pub async fn init_backend(&self) -> error::Result<()> {
+        self.backend.lock().await.init().await?;
+        Ok(())
     }
 
     /// Load the data from the backend.
@@ -531,12 +762,132 @@ where
 
     /// Like [`Self::save`] but with explicit read (or write) lock to data.
     async fn save_data_locked<L: Deref<Target = Data>>(&self, lock: L) -> error::Result<()> {
-        let ser = self.deser.serialize(lock.deref())?;
-        drop(lock);
+        self.save_data_locked_as(lock, None).await
+    }
 
+    /// Like [`Self::save_data_locked`], recording `actor` in the audit trail.
+    async fn save_data_locked_as<L: Deref<Target = Data>>(
+        &self,
+        lock: L,
+        actor: Option<String>,
+    ) -> error::Result<()> {
+        self.degraded.check()?;
+        self.persist(lock, actor).await
+    }
+
+    /// Save the data even if the database is degraded, degrading it if the
+    /// backend fails.
+    async fn persist<L: Deref<Target = Data>>(
+        &self,
+        lock: L,
+        actor: Option<String>,
+    ) -> error::Result<()> {
+        let result = self.write_to_backend(lock).await;
+        self.finish_save(result, actor).await
+    }
+
+    /// Record the outcome `result` of a write to the backend, in the stats,
+    /// the operation log, the degraded mode and the audit trail.
+    async fn finish_save(
+        &self,
+        result: error::Result<Written<'_, Back>>,
+        actor: Option<String>,
+    ) -> error::Result<()> {
+        let written = result.as_ref().map(|(written, ..)| *written);
+        self.stats.record_save(&written);
+        self.oplog.record_save(&written);
+        if let Err(e) = &result {
+            self.degraded.record_failure(e);
+        }
+        let (_, snapshot, backend) = result?;
+        // Recorded before the next save reaches the backend, so that the
+        // records follow the order of the saves.
+        let recorded = self.record_audit(actor, snapshot).await;
+        drop(backend);
+        recorded
+    }
+
+    /// Serialize the data and write it to the backend, returning the number
+    /// of bytes written, the snapshot of the data for the audit trail and
+    /// the locked backend.
+    async fn write_to_backend<L: Deref<Target = Data>>(
+        &self,
+        lock: L,
+    ) -> error::Result<Written<'_, Back>> {
+        if self.merge.is_some() || self.hooks.has_before_save() {
+            // Merging and the hook may change the data, which needs the
+            // write lock.
+            drop(lock);
+            let data = self.data.write().await;
+            return self.write_mut_to_backend(data).await;
+        }
+        self.put_serialized(lock).await
+    }
+
+    /// Like [`Self::write_to_backend`], with the data already write locked,
+    /// which stays locked until the backend is.
+    async fn write_mut_to_backend(
+        &self,
+        mut data: RwLockWriteGuard<'_, Data>,
+    ) -> error::Result<Written<'_, Back>> {
+        self.hooks.before_save(&mut data);
+        if let Some(merge) = &self.merge {
+            let mut backend = self.backend.lock().await;
+            let mut snapshot = AuditSnapshot::default();
+            let written = merge
+                .save(
+                    &mut data,
+                    &mut *backend,
+                    &self.deser,
+                    self.canonical_json(),
+                    |data| {
+                        self.validate(data)?;
+                        snapshot = self.audit_snapshot(data)?;
+                        Ok(())
+                    },
+                )
+                .await?;
+            return Ok((written, snapshot, backend));
+        }
+        self.put_serialized(data).await
+    }
+
+    /// Serialize the data and write it to the backend as it is.
+    async fn put_serialized<L: Deref<Target = Data>>(
+        &self,
+        lock: L,
+    ) -> error::Result<Written<'_, Back>> {
+        self.validate(&lock)?;
+        let snapshot = self.audit_snapshot(&lock)?;
+        let ser = self.deser.serialize(&*lock)?;
+        // Taken before the data is released, so that concurrent saves reach
+        // the backend in the order they serialized the data.
         let mut backend = self.backend.lock().await;
-        backend.put_data(&ser).await?;
-        Ok(())
+        drop(lock);
+
+        let written = backend.put_data_counted(&ser).await?;
+        Ok((written, snapshot, backend))
+    }
+
+    /// Load the data from the backend and write it back right away, in the
+    /// format of the current `DeSer`.
+    ///
+    /// This is the "vacuum" of the database: whatever the stored data looked
+    /// like, as long as the `DeSer` can read it, it is rewritten from scratch
+    /// the way the `DeSer` writes it today. The data is write locked from
+    /// before the load until the backend is locked for the save, also with a
+    /// merge strategy or a hook of [`Database::on_before_save`], so no other
+    /// write or save gets in between. With a [`PathBackend`] the file is
+    /// replaced atomically.
+    ///
+    /// Anything in memory that wasn't saved yet is replaced by the loaded
+    /// data, like with [`Database::load`].
+    pub async fn compact_now(&self) -> error::Result<()> {
+        let mut data = self.data.write().await;
+        *data = self.load_fresh().await?;
+        self.degraded.check()?;
+        let result = self.write_mut_to_backend(data).await;
+        self.finish_save(result, None).await
     }
 
     /// Flush the data structure to the backend.
@@ -564,6 +915,7 @@ where
     pub async fn put_data(&self, new_data: Data, save: bool) -> error::Result<()> {
         let mut data = self.data.write().await;
         *data = new_data;
+        self.oplog.record_write();
         if save {
             self.save_data_locked(data).await
         } else {
@@ -571,12 +923,116 @@ where
         }
     }
 
+    /// Check `data` against the schema set with
+    /// [`Database::with_validation`], if any.
+    #[cfg(feature = "schema_validation")]
+    fn validate(&self, data: &Data) -> error::Result<()> {
+        let Some(validator) = &self.validator else {
+            return Ok(());
+        };
+        let value = serde_json::to_value(data).map_err(DeSerError::from)?;
+        let errors: Vec<String> = validator
+            .iter_errors(&value)
+            .map(|e| format!("{}: {}", e.instance_path, e))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(BackendError::ValidationFailed { errors }.into())
+        }
+    }
+
+    /// Without the `schema_validation` feature there is nothing to check.
+    #[cfg(not(feature = "schema_validation"))]
+    #[allow(clippy::unused_self, clippy::unnecessary_wraps)]
+    fn validate(&self, _data: &Data) -> error::Result<()> {
+        Ok(())
+    }
+
+    /// The data about to be saved as JSON, if there is an audit trail.
+    #[cfg(feature = "json_enc")]
+    fn audit_snapshot(&self, data: &Data) -> error::Result<AuditSnapshot> {
+        self.audit.snapshot(data)
+    }
+
+    /// Without the `json_enc` feature there is no audit trail.
+    #[cfg(not(feature = "json_enc"))]
+    #[allow(clippy::unused_self, clippy::unnecessary_wraps)]
+    fn audit_snapshot(&self, _data: &Data) -> error::Result<AuditSnapshot> {
+        Ok(None)
+    }
+
+    /// Append the record of a save to the audit trail, if there is one.
+    #[cfg(feature = "json_enc")]
+    async fn record_audit(
+        &self,
+        actor: Option<String>,
+        snapshot: AuditSnapshot,
+    ) -> error::Result<()> {
+        self.audit.record(actor, snapshot).await
+    }
+
+    /// Without the `json_enc` feature there is no audit trail.
+    #[cfg(not(feature = "json_enc"))]
+    #[allow(clippy::unused_self, clippy::unnecessary_wraps, clippy::unused_async)]
+    async fn record_audit(
+        &self,
+        _actor: Option<String>,
+        _snapshot: AuditSnapshot,
+    ) -> error::Result<()> {
+        Ok(())
+    }
+
+    /// Refuse to save data that doesn't match the JSON `schema`.
+    ///
+    /// Before every save the data is converted to a JSON value and checked
+    /// against the schema. If it doesn't match, the save fails with
+    /// [`error::BackendError::ValidationFailed`] listing the mismatches, and
+    /// the backend is left untouched. This guards against bugs persisting a
+    /// malformed state, at the cost of converting the data on every save.
+    /// With the `schemars` feature, [`Database::json_schema`] gives a schema
+    /// to start from.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`error::BackendError::InvalidSchema`] if `schema` is not a
+    /// valid JSON Schema.
+    ///
+    /// **Important**: This method is only available with the
+    /// `schema_validation` feature
+    #[cfg(feature = "schema_validation")]
+    pub fn with_validation(mut self, schema: &serde_json::Value) -> error::Result<Self> {
+        let validator = jsonschema::validator_for(schema)
+            .map_err(|e| BackendError::InvalidSchema(e.to_string()))?;
+        self.validator = Some(validator);
+        Ok(self)
+    }
+
+    /// A snapshot of the counters of loads and saves made through this
+    /// database.
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
     /// Create a database from its constituents.
     pub fn from_parts(data: Data, backend: Back, deser: DeSer) -> Self {
         Self {
             data: RwLock::new(data),
             backend: Mutex::new(backend),
             deser,
+            merge: None,
+            hooks: hooks::Hooks::default(),
+            stats: stats::Counters::default(),
+            oplog: oplog::OperationLog::default(),
+            background: autosave::BackgroundSaves::default(),
+            degraded: degraded::Degraded::default(),
+            #[cfg(feature = "schema_validation")]
+            validator: None,
+            #[cfg(feature = "json_enc")]
+            audit: audit::AuditTrail::default(),
+            #[cfg(feature = "json_enc")]
+            canonical_json: false,
         }
     }
 
@@ -599,50 +1055,57 @@ where
     ///
     /// ```rust
     /// # #[macro_use] extern crate serde_derive;
-    /// # extern crate rustbreak;
+    /// # extern crate dropbreak;
     /// # extern crate serde;
     /// # extern crate tempfile;
-    /// use rustbreak::{deser::Ron, FileDatabase};
+    /// use dropbreak::{deser::Ron, FileDatabase};
     ///
     /// #[derive(Debug, Serialize, Deserialize, Clone)]
     /// struct Data {
     ///     level: u32,
     /// }
     ///
-    /// # fn main() {
-    /// # let func = || -> Result<(), Box<dyn std::error::Error>> {
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// # let file = tempfile::tempfile()?;
     /// let db = FileDatabase::<Data, Ron>::from_file(file, Data { level: 0 })?;
     ///
     /// db.write(|db| {
     ///     db.level = 42;
-    /// })?;
+    /// })
+    /// .await?;
     ///
-    /// db.save()?;
+    /// db.save().await?;
     ///
-    /// let other_db = db.try_clone()?;
+    /// let other_db = db.try_clone().await?;
     ///
     /// // You can also return from a `.read()`. But don't forget that you cannot return references
     /// // into the structure
-    /// let value = other_db.read(|db| db.level)?;
+    /// let value = other_db.read(|db| db.level).await?;
     /// assert_eq!(42, value);
-    /// # return Ok(());
-    /// # };
-    /// # func().unwrap();
+    /// # Ok(())
     /// # }
     /// ```
     pub async fn try_clone(&self) -> error::Result<MemoryDatabase<Data, DeSer>> {
         let lock = self.data.read().await;
 
-        Ok(Database {
-            data: RwLock::new(lock.clone()),
-            backend: Mutex::new(MemoryBackend::new()),
-            deser: self.deser.clone(),
-        })
+        Ok(Database::from_parts(
+            lock.clone(),
+            MemoryBackend::new(),
+            self.deser.clone(),
+        ))
     }
 }
 
 /// A database backed by a file.
+///
+/// The `load_from_path_or*` constructors and `create_at_path` only initialise
+/// the file if it doesn't exist. The initial data is written to a temporary
+/// file next to it, which is moved into place once complete, so a crash
+/// while initialising leaves no file and the next start initialises again.
+/// An existing file, even an empty one, therefore always holds saved data
+/// and is loaded like any other: some `DeSer`s serialize values like `()` to
+/// no bytes at all.
 pub type FileDatabase<D, DS> = Database<D, FileBackend, DS>;
 
 impl<Data, DeSer> Database<Data, FileBackend, DeSer>
@@ -660,12 +1123,7 @@ where
         let deser = DeSer::default();
         let data = Self::load_from_backend(&mut backend, &deser).await?;
 
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
-        };
-        Ok(db)
+        Ok(Self::from_parts(data, backend, deser))
     }
 
     /// Load [`FileDatabase`] at `path` or initialise with `data`.
@@ -677,24 +1135,7 @@ where
     where
         S: AsRef<std::path::Path>,
     {
-        let (mut backend, exists) = FileBackend::from_path_or_create(path)?;
-        let deser = DeSer::default();
-        if !exists {
-            let ser = deser.serialize(&data)?;
-            backend.put_data(&ser).await?;
-        }
-
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
-        };
-
-        if exists {
-            db.load().await?;
-        }
-
-        Ok(db)
+        Self::load_from_path_or_else(path, || data).await
     }
 
     /// Load [`FileDatabase`] at `path` or initialise with `closure`.
@@ -707,25 +1148,15 @@ where
         S: AsRef<std::path::Path>,
         C: FnOnce() -> Data,
     {
-        let (mut backend, exists) = FileBackend::from_path_or_create(path)?;
         let deser = DeSer::default();
-        let data = if exists {
-            Self::load_from_backend(&mut backend, &deser).await?
-        } else {
-            let data = closure();
-
-            let ser = deser.serialize(&data)?;
-            backend.put_data(&ser).await?;
-
-            data
+        let created = Self::create_file_with(path.as_ref(), &deser, closure)?;
+        let mut backend = FileBackend::from_path_or_fail(path)?;
+        let data = match created {
+            Some(data) => data,
+            None => Self::load_from_backend(&mut backend, &deser).await?,
         };
 
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
-        };
-        Ok(db)
+        Ok(Self::from_parts(data, backend, deser))
     }
 
     /// Create [`FileDatabase`] at `path`. Initialise with `data` if the file
@@ -734,34 +1165,23 @@ where
     /// Create new [`FileDatabase`] from the file at [`Path`](std::path::Path).
     /// Contents are not loaded. If the file does not exist, it is
     /// initialised with `data`. Frontend is always initialised with `data`.
+    #[allow(clippy::unused_async)] // kept async like the other constructors
     pub async fn create_at_path<S>(path: S, data: Data) -> error::Result<Self>
     where
         S: AsRef<std::path::Path>,
     {
-        let (mut backend, exists) = FileBackend::from_path_or_create(path)?;
         let deser = DeSer::default();
-        if !exists {
-            let ser = deser.serialize(&data)?;
-            backend.put_data(&ser).await?;
-        }
+        Self::create_file_with(path.as_ref(), &deser, || data.clone())?;
+        let backend = FileBackend::from_path_or_fail(path)?;
 
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
-        };
-        Ok(db)
+        Ok(Self::from_parts(data, backend, deser))
     }
 
     /// Create new [`FileDatabase`] from a file.
     pub fn from_file(file: std::fs::File, data: Data) -> error::Result<Self> {
         let backend = FileBackend::from_file(file);
 
-        Ok(Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser: DeSer::default(),
-        })
+        Ok(Self::from_parts(data, backend, DeSer::default()))
     }
 }
 
@@ -784,6 +1204,10 @@ where
 }
 
 /// A database backed by a file, using atomic saves.
+///
+/// Like with [`FileDatabase`], the constructors only create the file once
+/// it holds the initial data, so an empty file is never mistaken for an
+/// initialised one.
 pub type PathDatabase<D, DS> = Database<D, PathBackend, DS>;
 
 impl<Data, DeSer> Database<Data, PathBackend, DeSer>
@@ -798,12 +1222,7 @@ where
         let deser = DeSer::default();
         let data = Self::load_from_backend(&mut backend, &deser).await?;
 
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
-        };
-        Ok(db)
+        Ok(Self::from_parts(data, backend, deser))
     }
 
     /// Load [`PathDatabase`] at `path` or initialise with `data`.
@@ -812,24 +1231,7 @@ where
     /// and load the contents. If the file does not exist, initialise with
     /// `data`.
     pub async fn load_from_path_or(path: PathBuf, data: Data) -> error::Result<Self> {
-        let (mut backend, exists) = PathBackend::from_path_or_create(path).await?;
-        let deser = DeSer::default();
-        if !exists {
-            let ser = deser.serialize(&data)?;
-            backend.put_data(&ser).await?;
-        }
-
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
-        };
-
-        if exists {
-            db.load().await?;
-        }
-
-        Ok(db)
+        Self::load_from_path_or_else(path, || data).await
     }
 
     /// Load [`PathDatabase`] at `path` or initialise with `closure`.
@@ -841,25 +1243,15 @@ where
     where
         C: FnOnce() -> Data,
     {
-        let (mut backend, exists) = PathBackend::from_path_or_create(path).await?;
         let deser = DeSer::default();
-        let data = if exists {
-            Self::load_from_backend(&mut backend, &deser).await?
-        } else {
-            let data = closure();
-
-            let ser = deser.serialize(&data)?;
-            backend.put_data(&ser).await?;
-
-            data
+        let created = Self::create_file_with(&path, &deser, closure)?;
+        let mut backend = PathBackend::from_path_or_fail(path).await?;
+        let data = match created {
+            Some(data) => data,
+            None => Self::load_from_backend(&mut backend, &deser).await?,
         };
 
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
-        };
-        Ok(db)
+        Ok(Self::from_parts(data, backend, deser))
     }
 
     /// Create [`PathDatabase`] at `path`. Initialise with `data` if the file
@@ -869,19 +1261,54 @@ where
     /// Contents are not loaded. If the file does not exist, it is
     /// initialised with `data`. Frontend is always initialised with `data`.
     pub async fn create_at_path(path: PathBuf, data: Data) -> error::Result<Self> {
-        let (mut backend, exists) = PathBackend::from_path_or_create(path).await?;
         let deser = DeSer::default();
-        if !exists {
-            let ser = deser.serialize(&data)?;
-            backend.put_data(&ser).await?;
+        Self::create_file_with(&path, &deser, || data.clone())?;
+        let backend = PathBackend::from_path_or_fail(path).await?;
+
+        Ok(Self::from_parts(data, backend, deser))
+    }
+
+    /// Flush the data structure to the file like [`Database::save`], but
+    /// serialize it straight into the temporary file.
+    ///
+    /// At most `buffer_size` bytes of the serialized data are held in memory,
+    /// on top of what the `DeSer` needs for itself, which makes this suitable
+    /// for states too large to serialize into a buffer. `DeSer`s relying on
+    /// the default [`DeSerializer::serialize_into`] still build the whole
+    /// buffer.
+    ///
+    /// Detecting conflicts needs the serialized data, so with a merge strategy
+    /// set (see [`Database::with_merge_strategy`]) this is a regular save.
+    /// Otherwise it is refused, recorded and audited like one.
+    pub async fn save_streaming(&self, buffer_size: usize) -> error::Result<()> {
+        if self.merge.is_some() {
+            return self.save().await;
         }
+        self.degraded.check()?;
+        let result = self.stream_to_backend(buffer_size).await;
+        self.finish_save(result, None).await
+    }
 
-        let db = Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser,
+    /// Serialize the data straight into the file, for
+    /// [`Database::save_streaming`].
+    async fn stream_to_backend(
+        &self,
+        buffer_size: usize,
+    ) -> error::Result<Written<'_, PathBackend>> {
+        let data = if self.hooks.has_before_save() {
+            let mut data = self.data.write().await;
+            self.hooks.before_save(&mut data);
+            data.downgrade()
+        } else {
+            self.data.read().await
         };
-        Ok(db)
+        self.validate(&data)?;
+        let snapshot = self.audit_snapshot(&data)?;
+        let mut backend = self.backend.lock().await;
+        let written = backend.put_data_with(buffer_size, |writer| {
+            Ok::<_, RustbreakError>(self.deser.serialize_into(&*data, writer)?)
+        })?;
+        Ok((written, snapshot, backend))
     }
 }
 
@@ -912,11 +1339,25 @@ where
     pub fn memory(data: Data) -> error::Result<Self> {
         let backend = MemoryBackend::new();
 
-        Ok(Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser: DeSer::default(),
-        })
+        Ok(Self::from_parts(data, backend, DeSer::default()))
+    }
+
+    /// Create a new in-memory database from the serialized data read from
+    /// `reader`, such as stdin or a socket.
+    ///
+    /// All of `reader` is read and deserialized with `deser` right away. The
+    /// bytes are kept in the [`MemoryBackend`], so [`Database::load`] goes
+    /// back to them, but nothing is ever written anywhere durable.
+    pub fn from_reader<R: std::io::Read>(mut reader: R, deser: DeSer) -> error::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(BackendError::from)?;
+        let data = deser.deserialize(&bytes[..])?;
+
+        Ok(Self::from_parts(
+            data,
+            MemoryBackend::from_vec(bytes),
+            deser,
+        ))
     }
 }
 
@@ -934,22 +1375,14 @@ where
     pub fn mmap(data: Data) -> error::Result<Self> {
         let backend = MmapStorage::new()?;
 
-        Ok(Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser: DeSer::default(),
-        })
+        Ok(Self::from_parts(data, backend, DeSer::default()))
     }
 
     /// Create new [`MmapDatabase`] with specified initial size.
     pub fn mmap_with_size(data: Data, size: usize) -> error::Result<Self> {
         let backend = MmapStorage::with_size(size)?;
 
-        Ok(Self {
-            data: RwLock::new(data),
-            backend: Mutex::new(backend),
-            deser: DeSer::default(),
-        })
+        Ok(Self::from_parts(data, backend, DeSer::default()))
     }
 }
 
@@ -960,6 +1393,18 @@ impl<Data, Back, DeSer> Database<Data, Back, DeSer> {
             backend: self.backend,
             data: self.data,
             deser,
+            merge: self.merge,
+            hooks: self.hooks,
+            stats: self.stats,
+            oplog: self.oplog,
+            background: self.background,
+            degraded: self.degraded,
+            #[cfg(feature = "schema_validation")]
+            validator: self.validator,
+            #[cfg(feature = "json_enc")]
+            audit: self.audit,
+            #[cfg(feature = "json_enc")]
+            canonical_json: self.canonical_json,
         }
     }
 }
@@ -974,6 +1419,18 @@ impl<Data, Back, DeSer> Database<Data, Back, DeSer> {
             backend: Mutex::new(backend),
             data: self.data,
             deser: self.deser,
+            merge: self.merge.map(merge::Merge::forget_base),
+            hooks: self.hooks,
+            stats: self.stats,
+            oplog: self.oplog,
+            background: self.background,
+            degraded: self.degraded.forget_failure(),
+            #[cfg(feature = "schema_validation")]
+            validator: self.validator,
+            #[cfg(feature = "json_enc")]
+            audit: self.audit,
+            #[cfg(feature = "json_enc")]
+            canonical_json: self.canonical_json,
         }
     }
 }
@@ -981,7 +1438,7 @@ impl<Data, Back, DeSer> Database<Data, Back, DeSer> {
 impl<Data, Back, DeSer> Database<Data, Back, DeSer>
 where
     Data: Serialize + DeserializeOwned + Clone + Send,
-    Back: Backend,
+    Back: Backend + Send,
     DeSer: DeSerializer<Data> + Send + Sync + Clone,
 {
     /// Converts from one data type to another.
@@ -997,15 +1454,144 @@ where
         DeSer: DeSerializer<OutputData> + Send + Sync,
     {
         let (data, backend, deser) = self.into_inner()?;
-        Ok(Database {
-            data: RwLock::new(convert(data)),
-            backend: Mutex::new(backend),
-            deser,
-        })
+        Ok(Database::from_parts(convert(data), backend, deser))
+    }
+}
+
+#[cfg(feature = "json_enc")]
+impl<Data, Back> Database<Data, Back, deser::Json>
+where
+    Back: Backend + Send,
+{
+    /// Deserialize the single value at a JSON `pointer` straight out of the
+    /// backend, without loading the whole `Data`.
+    ///
+    /// The pointer uses the syntax of [RFC 6901](https://tools.ietf.org/html/rfc6901),
+    /// e.g. `"/users/0/name"`. The value is read from the backend rather than
+    /// from the in-memory data, so anything not yet saved is not seen.
+    ///
+    /// # Errors
+    ///
+    /// May return:
+    ///
+    /// - [`error::RustbreakError::Backend`]
+    /// - [`error::RustbreakError::DeSerialization`], with
+    ///   [`error::DeSerError::PointerNotFound`] if there is no value at
+    ///   `pointer`
+    ///
+    /// **Important**: This method is only available with the `json_enc`
+    /// feature
+    pub async fn read_pointer<R>(&self, pointer: &str) -> error::Result<R>
+    where
+        R: DeserializeOwned,
+    {
+        let mut backend = self.backend.lock().await;
+        let mut value: serde_json::Value =
+            serde_json::from_slice(&backend.get_data_cow().await?).map_err(deser::parse_error)?;
+        drop(backend);
+
+        let node = value
+            .pointer_mut(pointer)
+            .ok_or_else(|| DeSerError::PointerNotFound(pointer.to_owned()))?
+            .take();
+        Ok(self.deser.deserialize_value(node)?)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Serialize the in-memory data into a [`bytes::Bytes`] buffer, without
+    /// touching the backend.
+    ///
+    /// **Important**: This method is only available with the `bytes`
+    /// feature
+    pub async fn save_to_bytes(&self) -> error::Result<bytes::Bytes> {
+        let data = self.data.read().await;
+        Ok(self.deser.serialize(&*data)?.into())
+    }
+
+    /// Replace the in-memory data with the data deserialized from `bytes`,
+    /// without touching the backend.
+    ///
+    /// The data is not saved, call [`Database::save`] to do so. If `bytes`
+    /// can't be deserialized the data is left as it was. The load is counted
+    /// in [`Database::stats`] and the operation log like one from the
+    /// backend, and runs the hook of [`Database::on_after_load`].
+    ///
+    /// **Important**: This method is only available with the `bytes`
+    /// feature
+    pub async fn load_from_bytes(&self, bytes: bytes::Bytes) -> error::Result<()> {
+        let mut fresh_data = self.deser.deserialize(&bytes[..])?;
+        self.stats.record_load(bytes.len());
+        self.oplog.record_load(bytes.len());
+        self.hooks.after_load(&mut fresh_data);
+        *self.data.write().await = fresh_data;
+        Ok(())
     }
 }
 
-#[cfg(test)]
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: backend::VersionedBackend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// The versions of the data kept by the storage of the backend, newest
+    /// first.
+    pub async fn list_versions(&self) -> error::Result<Vec<backend::ObjectVersion>> {
+        Ok(self.backend.lock().await.list_versions().await?)
+    }
+
+    /// Load the data of the earlier version `version_id` of the storage,
+    /// replacing the data in memory.
+    ///
+    /// This is like [`Database::load`], reading the given version instead of
+    /// the latest one, and runs the hook of [`Database::on_after_load`] as
+    /// well. The storage isn't changed: saving writes the loaded data as a
+    /// new version, restoring it. If the version can't be read or
+    /// deserialized the data in memory is left as it was.
+    pub async fn load_version(&self, version_id: &str) -> error::Result<()> {
+        let bytes = self
+            .backend
+            .lock()
+            .await
+            .get_data_version(version_id)
+            .await?;
+        let mut fresh_data = self.deser.deserialize(&bytes[..])?;
+        self.stats.record_load(bytes.len());
+        self.oplog.record_load(bytes.len());
+        self.hooks.after_load(&mut fresh_data);
+        *self.data.write().await = fresh_data;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "schemars")]
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: schemars::JsonSchema,
+{
+    /// The JSON Schema of the stored `Data`.
+    ///
+    /// This can be used to validate files edited by hand against the
+    /// expected shape, or to document it. The schema describes the data the
+    /// way serde sees it, so it only matches the file itself for a JSON
+    /// `DeSer` with the default enum tagging.
+    ///
+    /// **Important**: This method is only available with the `schemars`
+    /// feature
+    #[must_use]
+    pub fn json_schema() -> schemars::Schema {
+        schemars::schema_for!(Data)
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
 mod tests {
     use super::*;
     use serde_derive::{Deserialize, Serialize};
@@ -1023,6 +1609,7 @@ mod tests {
     }
 
     /// Used to test that `Default::default` isn't called.
+    #[allow(dead_code)] // only used by the tests still awaiting the async port below
     #[derive(Clone, Debug, Serialize, Deserialize)]
     struct PanicDefault;
     impl Default for PanicDefault {
@@ -1082,11 +1669,92 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn try_write_would_block() {
+        let db = std::sync::Arc::new(TestMemDb::memory(test_data()).expect("Could not create db"));
+        let (locked_tx, locked_rx) = tokio::sync::oneshot::channel();
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let holder = {
+            let db = std::sync::Arc::clone(&db);
+            tokio::spawn(async move {
+                let _lock = db.borrow_data_mut().await;
+                locked_tx.send(()).expect("test dropped");
+                let _ = release_rx.await;
+            })
+        };
+
+        locked_rx.await.expect("holder did not lock");
+        assert_eq!(
+            Err(error::WouldBlock),
+            db.try_write(|data| data.insert(2, "Blocked".to_string()))
+        );
+        release_tx.send(()).expect("holder stopped");
+        holder.await.expect("holder panicked");
+
+        db.try_write(|data| data.insert(2, "Written".to_string()))
+            .expect("data still locked");
+        let value = db
+            .read(|data| data.get(&2).cloned())
+            .await
+            .expect("no data");
+        assert_eq!(Some("Written".to_string()), value);
+    }
+
+    #[tokio::test]
+    async fn write_returning_old_value() {
+        let db = TestMemDb::memory(test_data()).expect("Could not create database");
+        let (old, previous) = db
+            .write_returning_old(|d| d.insert(1, "Changed".to_string()))
+            .await
+            .expect("Rustbreak write error");
+        assert_eq!(test_data(), old);
+        assert_eq!(Some("Hello World".to_string()), previous);
+
+        let current = db.get_data(false).await.expect("Rustbreak read error");
+        assert_ne!(old, current);
+        assert_eq!("Changed", current[&1]);
+        // The change was saved as well.
+        assert_eq!(
+            current,
+            db.get_data(true).await.expect("Rustbreak load error")
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn swap_saves_both() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut other_data = TestData::new();
+        other_data.insert(7, "Other".to_string());
+        let first = TestDb::<PathBackend>::create_at_path(dir.path().join("first"), test_data())
+            .await
+            .expect("could not create db");
+        let second =
+            TestDb::<PathBackend>::create_at_path(dir.path().join("second"), other_data.clone())
+                .await
+                .expect("could not create db");
+
+        // In both directions at once, which must not deadlock.
+        let (a, b) = tokio::join!(first.swap(&second), second.swap(&first));
+        a.and(b).expect("could not swap");
+        first.swap(&second).await.expect("could not swap");
+        first
+            .swap(&first)
+            .await
+            .expect("could not swap with itself");
+
+        assert_eq!(other_data, first.get_data(false).await.expect("no data"));
+        assert_eq!(test_data(), second.get_data(false).await.expect("no data"));
+        // Both files hold the swapped data.
+        assert_eq!(other_data, first.get_data(true).await.expect("no data"));
+        assert_eq!(test_data(), second.get_data(true).await.expect("no data"));
+    }
+
     #[tokio::test]
     async fn save_load() {
         let db = TestMemDb::memory(test_data()).expect("Could not create database");
         db.save().await.expect("Rustbreak save error");
-        db.write(|d| d.clear())
+        db.write(HashMap::clear)
             .await
             .expect("Rustbreak write error");
         db.load().await.expect("Rustbreak load error");
@@ -1238,7 +1906,7 @@ mod tests {
     async fn get_data_load() {
         let db = TestMemDb::memory(test_data()).expect("Could not create database");
         db.save().await.expect("Rustbreak save error");
-        db.write(|d| d.clear())
+        db.write(HashMap::clear)
             .await
             .expect("Rustbreak write error");
         let data = db.get_data(true).await.expect("could not get data");
@@ -1294,6 +1962,435 @@ mod tests {
         assert_eq!(test_data(), data);
     }
 
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn pathdb_save_streaming() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut path = dir.path().to_owned();
+        path.push("rustbreak_path_db.db");
+        let db = TestDb::<PathBackend>::create_at_path(path.clone(), TestData::default())
+            .await
+            .expect("could not create db");
+        db.put_data(test_data(), false)
+            .await
+            .expect("could not put data");
+        db.save_streaming(16).await.expect("could not save");
+
+        let db = TestDb::<PathBackend>::load_from_path(path)
+            .await
+            .expect("could not load");
+        assert_eq!(test_data(), db.get_data(false).await.expect("no data"));
+        dir.close().expect("Error while deleting temp directory!");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn pathdb_save_streaming_degrades() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("rustbreak_path_db.db");
+        let db = TestDb::<PathBackend>::create_at_path(path, test_data())
+            .await
+            .expect("could not create db")
+            .with_degraded_mode();
+        db.save_streaming(16).await.expect("could not save");
+        assert_eq!(1, db.stats().saves);
+
+        // The temporary file can't be created anymore.
+        dir.close().expect("Error while deleting temp directory!");
+        assert!(matches!(
+            db.save_streaming(16).await,
+            Err(RustbreakError::Backend(BackendError::Io(_)))
+        ));
+        assert_eq!(1, db.stats().save_failures);
+        assert!(db.is_degraded());
+        assert!(matches!(
+            db.save_streaming(16).await,
+            Err(RustbreakError::Backend(BackendError::Degraded { .. }))
+        ));
+        assert_eq!(1, db.stats().save_failures);
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn json_schema_properties() {
+        #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+        struct Config {
+            name: String,
+            retries: u32,
+            tags: Vec<String>,
+        }
+
+        let schema = MemoryDatabase::<Config, crate::deser::Ron>::json_schema();
+        let properties = schema.as_value()["properties"]
+            .as_object()
+            .expect("schema has no properties");
+        let mut names: Vec<&str> = properties.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(vec!["name", "retries", "tags"], names);
+        assert_eq!(Some("integer"), properties["retries"]["type"].as_str());
+    }
+
+    #[cfg(feature = "schema_validation")]
+    #[tokio::test]
+    async fn validation_rejects_invalid_state() {
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            name: String,
+            retries: i64,
+        }
+
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "retries": { "type": "integer", "minimum": 0 }
+            },
+            "required": ["name", "retries"]
+        });
+        let valid = Config {
+            name: "db".to_owned(),
+            retries: 3,
+        };
+        let db = MemoryDatabase::<Config, crate::deser::Ron>::memory(valid.clone())
+            .expect("Could not create database")
+            .with_validation(&schema)
+            .expect("invalid schema");
+        db.save().await.expect("could not save a valid state");
+
+        db.write(|c| {
+            c.name.clear();
+            c.retries = -1;
+        })
+        .await
+        .expect("could not write");
+        match db.save().await {
+            Err(RustbreakError::Backend(BackendError::ValidationFailed { errors })) => {
+                assert_eq!(2, errors.len(), "{errors:?}");
+                assert!(errors.iter().any(|e| e.starts_with("/name")));
+                assert!(errors.iter().any(|e| e.starts_with("/retries")));
+            }
+            res => panic!("expected a validation failure, got {:?}", res),
+        }
+
+        // The invalid state never reached the backend.
+        db.load().await.expect("could not load");
+        assert_eq!(valid, db.get_data(false).await.expect("no data"));
+        assert_eq!(1, db.stats().save_failures);
+    }
+
+    #[cfg(feature = "schema_validation")]
+    #[test]
+    fn validation_rejects_invalid_schema() {
+        let db =
+            MemoryDatabase::<u32, crate::deser::Ron>::memory(0).expect("Could not create database");
+        let err = db
+            .with_validation(&serde_json::json!({ "type": 12 }))
+            .expect_err("schema should be invalid");
+        assert!(matches!(
+            err,
+            RustbreakError::Backend(BackendError::InvalidSchema(_))
+        ));
+    }
+
+    #[cfg(feature = "json_enc")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn load_reports_parse_position() {
+        let file = tempfile::NamedTempFile::new().expect("could not create temporary file");
+        // The comma after `2` is missing.
+        std::fs::write(file.path(), "{\n  \"a\": 1,\n  \"b\": 2\n  \"c\": 3\n}\n")
+            .expect("could not write file");
+        let db = PathDatabase::<HashMap<String, u32>, crate::deser::Json>::create_at_path(
+            file.path().to_owned(),
+            HashMap::new(),
+        )
+        .await
+        .expect("could not create db");
+
+        match db.load().await {
+            Err(RustbreakError::DeSerialization(DeSerError::Parse {
+                line,
+                column,
+                message,
+            })) => {
+                assert_eq!((4, 3), (line, column));
+                assert_eq!("expected `,` or `}`", message);
+            }
+            res => panic!("expected a parse error, got {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "json_enc")]
+    #[tokio::test]
+    async fn read_pointer_nested() {
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct Address {
+            city: String,
+            zip: u32,
+        }
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        struct User {
+            name: String,
+            address: Address,
+        }
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        struct State {
+            users: HashMap<String, Vec<User>>,
+        }
+
+        let mut users = HashMap::new();
+        users.insert(
+            "admins".to_owned(),
+            vec![User {
+                name: "root".to_owned(),
+                address: Address {
+                    city: "Basel".to_owned(),
+                    zip: 4051,
+                },
+            }],
+        );
+        let db = MemoryDatabase::<State, crate::deser::Json>::memory(State { users })
+            .expect("Could not create database");
+        db.save().await.expect("could not save");
+
+        let address: Address = db
+            .read_pointer("/users/admins/0/address")
+            .await
+            .expect("could not read pointer");
+        assert_eq!(
+            Address {
+                city: "Basel".to_owned(),
+                zip: 4051,
+            },
+            address
+        );
+        let zip: u32 = db
+            .read_pointer("/users/admins/0/address/zip")
+            .await
+            .expect("could not read pointer");
+        assert_eq!(4051, zip);
+
+        let err = db
+            .read_pointer::<Address>("/users/guests/0/address")
+            .await
+            .expect_err("pointer should not resolve");
+        assert!(matches!(
+            err,
+            RustbreakError::DeSerialization(DeSerError::PointerNotFound(_))
+        ));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[tokio::test]
+    async fn bytes_roundtrip() {
+        let db = TestMemDb::memory(test_data()).expect("Could not create database");
+        let bytes = db.save_to_bytes().await.expect("could not save to bytes");
+
+        let other = TestMemDb::memory(TestData::default())
+            .expect("Could not create database")
+            .with_operation_log(4);
+        other
+            .load_from_bytes(bytes.clone())
+            .await
+            .expect("could not load from bytes");
+        assert_eq!(test_data(), other.get_data(false).await.expect("no data"));
+        assert_eq!(1, other.stats().loads);
+        assert_eq!(bytes.len() as u64, other.stats().bytes_read);
+        let log = other.operation_log();
+        assert_eq!(1, log.len());
+        assert_eq!(OperationKind::Load, log[0].kind);
+        assert_eq!(Some(bytes.len() as u64), log[0].bytes);
+
+        other
+            .load_from_bytes(bytes.slice(1..))
+            .await
+            .expect_err("loaded a broken buffer");
+        assert_eq!(test_data(), other.get_data(false).await.expect("no data"));
+    }
+
+    #[tokio::test]
+    async fn from_reader_deserializes() {
+        let bytes = crate::deser::Ron
+            .serialize(&test_data())
+            .expect("could not serialize");
+        let db = TestMemDb::from_reader(std::io::Cursor::new(bytes), crate::deser::Ron)
+            .expect("could not create db");
+        assert_eq!(
+            Some("Rustbreak".to_string()),
+            db.read(|d| d.get(&100).cloned())
+                .await
+                .expect("Rustbreak read error")
+        );
+
+        db.write(HashMap::clear)
+            .await
+            .expect("Rustbreak write error");
+        db.load().await.expect("could not load");
+        assert_eq!(test_data(), db.get_data(false).await.expect("no data"));
+
+        TestMemDb::from_reader(&b"not ron"[..], crate::deser::Ron)
+            .expect_err("deserialized garbage");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn compact_now_rewrites_file() {
+        let file = tempfile::NamedTempFile::new().expect("could not create temporary file");
+        // Written by hand, on a single line.
+        std::fs::write(file.path(), "{100:\"Rustbreak\",  1 : \"Hello World\"}")
+            .expect("could not write file");
+        let db = TestDb::<PathBackend>::create_at_path(file.path().to_owned(), TestData::new())
+            .await
+            .expect("could not create db");
+
+        db.compact_now().await.expect("could not compact");
+        let data = db.get_data(false).await.expect("no data");
+        assert_eq!(test_data(), data);
+        assert_eq!(
+            crate::deser::Ron
+                .serialize(&data)
+                .expect("could not serialize"),
+            std::fs::read(file.path()).expect("could not read file")
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn compact_now_with_hook_saves_loaded_data() {
+        let file = tempfile::NamedTempFile::new().expect("could not create temporary file");
+        std::fs::write(file.path(), "{1: \"Hello World\"}").expect("could not write file");
+        let db = TestDb::<PathBackend>::create_at_path(file.path().to_owned(), test_data())
+            .await
+            .expect("could not create db")
+            .with_merge_strategy(crate::merge::LastWriteWins);
+        db.on_before_save(|data| {
+            data.insert(2, "hooked".to_owned());
+        });
+
+        db.compact_now().await.expect("could not compact");
+        let mut expected = TestData::new();
+        expected.insert(1, "Hello World".to_owned());
+        expected.insert(2, "hooked".to_owned());
+        let data = db.get_data(false).await.expect("no data");
+        assert_eq!(expected, data);
+        assert_eq!(
+            crate::deser::Ron
+                .serialize(&data)
+                .expect("could not serialize"),
+            std::fs::read(file.path()).expect("could not read file")
+        );
+    }
+
+    #[tokio::test]
+    async fn unit_save_load() {
+        let db = MemoryDatabase::<(), crate::deser::Ron>::memory(()).expect("could not create db");
+        db.save().await.expect("could not save");
+        db.load().await.expect("could not load");
+    }
+
+    // Bincode serializes `()` to no bytes, the empty file must still count as
+    // initialised.
+    #[cfg(feature = "bin_enc")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn unit_empty_file_is_initialised() {
+        type UnitDb<B> = Database<(), B, crate::deser::Bincode>;
+
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("rustbreak_path_db.db");
+        let db = UnitDb::<PathBackend>::load_from_path_or_else(path.clone(), || ())
+            .await
+            .expect("could not create db");
+        db.save().await.expect("could not save");
+        assert_eq!(
+            0,
+            std::fs::metadata(&path)
+                .expect("file was not created")
+                .len()
+        );
+        drop(db);
+
+        let db = UnitDb::<PathBackend>::load_from_path_or_else(path.clone(), || {
+            panic!("Closure called but file already existed")
+        })
+        .await
+        .expect("could not load db");
+        db.load().await.expect("could not load");
+        drop(db);
+
+        let db = UnitDb::<FileBackend>::load_from_path_or_else(&path, || {
+            panic!("Closure called but file already existed")
+        })
+        .await
+        .expect("could not load db");
+        db.save().await.expect("could not save");
+        db.load().await.expect("could not load");
+        dir.close().expect("Error while deleting temp directory!");
+    }
+
+    /// Data whose serialization panics when `crash` is set, standing in for a
+    /// crash while the file is initialised.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct CrashOnSave {
+        #[serde(serialize_with = "crash_if_set")]
+        crash: bool,
+    }
+
+    #[allow(clippy::trivially_copy_pass_by_ref)] // the signature serde expects
+    fn crash_if_set<S: ::serde::Serializer>(
+        crash: &bool,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        assert!(!crash, "crashed while initialising");
+        serializer.serialize_bool(*crash)
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn crash_while_initialising_leaves_no_file() {
+        type CrashDb<B> = Database<CrashOnSave, B, crate::deser::Ron>;
+
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("rustbreak_path_db.db");
+        let crashing = path.clone();
+        tokio::spawn(async move {
+            CrashDb::<PathBackend>::load_from_path_or(crashing, CrashOnSave { crash: true }).await
+        })
+        .await
+        .expect_err("initialising did not crash");
+        assert!(!path.exists(), "a file was left behind");
+
+        let db =
+            CrashDb::<PathBackend>::load_from_path_or(path.clone(), CrashOnSave { crash: false })
+                .await
+                .expect("could not create db");
+        assert_eq!(
+            CrashOnSave { crash: false },
+            db.get_data(true).await.expect("could not load")
+        );
+        drop(db);
+        std::fs::remove_file(&path).expect("could not remove file");
+
+        let crashing = path.clone();
+        tokio::spawn(async move {
+            CrashDb::<FileBackend>::load_from_path_or(crashing, CrashOnSave { crash: true }).await
+        })
+        .await
+        .expect_err("initialising did not crash");
+        assert!(!path.exists(), "a file was left behind");
+
+        let db = CrashDb::<FileBackend>::load_from_path_or(&path, CrashOnSave { crash: false })
+            .await
+            .expect("could not create db");
+        assert_eq!(
+            CrashOnSave { crash: false },
+            db.get_data(true).await.expect("could not load")
+        );
+        dir.close().expect("Error while deleting temp directory!");
+    }
+
     /*
     #[test]
     fn save_and_into_inner() {
@@ -0,0 +1,152 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Helpers for a [`Database`] whose data is a map, changing all of its
+//! entries at once.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backend::Backend;
+use crate::{error, Database, DeSerializer};
+
+/// Implement the helpers for the map type `$map`, whose keys are bound by
+/// `$key` and which has the extra generics `$extra`.
+macro_rules! map_helpers {
+    ($map:ident<K, V $(, $extra:ident)*>, K: $($key:tt)+) => {
+        impl<K, V, $($extra,)* Back, DeSer> Database<$map<K, V $(, $extra)*>, Back, DeSer>
+        where
+            K: $($key)+,
+            V: Clone + PartialEq,
+            $map<K, V $(, $extra)*>: Serialize + DeserializeOwned + Clone + Send,
+            Back: Backend + Send,
+            DeSer: DeSerializer<$map<K, V $(, $extra)*>> + Send + Sync + Clone,
+        {
+            /// Keep only the entries for which `keep` returns true, and save
+            /// the map once if any entry was removed.
+            ///
+            /// The map is write locked until it is saved, so the entries are
+            /// removed and saved as one change, instead of one save per
+            /// entry. Returns the number of entries removed.
+            ///
+            /// # Errors
+            ///
+            /// Returns the errors of [`Database::save`]. The entries stay
+            /// removed in memory even if the map could not be saved.
+            pub async fn retain<F>(&self, mut keep: F) -> error::Result<usize>
+            where
+                F: FnMut(&K, &mut V) -> bool,
+            {
+                let mut lock = self.data.write().await;
+                let before = lock.len();
+                lock.retain(|key, value| keep(key, value));
+                let removed = before - lock.len();
+                if removed == 0 {
+                    return Ok(0);
+                }
+                self.oplog.record_write();
+                self.save_data_locked(lock).await?;
+                Ok(removed)
+            }
+
+            /// Run `update` on every entry, and save the map once if any
+            /// value changed.
+            ///
+            /// Each value is compared with a copy taken before `update` ran
+            /// on it, so an update that leaves everything as it was doesn't
+            /// write to the backend. The map is write locked until it is
+            /// saved, like with [`Database::retain`]. Returns the number of
+            /// values changed.
+            ///
+            /// # Errors
+            ///
+            /// Returns the errors of [`Database::save`]. The values stay
+            /// changed in memory even if the map could not be saved.
+            pub async fn update_all<F>(&self, mut update: F) -> error::Result<usize>
+            where
+                F: FnMut(&K, &mut V),
+            {
+                let mut lock = self.data.write().await;
+                let mut changed = 0;
+                for (key, value) in lock.iter_mut() {
+                    let old = value.clone();
+                    update(key, value);
+                    if *value != old {
+                        changed += 1;
+                    }
+                }
+                if changed == 0 {
+                    return Ok(0);
+                }
+                self.oplog.record_write();
+                self.save_data_locked(lock).await?;
+                Ok(changed)
+            }
+        }
+    };
+}
+
+map_helpers!(HashMap<K, V, S>, K: Eq + Hash);
+map_helpers!(BTreeMap<K, V>, K: Ord);
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use crate::deser::Ron;
+    use crate::MemoryDatabase;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[tokio::test]
+    async fn retain_saves_once() {
+        let data: HashMap<u32, String> = (0..10).map(|i| (i, i.to_string())).collect();
+        let db =
+            MemoryDatabase::<HashMap<u32, String>, Ron>::memory(data).expect("could not create db");
+        let saves = db.stats().saves;
+
+        let removed = db
+            .retain(|key, _| key % 2 == 0)
+            .await
+            .expect("could not retain");
+        assert_eq!(5, removed);
+        assert_eq!(saves + 1, db.stats().saves);
+
+        db.put_data(HashMap::new(), false)
+            .await
+            .expect("could not put data");
+        db.load().await.expect("could not load");
+        let mut keys: Vec<u32> = db
+            .read(|map| map.keys().copied().collect())
+            .await
+            .expect("no data");
+        keys.sort_unstable();
+        assert_eq!(vec![0, 2, 4, 6, 8], keys);
+
+        assert_eq!(0, db.retain(|_, _| true).await.expect("could not retain"));
+        assert_eq!(saves + 1, db.stats().saves);
+    }
+
+    #[tokio::test]
+    async fn update_all_without_changes_does_not_save() {
+        let data: BTreeMap<String, u32> = [("a".to_owned(), 1), ("b".to_owned(), 20)].into();
+        let db = MemoryDatabase::<BTreeMap<String, u32>, Ron>::memory(data)
+            .expect("could not create db");
+        let saves = db.stats().saves;
+
+        let changed = db
+            .update_all(|_, value| *value = (*value).min(100))
+            .await
+            .expect("could not update");
+        assert_eq!(0, changed);
+        assert_eq!(saves, db.stats().saves);
+
+        let changed = db
+            .update_all(|_, value| *value = (*value).min(10))
+            .await
+            .expect("could not update");
+        assert_eq!(1, changed);
+        assert_eq!(saves + 1, db.stats().saves);
+    }
+}
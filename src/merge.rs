@@ -0,0 +1,329 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Merging of concurrent writes by several processes sharing a backend.
+
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::backend::{data_hash, Backend};
+use crate::error;
+use crate::{Database, DeSerializer};
+
+/// Resolves a conflict between the data of a [`Database`] and what another
+/// process saved to the same backend in the meantime.
+///
+/// See [`Database::with_merge_strategy`]. Closures taking
+/// `(base, ours, theirs)` implement this trait as well.
+pub trait MergeStrategy<T> {
+    /// Merge the conflicting versions of the data.
+    ///
+    /// `base` is the data as it was last read from or written to the backend
+    /// by this database, `ours` the data in memory, and `theirs` what is
+    /// stored in the backend now. The result is saved and becomes the data in
+    /// memory.
+    fn merge(&self, base: T, ours: T, theirs: T) -> T;
+}
+
+impl<T, F> MergeStrategy<T> for F
+where
+    F: Fn(T, T, T) -> T,
+{
+    fn merge(&self, base: T, ours: T, theirs: T) -> T {
+        self(base, ours, theirs)
+    }
+}
+
+/// A [`MergeStrategy`] keeping the data being saved, dropping whatever the
+/// other process wrote.
+///
+/// This is what happens without any merge strategy, it is mostly useful as
+/// a fallback in a custom strategy.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LastWriteWins;
+
+impl<T> MergeStrategy<T> for LastWriteWins {
+    fn merge(&self, _base: T, ours: T, _theirs: T) -> T {
+        ours
+    }
+}
+
+/// The merge strategy of a [`Database`], with what it needs to detect
+/// conflicts.
+pub(crate) struct Merge<T> {
+    strategy: Box<dyn MergeStrategy<T> + Send + Sync>,
+    /// The hash of the data last read from or written to the backend, and
+    /// the data itself.
+    base: Mutex<Option<(u64, T)>>,
+}
+
+impl<T> fmt::Debug for Merge<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Merge").finish_non_exhaustive()
+    }
+}
+
+impl<T> Merge<T> {
+    /// Forget the base, for when it doesn't describe the backend anymore.
+    pub(crate) fn forget_base(self) -> Self {
+        Self {
+            strategy: self.strategy,
+            base: Mutex::new(None),
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Merge<T> {
+    /// Load the data from `backend`, remembering it as the base.
+    ///
+    /// Returns the data and the number of bytes read.
+    /// With `canonical`, the base is hashed in its canonical JSON form.
+    pub(crate) async fn load<B, D>(
+        &self,
+        backend: &mut B,
+        deser: &D,
+        canonical: bool,
+    ) -> error::Result<(T, usize)>
+    where
+        B: Backend + Send,
+        D: DeSerializer<T>,
+    {
+        let bytes = backend.get_data_cow().await?;
+        let data = deser.deserialize(&bytes[..])?;
+        *self.base.lock().await = Some((data_hash(&bytes, canonical), data.clone()));
+        Ok((data, bytes.len()))
+    }
+
+    /// Save `ours` to `backend`, merging it first if the backend changed
+    /// since the base was recorded.
+    ///
+    /// `check` is called with the data about to be saved, and aborts the save
+    /// if it fails. Returns the number of bytes written. With `canonical`,
+    /// the data in the backend is compared by its canonical JSON form.
+    pub(crate) async fn save<B, D, C>(
+        &self,
+        ours: &mut T,
+        backend: &mut B,
+        deser: &D,
+        canonical: bool,
+        check: C,
+    ) -> error::Result<usize>
+    where
+        B: Backend + Send,
+        D: DeSerializer<T>,
+        C: FnOnce(&T) -> error::Result<()>,
+    {
+        let mut base = self.base.lock().await;
+        let mut merged = None;
+        if let Some((hash, base_data)) = &*base {
+            let current = backend.get_data_cow().await?;
+            if data_hash(&current, canonical) != *hash {
+                let theirs = deser.deserialize(&current[..])?;
+                drop(current);
+                merged = Some(self.strategy.merge(base_data.clone(), ours.clone(), theirs));
+            }
+        }
+        let data = merged.as_ref().unwrap_or(ours);
+        check(data)?;
+        let ser = deser.serialize(data)?;
+        let written = backend.put_data_counted(&ser).await?;
+        if let Some(merged) = merged {
+            *ours = merged;
+        }
+        *base = Some((data_hash(&ser, canonical), ours.clone()));
+        Ok(written)
+    }
+}
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Detect saves of other processes to the same backend, and merge with
+    /// them using `strategy`.
+    ///
+    /// Once set, every load remembers a hash of what was read and the data
+    /// itself as the base. A save first reads the backend again. If its hash
+    /// changed, another process wrote in the meantime, and the data saved is
+    /// `strategy.merge(base, ours, theirs)` instead of the data in memory,
+    /// which is replaced by the merged data as well.
+    ///
+    /// Conflicts are only detected after the first load or save following
+    /// this call. This narrows the window in which writes can be lost but is
+    /// no replacement for locking: a write happening between the check and
+    /// the save is still overwritten. It also costs an extra read per save.
+    #[must_use]
+    pub fn with_merge_strategy<S>(mut self, strategy: S) -> Self
+    where
+        S: MergeStrategy<Data> + Send + Sync + 'static,
+    {
+        self.merge = Some(Merge {
+            strategy: Box::new(strategy),
+            base: Mutex::new(None),
+        });
+        self
+    }
+
+    /// Compare the data in the backend by its
+    /// [canonical form](crate::deser::canonicalize), which has to be JSON,
+    /// when detecting saves of other processes.
+    ///
+    /// Without this a process rewriting the same data with a different
+    /// formatting, or with the keys of objects in another order, counts as a
+    /// conflict for [`Database::with_merge_strategy`]. Data which isn't JSON
+    /// is still compared byte by byte.
+    ///
+    /// **Important**: This is only available with the `json_enc` feature
+    #[cfg(feature = "json_enc")]
+    #[must_use]
+    pub fn with_canonical_json(mut self, enabled: bool) -> Self {
+        self.canonical_json = enabled;
+        self
+    }
+
+    /// Whether the data in the backend is compared by its canonical form.
+    #[cfg_attr(not(feature = "json_enc"), allow(clippy::unused_self))]
+    pub(crate) fn canonical_json(&self) -> bool {
+        #[cfg(feature = "json_enc")]
+        {
+            self.canonical_json
+        }
+        #[cfg(not(feature = "json_enc"))]
+        {
+            false
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use super::LastWriteWins;
+    use crate::deser::Ron;
+    use crate::PathDatabase;
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::NamedTempFile;
+
+    type Db = PathDatabase<BTreeSet<String>, Ron>;
+
+    async fn open(file: &NamedTempFile) -> Db {
+        Db::load_from_path(file.path().to_owned())
+            .await
+            .expect("could not load db")
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn custom_merge_combines_writers() {
+        let file = NamedTempFile::new().expect("could not create temporary file");
+        std::fs::write(file.path(), "[]").expect("could not initialise file");
+        let merges = Arc::new(AtomicUsize::new(0));
+        let union = {
+            let merges = Arc::clone(&merges);
+            move |base: BTreeSet<String>, ours: BTreeSet<String>, theirs: BTreeSet<String>| {
+                merges.fetch_add(1, Ordering::SeqCst);
+                // Keep everything either side added, drop what either removed.
+                ours.union(&theirs)
+                    .filter(|v| !base.contains(*v) || (ours.contains(*v) && theirs.contains(*v)))
+                    .cloned()
+                    .collect()
+            }
+        };
+        let first = open(&file).await.with_merge_strategy(union.clone());
+        let second = open(&file).await.with_merge_strategy(union);
+        first.load().await.expect("could not load");
+        second.load().await.expect("could not load");
+
+        first
+            .write(|d| d.insert("first".to_owned()))
+            .await
+            .expect("could not write");
+        first.save().await.expect("could not save");
+        assert_eq!(0, merges.load(Ordering::SeqCst));
+
+        second
+            .write(|d| d.insert("second".to_owned()))
+            .await
+            .expect("could not write");
+        second.save().await.expect("could not save");
+        assert_eq!(1, merges.load(Ordering::SeqCst));
+
+        let both: BTreeSet<String> = ["first", "second"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(both, second.get_data(false).await.expect("no data"));
+        assert_eq!(both, first.get_data(true).await.expect("could not load"));
+        assert_eq!(
+            both,
+            open(&file).await.get_data(false).await.expect("no data")
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn last_write_wins() {
+        let file = NamedTempFile::new().expect("could not create temporary file");
+        std::fs::write(file.path(), "[]").expect("could not initialise file");
+        let first = open(&file).await.with_merge_strategy(LastWriteWins);
+        let second = open(&file).await.with_merge_strategy(LastWriteWins);
+        first.load().await.expect("could not load");
+        second.load().await.expect("could not load");
+
+        first
+            .put_data(["first".to_owned()].iter().cloned().collect(), true)
+            .await
+            .expect("could not save");
+        second
+            .put_data(["second".to_owned()].iter().cloned().collect(), true)
+            .await
+            .expect("could not save");
+
+        let data = open(&file).await.get_data(false).await.expect("no data");
+        assert_eq!(vec!["second"], data.into_iter().collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "json_enc")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn canonical_json_ignores_reformatting() {
+        use crate::deser::Json;
+        use std::collections::BTreeMap;
+
+        type JsonDb = PathDatabase<BTreeMap<String, u32>, Json>;
+
+        for canonical in [false, true] {
+            let file = NamedTempFile::new().expect("could not create temporary file");
+            std::fs::write(file.path(), r#"{"a": 1, "b": 2}"#).expect("could not initialise file");
+            let merges = Arc::new(AtomicUsize::new(0));
+            let counting = {
+                let merges = Arc::clone(&merges);
+                move |_base, ours, _theirs| {
+                    merges.fetch_add(1, Ordering::SeqCst);
+                    ours
+                }
+            };
+            let db = JsonDb::load_from_path(file.path().to_owned())
+                .await
+                .expect("could not load db")
+                .with_merge_strategy(counting)
+                .with_canonical_json(canonical);
+            db.load().await.expect("could not load");
+
+            // Another process rewrites the same data, formatted differently.
+            std::fs::write(file.path(), r#"{"b":2,"a":1}"#).expect("could not rewrite file");
+            db.write(|d| d.insert("c".to_owned(), 3))
+                .await
+                .expect("could not write");
+            db.save().await.expect("could not save");
+            assert_eq!(usize::from(!canonical), merges.load(Ordering::SeqCst));
+        }
+    }
+}
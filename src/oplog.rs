@@ -0,0 +1,173 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A log of the last operations done through a [`Database`], for debugging.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backend::Backend;
+use crate::stats::to_u64;
+use crate::{Database, DeSerializer};
+
+/// What kind of [`Operation`] was done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    /// The data in memory was changed, by a closure or by replacing it.
+    Write,
+    /// The data was saved to the backend.
+    Save,
+    /// The data was loaded from the backend.
+    Load,
+}
+
+/// An entry of the log of [`Database::operation_log`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    /// What was done.
+    pub kind: OperationKind,
+    /// When it was done.
+    pub at: SystemTime,
+    /// The bytes a save wrote, as reported by the backend, or a load read.
+    pub bytes: Option<u64>,
+    /// Whether a save failed. Failed loads and writes are not logged.
+    pub failed: bool,
+}
+
+/// The last operations of a database, at most `capacity` of them.
+#[derive(Debug, Default)]
+pub(crate) struct OperationLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<Operation>>,
+}
+
+impl OperationLog {
+    fn push(&self, kind: OperationKind, bytes: Option<u64>, failed: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(Operation {
+            kind,
+            at: SystemTime::now(),
+            bytes,
+            failed,
+        });
+    }
+
+    pub(crate) fn record_write(&self) {
+        self.push(OperationKind::Write, None, false);
+    }
+
+    pub(crate) fn record_load(&self, bytes: usize) {
+        self.push(OperationKind::Load, Some(to_u64(bytes)), false);
+    }
+
+    /// Log the outcome of a save, given the bytes it wrote.
+    pub(crate) fn record_save<E>(&self, result: &Result<usize, E>) {
+        match result {
+            Ok(bytes) => self.push(OperationKind::Save, Some(to_u64(*bytes)), false),
+            Err(_) => self.push(OperationKind::Save, None, true),
+        }
+    }
+}
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Keep a log of the last `capacity` writes, saves and loads, see
+    /// [`Database::operation_log`].
+    ///
+    /// The log starts out empty and replaces any log kept before. It costs a
+    /// lock and a clock read per operation, so it is meant for debugging
+    /// rather than to be left on.
+    #[must_use]
+    pub fn with_operation_log(mut self, capacity: usize) -> Self {
+        self.oplog = OperationLog {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        };
+        self
+    }
+
+    /// The last operations done through this database, oldest first.
+    ///
+    /// Writes are logged by [`Database::write`] and its variants,
+    /// [`Database::put_data`] and [`Database::swap`], once the data changed.
+    /// Changes made through [`Database::borrow_data_mut`] are not logged.
+    /// Saves and loads are logged once they completed, like in
+    /// [`Database::stats`]. Empty unless the log was enabled with
+    /// [`Database::with_operation_log`].
+    #[must_use]
+    pub fn operation_log(&self) -> Vec<Operation> {
+        let entries = self
+            .oplog
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use super::OperationKind;
+    use crate::deser::{DeSerializer, Ron};
+    use crate::MemoryDatabase;
+
+    #[tokio::test]
+    async fn logs_last_operations_in_order() {
+        let db = MemoryDatabase::<Vec<u32>, Ron>::memory(vec![1])
+            .expect("could not create db")
+            .with_operation_log(4);
+        assert!(db.operation_log().is_empty());
+
+        db.write(|data| data.push(2))
+            .await
+            .expect("could not write");
+        db.save().await.expect("could not save");
+        db.load().await.expect("could not load");
+        db.put_data(vec![3], true).await.expect("could not save");
+        db.write_safe(|data| data.push(4))
+            .await
+            .expect("could not write");
+
+        let log = db.operation_log();
+        let kinds: Vec<OperationKind> = log.iter().map(|op| op.kind).collect();
+        assert_eq!(
+            vec![
+                OperationKind::Load,
+                OperationKind::Write,
+                OperationKind::Save,
+                OperationKind::Write
+            ],
+            kinds
+        );
+        let size = |data: Vec<u32>| Ron.serialize(&data).expect("could not serialize").len() as u64;
+        assert_eq!(Some(size(vec![1, 2])), log[0].bytes);
+        assert_eq!(Some(size(vec![3])), log[2].bytes);
+        assert!(log.iter().all(|op| !op.failed));
+        assert!(log.windows(2).all(|pair| pair[0].at <= pair[1].at));
+    }
+
+    #[tokio::test]
+    async fn disabled_by_default() {
+        let db = MemoryDatabase::<Vec<u32>, Ron>::memory(vec![1]).expect("could not create db");
+        db.write(|data| data.push(2))
+            .await
+            .expect("could not write");
+        db.save().await.expect("could not save");
+        assert!(db.operation_log().is_empty());
+    }
+}
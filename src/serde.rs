@@ -0,0 +1,129 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Serde helpers to control how values are laid out in the database.
+//!
+//! The modules in here are meant to be used with serde's `with` attribute,
+//! so that a field is encoded the same way regardless of the `DeSer` in use.
+//!
+//! ```rust
+//! # #[macro_use] extern crate serde_derive;
+//! use chrono::{DateTime, Utc};
+//!
+//! #[derive(Debug, Serialize, Deserialize, Clone)]
+//! struct Entry {
+//!     #[serde(with = "dropbreak::serde::rfc3339")]
+//!     created: DateTime<Utc>,
+//!     #[serde(with = "dropbreak::serde::unix_seconds")]
+//!     touched: DateTime<Utc>,
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! **Important**: This module is only available with the `chrono` feature
+
+/// Store a `DateTime<Utc>` as an RFC 3339 string, e.g.
+/// `"2020-01-02T03:04:05Z"`.
+///
+/// Sub-second precision is kept if present. Offsets other than UTC are
+/// accepted when reading and converted to UTC.
+pub mod rfc3339 {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Serialize the timestamp as an RFC 3339 string.
+    pub fn serialize<S: Serializer>(time: &DateTime<Utc>, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(&time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+    }
+
+    /// Deserialize a timestamp from an RFC 3339 string.
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<DateTime<Utc>, D::Error> {
+        let s = String::deserialize(de)?;
+        DateTime::parse_from_rfc3339(&s)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Store a `DateTime<Utc>` as the number of seconds since the unix epoch.
+///
+/// Sub-second precision is dropped when writing.
+pub mod unix_seconds {
+    use chrono::{DateTime, Utc};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Serialize the timestamp as seconds since the unix epoch.
+    pub fn serialize<S: Serializer>(time: &DateTime<Utc>, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_i64(time.timestamp())
+    }
+
+    /// Deserialize a timestamp from seconds since the unix epoch.
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<DateTime<Utc>, D::Error> {
+        let secs = i64::deserialize(de)?;
+        DateTime::from_timestamp(secs, 0)
+            .ok_or_else(|| D::Error::custom(format!("timestamp {secs} is out of range")))
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use crate::deser::{DeSerializer, Ron};
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        #[serde(with = "crate::serde::rfc3339")]
+        created: DateTime<Utc>,
+        #[serde(with = "crate::serde::unix_seconds")]
+        touched: DateTime<Utc>,
+    }
+
+    fn entry() -> Entry {
+        let time = Utc
+            .with_ymd_and_hms(2020, 1, 2, 3, 4, 5)
+            .single()
+            .expect("valid timestamp");
+        Entry {
+            created: time,
+            touched: time,
+        }
+    }
+
+    #[test]
+    fn rfc3339_and_unix_seconds_layout() {
+        let bytes = Ron.serialize(&entry()).expect("could not serialize");
+        let text = String::from_utf8(bytes.clone()).expect("ron output is not utf8");
+        assert!(
+            text.contains(r#"created: "2020-01-02T03:04:05Z""#),
+            "{}",
+            text
+        );
+        assert!(text.contains("touched: 1577934245"), "{}", text);
+
+        let back: Entry = Ron.deserialize(&bytes[..]).expect("could not deserialize");
+        assert_eq!(entry(), back);
+    }
+
+    #[test]
+    fn rfc3339_accepts_offsets() {
+        let text = r#"(created: "2020-01-02T05:04:05+02:00", touched: 1577934245)"#;
+        let back: Entry = Ron
+            .deserialize(text.as_bytes())
+            .expect("could not deserialize");
+        assert_eq!(entry(), back);
+    }
+
+    #[test]
+    fn unix_seconds_out_of_range() {
+        let text = format!(
+            r#"(created: "2020-01-02T03:04:05Z", touched: {})"#,
+            i64::MAX
+        );
+        let res: crate::DeSerResult<Entry> = Ron.deserialize(text.as_bytes());
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,171 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Cumulative counters of the work done by a [`Database`](crate::Database).
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of the counters of a database, see
+/// [`Database::stats`](crate::Database::stats).
+///
+/// The counters start at zero when the database is created and only count
+/// the loads and saves made through it, not the initial load of constructors
+/// such as `load_from_path`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// How many saves succeeded.
+    pub saves: u64,
+    /// How many loads succeeded.
+    pub loads: u64,
+    /// The bytes written by successful saves, as reported by
+    /// [`Backend::put_data_counted`](crate::backend::Backend::put_data_counted).
+    pub bytes_written: u64,
+    /// The bytes read from the backend by successful loads.
+    pub bytes_read: u64,
+    /// How many saves failed, in serialization or in the backend.
+    pub save_failures: u64,
+}
+
+/// The counters behind [`Stats`].
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    saves: AtomicU64,
+    loads: AtomicU64,
+    bytes_written: AtomicU64,
+    bytes_read: AtomicU64,
+    save_failures: AtomicU64,
+}
+
+/// `bytes` as a `u64`, saturating on the platforms where it doesn't fit.
+pub(crate) fn to_u64(bytes: usize) -> u64 {
+    u64::try_from(bytes).unwrap_or(u64::MAX)
+}
+
+impl Counters {
+    pub(crate) fn record_load(&self, bytes: usize) {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(to_u64(bytes), Ordering::Relaxed);
+    }
+
+    /// Count the outcome of a save, given the bytes it wrote.
+    pub(crate) fn record_save<E>(&self, result: &Result<usize, E>) {
+        match result {
+            Ok(bytes) => {
+                self.saves.fetch_add(1, Ordering::Relaxed);
+                self.bytes_written
+                    .fetch_add(to_u64(*bytes), Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.save_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            saves: self.saves.load(Ordering::Relaxed),
+            loads: self.loads.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            save_failures: self.save_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use super::Stats;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::deser::{DeSerializer, Ron};
+    use crate::error::{self, BackendError};
+    use crate::{Database, MemoryDatabase};
+
+    /// Fails every write.
+    #[derive(Debug, Default)]
+    struct ReadOnly(MemoryBackend);
+
+    impl Backend for ReadOnly {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            self.0.get_data().await
+        }
+
+        async fn put_data(&mut self, _data: &[u8]) -> error::BackendResult<()> {
+            Err(BackendError::Internal("read only".to_owned()))
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_saves_and_loads() {
+        let db =
+            MemoryDatabase::<Vec<u32>, Ron>::memory(vec![1, 2, 3]).expect("could not create db");
+        let size = Ron
+            .serialize(&vec![1_u32, 2, 3])
+            .expect("could not serialize")
+            .len() as u64;
+        assert_eq!(Stats::default(), db.stats());
+
+        db.save().await.expect("could not save");
+        db.save().await.expect("could not save");
+        db.load().await.expect("could not load");
+        db.put_data(vec![1, 2, 3], true)
+            .await
+            .expect("could not save");
+        db.get_data(true).await.expect("could not load");
+
+        assert_eq!(
+            Stats {
+                saves: 3,
+                loads: 2,
+                bytes_written: 3 * size,
+                bytes_read: 2 * size,
+                save_failures: 0,
+            },
+            db.stats()
+        );
+    }
+
+    /// Stores the data behind a 4 byte frame.
+    #[derive(Debug, Default)]
+    struct Framed(MemoryBackend);
+
+    impl Backend for Framed {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            Ok(self.0.get_data().await?.split_off(4))
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.put_data_counted(data).await.map(drop)
+        }
+
+        async fn put_data_counted(&mut self, data: &[u8]) -> error::BackendResult<usize> {
+            let framed = [&b"DATA"[..], data].concat();
+            self.0.put_data_counted(&framed).await
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_bytes_reported_by_backend() {
+        let db =
+            Database::<u32, _, Ron>::from_parts(7, Framed::default(), Ron).with_operation_log(4);
+        db.save().await.expect("could not save");
+        assert_eq!(
+            Ron.serialize(&7_u32).expect("could not serialize").len() as u64 + 4,
+            db.stats().bytes_written
+        );
+        assert_eq!(Some(Some(5)), db.operation_log().last().map(|op| op.bytes));
+    }
+
+    #[tokio::test]
+    async fn counts_save_failures() {
+        let db = Database::<u32, _, Ron>::from_parts(1, ReadOnly::default(), Ron);
+
+        db.save().await.expect_err("saved to a read only backend");
+        db.save().await.expect_err("saved to a read only backend");
+        let stats = db.stats();
+        assert_eq!(0, stats.saves);
+        assert_eq!(0, stats.bytes_written);
+        assert_eq!(2, stats.save_failures);
+    }
+}
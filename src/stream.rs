@@ -0,0 +1,319 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Reading the elements of a [`Database`] stored with bincode one at a time,
+//! see [`Database::load_stream`].
+
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+
+use bincode::deserialize_from;
+use futures_util::stream::{self, Stream};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, DuplexStream};
+
+use crate::backend::Backend;
+use crate::deser::Bincode;
+use crate::error;
+use crate::Database;
+
+/// How many bytes of the backend [`Database::load_stream`] reads ahead of
+/// the element it decodes.
+const READ_AHEAD: usize = 64 * 1024;
+
+/// Whether bincode failed because `bytes` ended too early.
+fn is_eof(e: &bincode::Error) -> bool {
+    matches!(&**e, bincode::ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+/// How far [`Database::load_stream`] got.
+struct StreamState<F> {
+    /// Writes the data of the backend to the other end of `reader`,
+    /// `None` once it has written all of it.
+    feed: Option<Pin<Box<F>>>,
+    reader: DuplexStream,
+    /// The bytes read from `reader`, decoded up to `start`.
+    buffer: Vec<u8>,
+    start: usize,
+    /// Whether `reader` has no bytes left.
+    eof: bool,
+    /// The number of elements left, once the length of the sequence was
+    /// read.
+    remaining: Option<u64>,
+}
+
+/// What [`StreamState::fill`] got first.
+enum Filled {
+    Fed(error::BackendResult<u64>),
+    Read(io::Result<usize>),
+}
+
+impl<F> StreamState<F>
+where
+    F: Future<Output = error::BackendResult<u64>>,
+{
+    /// Decode the next element, or `None` once all of them were.
+    async fn next<T: DeserializeOwned>(&mut self) -> Option<error::Result<T>> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => match self.decode().await {
+                Ok(len) => len,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        if remaining == 0 {
+            self.remaining = Some(0);
+            return self.finish().await.err().map(Err);
+        }
+        let element = self.decode().await;
+        self.remaining = Some(remaining - 1);
+        Some(element)
+    }
+
+    /// Decode a value at `start`, reading from the backend until there
+    /// are enough bytes for it.
+    async fn decode<V: DeserializeOwned>(&mut self) -> error::Result<V> {
+        loop {
+            let mut unread = &self.buffer[self.start..];
+            match deserialize_from(&mut unread) {
+                Ok(value) => {
+                    self.start = self.buffer.len() - unread.len();
+                    return Ok(value);
+                }
+                Err(e) if !self.eof && is_eof(&e) => self.fill().await?,
+                Err(e) => return Err(error::DeSerError::from(e).into()),
+            }
+        }
+    }
+
+    /// Read the bytes after the sequence, so that the read of the backend
+    /// completes and its errors are returned.
+    async fn finish(&mut self) -> error::Result<()> {
+        while !self.eof {
+            self.start = self.buffer.len();
+            self.fill().await?;
+        }
+        Ok(())
+    }
+
+    /// Read more bytes into the buffer, or find that there are none,
+    /// while the backend is read.
+    async fn fill(&mut self) -> error::Result<()> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        let mut chunk = [0; 8 * 1024];
+        loop {
+            let filled = {
+                let feed = &mut self.feed;
+                let feeding = async {
+                    match feed {
+                        Some(feed) => feed.as_mut().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    res = feeding => Filled::Fed(res),
+                    res = self.reader.read(&mut chunk) => Filled::Read(res),
+                }
+            };
+            match filled {
+                Filled::Fed(res) => {
+                    self.feed = None;
+                    res?;
+                }
+                Filled::Read(res) => {
+                    match res.map_err(error::BackendError::from)? {
+                        0 => self.eof = true,
+                        n => self.buffer.extend_from_slice(&chunk[..n]),
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<T, Back> Database<Vec<T>, Back, Bincode>
+where
+    T: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+{
+    /// Read the elements stored in the backend one at a time, without
+    /// loading them into the database.
+    ///
+    /// Bincode stores a `Vec` as its length followed by its elements, so
+    /// each element is deserialized only when the stream is polled for
+    /// it, and can be dropped before the next one is read. The bytes are
+    /// read with [`Backend::get_data_into`], up to 64 KiB ahead of the
+    /// element being deserialized: with a backend which streams its reads,
+    /// like a [`PathBackend`](crate::backend::PathBackend), neither the
+    /// sequence nor its serialized bytes are ever in memory as a whole.
+    ///
+    /// The backend is read from the first poll, and locked until all of
+    /// it was read or the stream is dropped. The data of the database is
+    /// left untouched. The stream ends after the first error. Once the
+    /// backend was read, the load is counted in [`Database::stats`] and the
+    /// operation log.
+    pub fn load_stream(&self) -> impl Stream<Item = error::Result<T>> + '_ {
+        let (mut writer, reader) = tokio::io::duplex(READ_AHEAD);
+        let feed = async move {
+            let mut backend = self.backend.lock().await;
+            let read = backend.get_data_into(&mut writer).await;
+            // Ends the data of `reader`.
+            drop(writer);
+            if let Ok(bytes) = &read {
+                let bytes = usize::try_from(*bytes).unwrap_or(usize::MAX);
+                self.stats.record_load(bytes);
+                self.oplog.record_load(bytes);
+            }
+            read
+        };
+        let state = StreamState {
+            feed: Some(Box::pin(feed)),
+            reader,
+            buffer: Vec::new(),
+            start: 0,
+            eof: false,
+            remaining: None,
+        };
+        stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+            match state.next().await? {
+                Ok(element) => Some((Ok(element), Some(state))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::deser::Bincode;
+    use crate::deser::DeSerializer;
+    use crate::error::{self, RustbreakError};
+    use crate::{Database, OperationKind};
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn load_stream_yields_every_element() {
+        let elements: Vec<(u32, String)> = (0..100_000).map(|i| (i, i.to_string())).collect();
+        let mut backend = MemoryBackend::new();
+        backend
+            .put_data(&Bincode.serialize(&elements).expect("could not serialize"))
+            .await
+            .expect("could not put data");
+        let db = Database::<Vec<(u32, String)>, _, Bincode>::from_parts(vec![], backend, Bincode);
+
+        let mut stream = Box::pin(db.load_stream());
+        let mut count = 0_u32;
+        while let Some(element) = stream.next().await {
+            assert_eq!(
+                (count, count.to_string()),
+                element.expect("could not read element")
+            );
+            count += 1;
+        }
+        assert_eq!(100_000, count);
+        drop(stream);
+        assert!(db.get_data(false).await.expect("no data").is_empty());
+    }
+
+    #[tokio::test]
+    async fn load_stream_ends_after_truncated_element() {
+        let mut bytes = Bincode
+            .serialize(&vec![1_u64, 2, 3])
+            .expect("could not serialize");
+        bytes.truncate(bytes.len() - 4);
+        let mut backend = MemoryBackend::new();
+        backend.put_data(&bytes).await.expect("could not put data");
+        let db = Database::<Vec<u64>, _, Bincode>::from_parts(vec![], backend, Bincode);
+
+        let read: Vec<_> = db.load_stream().collect().await;
+        assert_eq!(3, read.len());
+        assert_eq!(1, *read[0].as_ref().expect("could not read element"));
+        assert_eq!(2, *read[1].as_ref().expect("could not read element"));
+        assert!(matches!(read[2], Err(RustbreakError::DeSerialization(_))));
+    }
+
+    /// Writes its data in small chunks, counting the bytes written.
+    struct Trickle {
+        data: Vec<u8>,
+        written: Arc<AtomicUsize>,
+    }
+
+    impl Backend for Trickle {
+        async fn get_data(&mut self) -> error::BackendResult<Vec<u8>> {
+            Ok(self.data.clone())
+        }
+
+        async fn put_data(&mut self, data: &[u8]) -> error::BackendResult<()> {
+            self.data = data.to_vec();
+            Ok(())
+        }
+
+        async fn get_data_into(
+            &mut self,
+            writer: &mut (dyn AsyncWrite + Unpin + Send),
+        ) -> error::BackendResult<u64> {
+            for chunk in self.data.chunks(1024) {
+                writer.write_all(chunk).await?;
+                self.written.fetch_add(chunk.len(), Ordering::SeqCst);
+            }
+            Ok(self.data.len() as u64)
+        }
+    }
+
+    #[tokio::test]
+    async fn load_stream_reads_as_it_goes() {
+        let elements: Vec<(u32, String)> = (0..100_000).map(|i| (i, i.to_string())).collect();
+        let data = Bincode.serialize(&elements).expect("could not serialize");
+        let total = data.len();
+        let written = Arc::new(AtomicUsize::new(0));
+        let backend = Trickle {
+            data,
+            written: Arc::clone(&written),
+        };
+        let db = Database::<Vec<(u32, String)>, _, Bincode>::from_parts(vec![], backend, Bincode);
+
+        let mut stream = Box::pin(db.load_stream());
+        let first = stream.next().await.expect("no element");
+        assert_eq!((0, "0".to_owned()), first.expect("could not read element"));
+        assert!(written.load(Ordering::SeqCst) < total / 4);
+
+        let mut count = 1;
+        while let Some(element) = stream.next().await {
+            element.expect("could not read element");
+            count += 1;
+        }
+        assert_eq!(100_000, count);
+        assert_eq!(total, written.load(Ordering::SeqCst));
+        drop(stream);
+        assert_eq!(total as u64, db.stats().bytes_read);
+    }
+
+    #[tokio::test]
+    async fn load_stream_is_logged() {
+        let bytes = Bincode
+            .serialize(&vec![1_u64, 2, 3])
+            .expect("could not serialize");
+        let mut backend = MemoryBackend::new();
+        backend.put_data(&bytes).await.expect("could not put data");
+        let db = Database::<Vec<u64>, _, Bincode>::from_parts(vec![], backend, Bincode)
+            .with_operation_log(4);
+
+        let read: Vec<_> = db.load_stream().collect().await;
+        assert_eq!(3, read.len());
+        let log = db.operation_log();
+        assert_eq!(1, log.len());
+        assert_eq!(OperationKind::Load, log[0].kind);
+        assert_eq!(Some(bytes.len() as u64), log[0].bytes);
+    }
+}
@@ -0,0 +1,202 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Guards giving access to the data of a [`Database`] as if it was a plain
+//! collection.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+use crate::backend::Backend;
+use crate::{error, Database, DeSerializer};
+
+/// Read access to the data of a [`Database`], see [`Database::view`].
+///
+/// Derefs to the data. The data is read locked until the view is dropped.
+#[derive(Debug)]
+pub struct View<'a, Data> {
+    lock: RwLockReadGuard<'a, Data>,
+}
+
+impl<Data> Deref for View<'_, Data> {
+    type Target = Data;
+
+    fn deref(&self) -> &Data {
+        &self.lock
+    }
+}
+
+/// Write access to the data of a [`Database`], saved by
+/// [`ViewMut::commit`], see [`Database::view_mut`].
+///
+/// Derefs mutably to the data, which marks it as changed. The data is write
+/// locked until the view is committed or dropped.
+///
+/// Dropping the view doesn't save the data: saving is asynchronous and
+/// `Drop` can't wait for it, and starting it in the background would hide
+/// its errors and leave the data unsaved for an unknown time. Changes made
+/// through a view that is dropped without being committed stay in memory,
+/// like after [`Database::write`], and are saved by the next save.
+#[derive(Debug)]
+#[must_use = "changes are only saved by `ViewMut::commit`"]
+pub struct ViewMut<'a, Data, Back, DeSer> {
+    lock: RwLockWriteGuard<'a, Data>,
+    db: &'a Database<Data, Back, DeSer>,
+    dirty: bool,
+}
+
+impl<Data, Back, DeSer> ViewMut<'_, Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Whether the data was borrowed mutably through this view.
+    #[must_use]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Save the data if it was changed through this view, and release the
+    /// lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Database::save`]. The changes stay in memory
+    /// even if the data could not be saved.
+    pub async fn commit(self) -> error::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.db.save_data_locked(self.lock).await
+    }
+}
+
+impl<Data, Back, DeSer> Deref for ViewMut<'_, Data, Back, DeSer> {
+    type Target = Data;
+
+    fn deref(&self) -> &Data {
+        &self.lock
+    }
+}
+
+impl<Data, Back, DeSer> DerefMut for ViewMut<'_, Data, Back, DeSer> {
+    fn deref_mut(&mut self) -> &mut Data {
+        if !self.dirty {
+            self.dirty = true;
+            self.db.oplog.record_write();
+        }
+        &mut self.lock
+    }
+}
+
+/// A [`View`] of a database holding a `HashMap`.
+pub type MapView<'a, K, V, S = RandomState> = View<'a, HashMap<K, V, S>>;
+/// A [`ViewMut`] of a database holding a `HashMap`.
+pub type MapViewMut<'a, K, V, Back, DeSer, S = RandomState> =
+    ViewMut<'a, HashMap<K, V, S>, Back, DeSer>;
+/// A [`View`] of a database holding a `Vec`.
+pub type VecView<'a, T> = View<'a, Vec<T>>;
+/// A [`ViewMut`] of a database holding a `Vec`.
+pub type VecViewMut<'a, T, Back, DeSer> = ViewMut<'a, Vec<T>, Back, DeSer>;
+
+impl<Data, Back, DeSer> Database<Data, Back, DeSer>
+where
+    Data: Serialize + DeserializeOwned + Clone + Send,
+    Back: Backend + Send,
+    DeSer: DeSerializer<Data> + Send + Sync + Clone,
+{
+    /// Read lock the data and return a guard dereferencing to it.
+    ///
+    /// With a `HashMap` or a `Vec` as data, this is a [`MapView`] or a
+    /// [`VecView`], which reads like the collection itself.
+    pub async fn view(&self) -> View<'_, Data> {
+        View {
+            lock: self.data.read().await,
+        }
+    }
+
+    /// Write lock the data and return a guard dereferencing mutably to it,
+    /// whose changes are saved by [`ViewMut::commit`].
+    ///
+    /// With a `HashMap` or a `Vec` as data, this is a [`MapViewMut`] or a
+    /// [`VecViewMut`], which can be changed like the collection itself. The
+    /// data is only saved if it was borrowed mutably. See [`ViewMut`] for why
+    /// dropping the guard doesn't save it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate dropbreak;
+    /// use dropbreak::{deser::Ron, MemoryDatabase};
+    /// use std::collections::HashMap;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = MemoryDatabase::<HashMap<u32, String>, Ron>::memory(HashMap::new())?;
+    ///
+    /// let mut users = db.view_mut().await;
+    /// users.insert(1, "Bob".to_owned());
+    /// users.commit().await?;
+    ///
+    /// assert_eq!(Some("Bob"), db.view().await.get(&1).map(String::as_str));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn view_mut(&self) -> ViewMut<'_, Data, Back, DeSer> {
+        ViewMut {
+            lock: self.data.write().await,
+            db: self,
+            dirty: false,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ron_enc"))]
+mod tests {
+    use super::{MapView, VecViewMut};
+    use crate::backend::MemoryBackend;
+    use crate::deser::Ron;
+    use crate::MemoryDatabase;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn view_reads_like_the_collection() {
+        let data: HashMap<u32, String> = [(1, "one".to_owned())].into();
+        let db =
+            MemoryDatabase::<HashMap<u32, String>, Ron>::memory(data).expect("could not create db");
+        let view: MapView<'_, u32, String> = db.view().await;
+        assert_eq!(1, view.len());
+        assert_eq!(Some(&"one".to_owned()), view.get(&1));
+    }
+
+    #[tokio::test]
+    async fn view_mut_saves_on_commit() {
+        let db = MemoryDatabase::<Vec<u32>, Ron>::memory(vec![1]).expect("could not create db");
+        let saves = db.stats().saves;
+
+        let view: VecViewMut<'_, u32, MemoryBackend, Ron> = db.view_mut().await;
+        assert_eq!(&[1], view.as_slice());
+        assert!(!view.is_dirty());
+        view.commit().await.expect("could not commit");
+        assert_eq!(saves, db.stats().saves);
+
+        let mut view = db.view_mut().await;
+        view.push(2);
+        assert!(view.is_dirty());
+        view.commit().await.expect("could not commit");
+        assert_eq!(saves + 1, db.stats().saves);
+
+        // Dropped without a commit, the change stays in memory only.
+        db.view_mut().await.push(3);
+        assert_eq!(vec![1, 2, 3], *db.view().await);
+        db.load().await.expect("could not load");
+        assert_eq!(vec![1, 2], *db.view().await);
+    }
+}